@@ -0,0 +1,28 @@
+#!/bin/sh
+# Signs a fixture interaction with the test key and sends it to a local lodestone_news instance.
+#
+# Start the bot with LN_DISCORD_PUBLIC_KEY set to the contents of public_key, then run
+#   ./send.sh ping [http://127.0.0.1:8080]
+#
+# Needs OpenSSL 3 for Ed25519 signing. The .sig files are signed for the timestamp
+# 1570000000, which is too old to be accepted, and are only used by the tests.
+
+set -e
+
+dir=$(dirname "$0")
+name=${1:?usage: send.sh <fixture> [base url]}
+base=${2:-http://127.0.0.1:8080}
+
+timestamp=$(date +%s)
+message=$(mktemp)
+trap 'rm -f "$message"' EXIT
+printf '%s' "$timestamp" > "$message"
+cat "$dir/$name.json" >> "$message"
+signature=$(openssl pkeyutl -sign -rawin -inkey "$dir/private_key.pem" -in "$message" | od -An -v -tx1 | tr -d ' \n')
+
+curl -sS -X POST "$base/interactions" \
+  -H "Content-Type: application/json" \
+  -H "X-Signature-Ed25519: $signature" \
+  -H "X-Signature-Timestamp: $timestamp" \
+  --data-binary "@$dir/$name.json"
+echo
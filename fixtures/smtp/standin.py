@@ -0,0 +1,55 @@
+#!/usr/bin/env python3
+# A minimal SMTP server that accepts every message and writes it to stdout.
+#
+# usage: standin.py [port]
+
+import socketserver
+import sys
+
+
+class Handler(socketserver.StreamRequestHandler):
+    def reply(self, line):
+        self.wfile.write((line + "\r\n").encode())
+
+    def handle(self):
+        self.reply("220 localhost lodestone_news stand-in")
+        envelope = []
+        while True:
+            line = self.rfile.readline()
+            if not line:
+                return
+            command = line.decode().strip()
+            verb = command.split(" ", 1)[0].upper()
+            if verb in ("EHLO", "HELO"):
+                self.reply("250 localhost")
+            elif verb in ("MAIL", "RCPT"):
+                envelope.append(command)
+                self.reply("250 OK")
+            elif verb == "DATA":
+                self.reply("354 end data with <CR><LF>.<CR><LF>")
+                data = []
+                while True:
+                    line = self.rfile.readline()
+                    if line in (b".\r\n", b".\n", b""):
+                        break
+                    data.append(line.decode())
+                print("\n".join(envelope))
+                print("".join(data), flush=True)
+                envelope = []
+                self.reply("250 OK queued")
+            elif verb == "RSET":
+                envelope = []
+                self.reply("250 OK")
+            elif verb == "QUIT":
+                self.reply("221 bye")
+                return
+            else:
+                self.reply("502 not implemented")
+
+
+if __name__ == "__main__":
+    port = int(sys.argv[1]) if len(sys.argv) > 1 else 2525
+    socketserver.ThreadingTCPServer.allow_reuse_address = True
+    with socketserver.ThreadingTCPServer(("127.0.0.1", port), Handler) as server:
+        print("listening on 127.0.0.1:{}".format(port), flush=True)
+        server.serve_forever()
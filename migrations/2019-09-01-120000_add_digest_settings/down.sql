@@ -0,0 +1,13 @@
+alter table servers rename to old_servers;
+
+create table servers (
+  id integer primary key not null,
+  title text not null,
+  url text not null,
+  created timestamp not null default current_timestamp
+);
+
+insert into servers (id, title, url, created)
+  select id, title, url, created from old_servers;
+
+drop table old_servers;
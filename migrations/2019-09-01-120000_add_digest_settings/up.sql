@@ -0,0 +1,2 @@
+alter table servers add column digest_schedule text;
+alter table servers add column digest_style text not null default 'embeds';
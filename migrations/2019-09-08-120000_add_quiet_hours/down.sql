@@ -0,0 +1,15 @@
+alter table servers rename to old_servers;
+
+create table servers (
+  id integer primary key not null,
+  title text not null,
+  url text not null,
+  created timestamp not null default current_timestamp,
+  digest_schedule text,
+  digest_style text not null default 'embeds'
+);
+
+insert into servers (id, title, url, created, digest_schedule, digest_style)
+  select id, title, url, created, digest_schedule, digest_style from old_servers;
+
+drop table old_servers;
@@ -0,0 +1,4 @@
+alter table servers add column timezone text;
+alter table servers add column quiet_start text;
+alter table servers add column quiet_end text;
+alter table servers add column quiet_bypass boolean not null default 1;
@@ -0,0 +1,19 @@
+alter table servers rename to old_servers;
+
+create table servers (
+  id integer primary key not null,
+  title text not null,
+  url text not null,
+  created timestamp not null default current_timestamp,
+  digest_schedule text,
+  digest_style text not null default 'embeds',
+  timezone text,
+  quiet_start text,
+  quiet_end text,
+  quiet_bypass boolean not null default 1
+);
+
+insert into servers (id, title, url, created, digest_schedule, digest_style, timezone, quiet_start, quiet_end, quiet_bypass)
+  select id, title, url, created, digest_schedule, digest_style, timezone, quiet_start, quiet_end, quiet_bypass from old_servers;
+
+drop table old_servers;
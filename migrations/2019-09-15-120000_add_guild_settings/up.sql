@@ -0,0 +1,4 @@
+alter table servers add column guild_id text;
+alter table servers add column channel_id text;
+alter table servers add column filter_kinds text;
+alter table servers add column filter_tags text;
@@ -0,0 +1,45 @@
+drop table scheduled_events;
+
+alter table servers rename to old_servers;
+
+create table servers (
+  id integer primary key not null,
+  title text not null,
+  url text not null,
+  created timestamp not null default current_timestamp,
+  digest_schedule text,
+  digest_style text not null default 'embeds',
+  timezone text,
+  quiet_start text,
+  quiet_end text,
+  quiet_bypass boolean not null default 1,
+  guild_id text,
+  channel_id text,
+  filter_kinds text,
+  filter_tags text
+);
+
+insert into servers (id, title, url, created, digest_schedule, digest_style, timezone, quiet_start, quiet_end, quiet_bypass, guild_id, channel_id, filter_kinds, filter_tags)
+  select id, title, url, created, digest_schedule, digest_style, timezone, quiet_start, quiet_end, quiet_bypass, guild_id, channel_id, filter_kinds, filter_tags from old_servers;
+
+drop table old_servers;
+
+alter table news_items rename to old_news_items;
+
+create table news_items (
+  id integer primary key not null,
+  title text not null,
+  url text not null,
+  description text,
+  fields text,
+  image text,
+  lodestone_id text not null,
+  kind smallint not null,
+  created timestamp not null,
+  tag text
+);
+
+insert into news_items (id, title, url, description, fields, image, lodestone_id, kind, created, tag)
+  select id, title, url, description, fields, image, lodestone_id, kind, created, tag from old_news_items;
+
+drop table old_news_items;
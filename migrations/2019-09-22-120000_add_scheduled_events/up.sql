@@ -0,0 +1,18 @@
+alter table news_items add column maintenance_start timestamp;
+alter table news_items add column maintenance_end timestamp;
+
+alter table servers add column bot_token text;
+alter table servers add column scheduled_events boolean not null default 0;
+
+create table scheduled_events (
+  server_id integer not null,
+  news_id integer not null,
+  event_id text not null,
+  start_time timestamp not null,
+  end_time timestamp not null,
+
+  primary key(server_id, news_id),
+
+  foreign key(server_id) references servers(id),
+  foreign key(news_id) references news_items(id)
+);
@@ -0,0 +1,27 @@
+drop table reminders;
+
+alter table servers rename to old_servers;
+
+create table servers (
+  id integer primary key not null,
+  title text not null,
+  url text not null,
+  created timestamp not null default current_timestamp,
+  digest_schedule text,
+  digest_style text not null default 'embeds',
+  timezone text,
+  quiet_start text,
+  quiet_end text,
+  quiet_bypass boolean not null default 1,
+  guild_id text,
+  channel_id text,
+  filter_kinds text,
+  filter_tags text,
+  bot_token text,
+  scheduled_events boolean not null default 0
+);
+
+insert into servers (id, title, url, created, digest_schedule, digest_style, timezone, quiet_start, quiet_end, quiet_bypass, guild_id, channel_id, filter_kinds, filter_tags, bot_token, scheduled_events)
+  select id, title, url, created, digest_schedule, digest_style, timezone, quiet_start, quiet_end, quiet_bypass, guild_id, channel_id, filter_kinds, filter_tags, bot_token, scheduled_events from old_servers;
+
+drop table old_servers;
@@ -0,0 +1,16 @@
+alter table servers add column reminders boolean not null default 0;
+alter table servers add column reminder_lead integer not null default 60;
+
+create table reminders (
+  id integer primary key not null,
+  server_id integer not null,
+  news_id integer not null,
+  kind text not null,
+  due timestamp not null,
+  sent boolean not null default 0,
+
+  unique(server_id, news_id, kind),
+
+  foreign key(server_id) references servers(id),
+  foreign key(news_id) references news_items(id)
+);
@@ -0,0 +1,2 @@
+alter table servers add column sink text not null default 'discord';
+alter table servers add column config text;
@@ -0,0 +1,16 @@
+alter table send_records rename to old_send_records;
+
+create table send_records (
+  server_id integer not null,
+  news_id integer not null,
+
+  primary key(server_id, news_id),
+
+  foreign key(server_id) references servers(id),
+  foreign key(news_id) references news_items(id)
+);
+
+insert into send_records (server_id, news_id)
+  select server_id, news_id from old_send_records;
+
+drop table old_send_records;
@@ -0,0 +1 @@
+alter table send_records add column remote_id text;
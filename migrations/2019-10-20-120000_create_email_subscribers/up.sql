@@ -0,0 +1,9 @@
+create table email_subscribers (
+  id integer primary key not null,
+  server_id integer not null,
+  address text not null,
+
+  unique(server_id, address),
+
+  foreign key(server_id) references servers(id)
+);
@@ -0,0 +1,31 @@
+drop table admin_tokens;
+
+alter table servers rename to old_servers;
+
+create table servers (
+  id integer primary key not null,
+  title text not null,
+  url text not null,
+  created timestamp not null default current_timestamp,
+  digest_schedule text,
+  digest_style text not null default 'embeds',
+  timezone text,
+  quiet_start text,
+  quiet_end text,
+  quiet_bypass boolean not null default 1,
+  guild_id text,
+  channel_id text,
+  filter_kinds text,
+  filter_tags text,
+  bot_token text,
+  scheduled_events boolean not null default 0,
+  reminders boolean not null default 0,
+  reminder_lead integer not null default 60,
+  sink text not null default 'discord',
+  config text
+);
+
+insert into servers (id, title, url, created, digest_schedule, digest_style, timezone, quiet_start, quiet_end, quiet_bypass, guild_id, channel_id, filter_kinds, filter_tags, bot_token, scheduled_events, reminders, reminder_lead, sink, config)
+  select id, title, url, created, digest_schedule, digest_style, timezone, quiet_start, quiet_end, quiet_bypass, guild_id, channel_id, filter_kinds, filter_tags, bot_token, scheduled_events, reminders, reminder_lead, sink, config from old_servers;
+
+drop table old_servers;
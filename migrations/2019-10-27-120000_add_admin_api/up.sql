@@ -0,0 +1,8 @@
+alter table servers add column enabled boolean not null default 1;
+
+create table admin_tokens (
+  id integer primary key not null,
+  name text not null,
+  token_hash text not null unique,
+  created timestamp not null default current_timestamp
+);
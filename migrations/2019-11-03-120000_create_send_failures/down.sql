@@ -0,0 +1 @@
+drop table send_failures;
@@ -0,0 +1,12 @@
+create table send_failures (
+  server_id integer not null,
+  news_id integer not null,
+  status integer,
+  error text not null,
+  attempted timestamp not null,
+
+  primary key(server_id, news_id),
+
+  foreign key(server_id) references servers(id),
+  foreign key(news_id) references news_items(id)
+);
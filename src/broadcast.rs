@@ -0,0 +1,19 @@
+use crossbeam_channel as chan;
+
+use std::sync::Mutex;
+
+lazy_static! {
+  static ref SUBSCRIBERS: Mutex<Vec<chan::Sender<()>>> = Mutex::new(Vec::new());
+}
+
+/// Returns a receiver that is woken every time new items are inserted.
+pub fn subscribe() -> chan::Receiver<()> {
+  let (tx, rx) = chan::unbounded();
+  SUBSCRIBERS.lock().unwrap().push(tx);
+  rx
+}
+
+/// Wakes every subscriber, forgetting those that have gone away.
+pub fn publish() {
+  SUBSCRIBERS.lock().unwrap().retain(|tx| tx.send(()).is_ok());
+}
@@ -0,0 +1,606 @@
+use chrono::Utc;
+
+use diesel::{
+  prelude::*,
+  delete,
+  insert_or_ignore_into,
+  insert_into,
+  update,
+};
+
+use crate::{
+  database::{
+    migrations,
+    models::{
+      admin_token::{AdminToken, NewAdminToken},
+      email_subscriber::{EmailSubscriber, NewEmailSubscriber},
+      news_item::NewsItem,
+      send_failure::SendFailure,
+      send_record::{NewSendRecord, SendRecord},
+      server::{NewServer, Server},
+    },
+  },
+  errors::*,
+  feed::FeedFilter,
+  export::ExportedItem,
+  health,
+  legacy::Import,
+  sender::NewsSender,
+  sink::Outcome,
+  validation,
+  web::{
+    admin::{self, ServerChanges},
+    news::parse_since,
+  },
+};
+
+use std::collections::BTreeMap;
+
+const USAGE: &str = "\
+usage: lodestone_news [command]
+
+commands:
+  run [--once] [--dry-run]         run the bot (the default); --once scrapes and sends once and
+                                   exits, --dry-run does the same but prints what would be sent
+                                   as JSON instead of sending it
+  migrate [--status]               apply pending database migrations, or list them with --status
+  server list                      list destinations
+  server add <title> <url> [--<column> <value>]...
+                                   add a destination, e.g. --sink slack --filter_kinds news
+  server remove <server>           delete a destination and its history
+  server enable <server>           resume sending to a destination
+  server disable <server>          stop sending to a destination
+  news list [--kind <kinds>] [--tag <tags>] [--region <region>] [--limit <n>]
+                                   list items, newest first
+  news show <item>                 show an item and where it has been sent
+  send-test <server>               send the newest item a destination accepts, without recording it
+  resend <item> <server>           send an item to a destination again
+  mark-sent <server> --before <date>
+                                   record everything published before a date as sent
+  import-legacy <file> [--dry-run] import items from an old lodestone_news.json as already sent;
+                                   --dry-run only reports what would be imported
+  token list                       list admin tokens
+  token add <name>                 create an admin token and print it
+  token revoke <token>             delete an admin token
+  subscriber list <server>         list the addresses an email destination sends to
+  subscriber add <server> <address>
+                                   subscribe an address to an email destination
+  subscriber remove <server> <address>
+                                   unsubscribe an address from an email destination
+
+<server> is a destination id. <item> is a Lodestone id or an item id. <token> is a token id.";
+
+// columns of `servers` that aren't text when given as options
+const BOOL_COLUMNS: &[&str] = &["quiet_bypass", "scheduled_events", "reminders", "enabled"];
+const INT_COLUMNS: &[&str] = &["reminder_lead"];
+const JSON_COLUMNS: &[&str] = &["config"];
+
+// commands that need the database to be migrated, which they check instead of migrating it
+const DATABASE_COMMANDS: &[&str] = &[
+  "server", "news", "send-test", "resend", "mark-sent", "import-legacy", "token", "subscriber",
+];
+
+/// Positional arguments, `--name value` options and `--name` flags.
+struct Args {
+  positional: Vec<String>,
+  options: BTreeMap<String, String>,
+  flags: Vec<String>,
+}
+
+impl Args {
+  fn parse(args: &[String]) -> Result<Args> {
+    Args::with_flags(args, &[])
+  }
+
+  fn with_flags(args: &[String], flags: &[&str]) -> Result<Args> {
+    let mut parsed = Args {
+      positional: Vec::new(),
+      options: BTreeMap::new(),
+      flags: Vec::new(),
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+      if !arg.starts_with("--") {
+        parsed.positional.push(arg.clone());
+        continue;
+      }
+      if flags.contains(&&arg[2..]) {
+        parsed.flags.push(arg[2..].to_string());
+        continue;
+      }
+      let (name, value) = match arg.find('=') {
+        Some(i) => (&arg[2..i], arg[i + 1..].to_string()),
+        None => (&arg[2..], args.next().ok_or_else(|| format!("missing value for {}", arg))?.clone()),
+      };
+      parsed.options.insert(name.replace('-', "_"), value);
+    }
+    Ok(parsed)
+  }
+
+  /// Checks the number of positional arguments.
+  fn expect_positional(&self, positional: usize) -> Result<()> {
+    if self.positional.len() != positional {
+      return Err(format!("expected {} argument{}\n\n{}", positional, if positional == 1 { "" } else { "s" }, USAGE).into());
+    }
+    Ok(())
+  }
+
+  /// Checks the number of positional arguments and that only known options were given.
+  fn expect(&self, positional: usize, options: &[&str]) -> Result<()> {
+    self.expect_positional(positional)?;
+    if let Some(name) = self.options.keys().find(|k| !options.contains(&k.as_str())) {
+      return Err(format!("unknown option --{}", name).into());
+    }
+    Ok(())
+  }
+}
+
+pub fn run(args: &[String]) -> Result<()> {
+  let command = args.first().map(String::as_str).unwrap_or("run");
+  let sub = args.get(1).map(String::as_str).unwrap_or_default();
+  if DATABASE_COMMANDS.contains(&command) {
+    check_database()?;
+  }
+  match (command, sub) {
+    ("run", _) => {
+      let args = Args::with_flags(&args[1.min(args.len())..], &["once", "dry-run"])?;
+      args.expect(0, &[])?;
+      let dry_run = args.flags.iter().any(|f| f == "dry-run");
+      // a dry run leaves the database alone, so it can't migrate it either
+      if dry_run {
+        check_database()?;
+        return crate::run_once(true);
+      }
+      migrations::run()?;
+      health::check_schema()?;
+      if args.flags.iter().any(|f| f == "once") {
+        return crate::run_once(false);
+      }
+      crate::run();
+      Ok(())
+    },
+    ("migrate", _) => migrate(&Args::with_flags(&args[1..], &["status"])?),
+    ("server", "list") => server_list(&Args::parse(&args[2..])?),
+    ("server", "add") => server_add(&Args::parse(&args[2..])?),
+    ("server", "remove") => server_remove(&Args::parse(&args[2..])?),
+    ("server", "enable") => server_enable(&Args::parse(&args[2..])?, true),
+    ("server", "disable") => server_enable(&Args::parse(&args[2..])?, false),
+    ("news", "list") => news_list(&Args::parse(&args[2..])?),
+    ("news", "show") => news_show(&Args::parse(&args[2..])?),
+    ("send-test", _) => send_test(&Args::parse(&args[1..])?),
+    ("resend", _) => resend(&Args::parse(&args[1..])?),
+    ("mark-sent", _) => mark_sent(&Args::parse(&args[1..])?),
+    ("import-legacy", _) => import_legacy(&Args::with_flags(&args[1..], &["dry-run"])?),
+    ("token", "list") => token_list(&Args::parse(&args[2..])?),
+    ("token", "add") => token_add(&Args::parse(&args[2..])?),
+    ("token", "revoke") => token_revoke(&Args::parse(&args[2..])?),
+    ("subscriber", "list") => subscriber_list(&Args::parse(&args[2..])?),
+    ("subscriber", "add") => subscriber_add(&Args::parse(&args[2..])?),
+    ("subscriber", "remove") => subscriber_remove(&Args::parse(&args[2..])?),
+    ("help", _) | ("--help", _) | ("-h", _) => {
+      println!("{}", USAGE);
+      Ok(())
+    },
+    _ => Err(format!("unknown command: {}\n\n{}", args.join(" "), USAGE).into()),
+  }
+}
+
+fn check_database() -> Result<()> {
+  migrations::status()?.check_current()?;
+  health::check_schema()
+}
+
+fn migrate(args: &Args) -> Result<()> {
+  args.expect(0, &[])?;
+  if args.flags.iter().any(|f| f == "status") {
+    let status = migrations::status()?;
+    for name in &status.pending {
+      println!("pending  {}", name);
+    }
+    for version in &status.unknown {
+      println!("unknown  {}", version);
+    }
+    if status.pending.is_empty() && status.unknown.is_empty() {
+      println!("up to date");
+    }
+    return status.check_known();
+  }
+
+  let applied = migrations::run()?;
+  for name in &applied {
+    println!("applied  {}", name);
+  }
+  if applied.is_empty() {
+    println!("up to date");
+  }
+  health::check_schema()
+}
+
+fn find_server(id: &str) -> Result<Server> {
+  let id: i32 = id.parse().chain_err(|| format!("invalid server id: {}", id))?;
+  let server: Option<Server> = crate::CONNECTION.with(|c| {
+    use crate::database::schema::servers;
+    servers::table.find(id).first(c).optional().chain_err(|| "could not load server")
+  })?;
+  server.ok_or_else(|| format!("no server with id {}", id).into())
+}
+
+fn find_item(id: &str) -> Result<NewsItem> {
+  let item: Option<NewsItem> = crate::CONNECTION.with(|c| {
+    use crate::database::schema::news_items;
+    let item = news_items::table
+      .filter(news_items::lodestone_id.eq(id))
+      .first(c)
+      .optional()?;
+    match (item, id.parse::<i32>()) {
+      (None, Ok(id)) => news_items::table.find(id).first(c).optional(),
+      (item, _) => Ok(item),
+    }
+  })
+  .chain_err(|| "could not load news item")?;
+  item.ok_or_else(|| format!("no item with id {}", id).into())
+}
+
+fn print_outcome(outcome: &Outcome) -> Result<()> {
+  match *outcome {
+    Outcome::Sent(Some(ref remote_id)) => println!("sent (remote id {})", remote_id),
+    Outcome::Sent(None) => println!("sent"),
+    Outcome::Rejected { status, ref body } => return Err(format!("rejected with status {}: {}", status, body).into()),
+    Outcome::Failed(ref e) => return Err(format!("could not send: {}", e).into()),
+  }
+  Ok(())
+}
+
+fn server_list(args: &Args) -> Result<()> {
+  args.expect(0, &[])?;
+  let servers: Vec<Server> = crate::CONNECTION.with(|c| {
+    use crate::database::schema::servers;
+    servers::table.order(servers::id).load(c).chain_err(|| "could not load servers")
+  })?;
+  for server in servers {
+    println!(
+      "{:>4}  {:<8}  {:<11}  {}  {}",
+      server.id,
+      if server.enabled { "enabled" } else { "disabled" },
+      server.sink,
+      server.title,
+      server.url,
+    );
+  }
+  Ok(())
+}
+
+fn server_add(args: &Args) -> Result<()> {
+  // the options are columns, which `ServerChanges` checks
+  args.expect_positional(2)?;
+  let mut changes = serde_json::Map::new();
+  for (name, value) in &args.options {
+    let value = if BOOL_COLUMNS.contains(&name.as_str()) {
+      value.parse::<bool>().chain_err(|| format!("--{} must be true or false", name))?.into()
+    } else if INT_COLUMNS.contains(&name.as_str()) {
+      value.parse::<i32>().chain_err(|| format!("--{} must be a number", name))?.into()
+    } else if JSON_COLUMNS.contains(&name.as_str()) {
+      serde_json::from_str(value).chain_err(|| format!("--{} must be JSON", name))?
+    } else {
+      value.clone().into()
+    };
+    changes.insert(name.clone(), value);
+  }
+  let changes: ServerChanges = serde_json::from_value(changes.into()).map_err(|e| e.to_string())?;
+
+  let mut server = NewServer::new(args.positional[0].clone(), args.positional[1].clone());
+  changes.apply(&mut server)?;
+  validation::server(&server)?;
+  let server: Server = crate::CONNECTION.with(|c| {
+    use crate::database::schema::servers;
+    c.transaction(|| {
+      insert_into(servers::table).values(&server).execute(c)?;
+      servers::table.order(servers::id.desc()).first(c)
+    })
+    .chain_err(|| "could not insert server")
+  })?;
+  println!("added server {}", server.id);
+  Ok(())
+}
+
+fn server_remove(args: &Args) -> Result<()> {
+  args.expect(1, &[])?;
+  let server = find_server(&args.positional[0])?;
+  server.delete()?;
+  println!("removed server {} ({})", server.id, server.title);
+  Ok(())
+}
+
+fn server_enable(args: &Args, enabled: bool) -> Result<()> {
+  args.expect(1, &[])?;
+  let server = find_server(&args.positional[0])?;
+  crate::CONNECTION.with(|c| {
+    use crate::database::schema::servers;
+    update(&server)
+      .set(servers::enabled.eq(enabled))
+      .execute(c)
+      .chain_err(|| "could not update server")
+  })?;
+  println!("{} server {} ({})", if enabled { "enabled" } else { "disabled" }, server.id, server.title);
+  Ok(())
+}
+
+fn news_list(args: &Args) -> Result<()> {
+  args.expect(0, &["kind", "tag", "region", "limit"])?;
+  let query: Vec<(String, String)> = args.options.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+  let items = FeedFilter::from_query(&query)?.load()?;
+  for item in items {
+    println!(
+      "{:>5}  {}  {:<14}  {}  {}",
+      item.id,
+      item.created.format("%Y-%m-%d %H:%M"),
+      item.kind.as_str(),
+      item.lodestone_id,
+      item.title,
+    );
+  }
+  Ok(())
+}
+
+fn news_show(args: &Args) -> Result<()> {
+  args.expect(1, &[])?;
+  let item = find_item(&args.positional[0])?;
+  let (servers, records, failures) = crate::CONNECTION.with(|c| -> QueryResult<_> {
+    use crate::database::schema::{send_failures, send_records, servers};
+    let servers: Vec<Server> = servers::table.order(servers::id).load(c)?;
+    let records: Vec<SendRecord> = send_records::table.filter(send_records::news_id.eq(item.id)).load(c)?;
+    let failures: Vec<SendFailure> = send_failures::table.filter(send_failures::news_id.eq(item.id)).load(c)?;
+    Ok((servers, records, failures))
+  })
+  .chain_err(|| "could not load deliveries")?;
+
+  let exported = serde_json::to_string_pretty(&ExportedItem::from(&item)).chain_err(|| "could not serialize item")?;
+  println!("{}\n", exported);
+  println!("deliveries:");
+  for server in servers {
+    let status = if let Some(record) = records.iter().find(|r| r.server_id == server.id) {
+      match record.remote_id {
+        Some(ref id) => format!("sent ({})", id),
+        None => "sent".to_string(),
+      }
+    } else if let Some(failure) = failures.iter().find(|f| f.server_id == server.id) {
+      format!("failed at {}: {}", failure.attempted.format("%Y-%m-%d %H:%M"), failure.error)
+    } else if item.created >= server.created {
+      "waiting".to_string()
+    } else {
+      "-".to_string()
+    };
+    println!("{:>4}  {}  {}", server.id, server.title, status);
+  }
+  Ok(())
+}
+
+fn send_test(args: &Args) -> Result<()> {
+  args.expect(1, &[])?;
+  let server = find_server(&args.positional[0])?;
+  let (item, outcome) = NewsSender::new().send_test(&server)?;
+  println!("sending {} ({})", item.title, item.lodestone_id);
+  print_outcome(&outcome)
+}
+
+fn resend(args: &Args) -> Result<()> {
+  args.expect(2, &[])?;
+  let item = find_item(&args.positional[0])?;
+  let server = find_server(&args.positional[1])?;
+  let outcome = NewsSender::new().resend(&server, &item)?;
+  print_outcome(&outcome)
+}
+
+fn mark_sent(args: &Args) -> Result<()> {
+  args.expect(1, &["before"])?;
+  let server = find_server(&args.positional[0])?;
+  let before = args.options.get("before").ok_or("--before is required")?;
+  let before = parse_since(before).ok_or_else(|| format!("invalid date: {}", before))?;
+
+  let marked = crate::CONNECTION.with(|c| {
+    use crate::database::schema::{news_items, send_records};
+    c.transaction(|| {
+      let ids: Vec<i32> = news_items::table
+        .select(news_items::id)
+        .filter(news_items::created.lt(before))
+        .load(c)?;
+      let records: Vec<NewSendRecord> = ids.into_iter()
+        .map(|id| NewSendRecord {
+          server_id: server.id,
+          news_id: id,
+          remote_id: None,
+        })
+        .collect();
+      // items that were already sent keep their record
+      insert_or_ignore_into(send_records::table).values(&records).execute(c)
+    })
+    .chain_err(|| "could not mark items as sent")
+  })?;
+  println!("marked {} item{} as sent to server {}", marked, if marked == 1 { "" } else { "s" }, server.id);
+  Ok(())
+}
+
+fn import_legacy(args: &Args) -> Result<()> {
+  args.expect(1, &[])?;
+  let dry_run = args.flags.iter().any(|f| f == "dry-run");
+  let import = Import::plan(&args.positional[0])?;
+
+  for (key, reason) in &import.invalid {
+    println!("invalid  {}: {}", key, reason);
+  }
+  if dry_run {
+    for item in &import.new {
+      println!("new      {}  {:<14}  {}  {}", item.created.format("%Y-%m-%d %H:%M"), item.kind.as_str(), item.lodestone_id, item.title);
+    }
+  }
+  println!(
+    "{} new item{}, {} already present, {} invalid",
+    import.new.len(),
+    if import.new.len() == 1 { "" } else { "s" },
+    import.existing,
+    import.invalid.len(),
+  );
+  if !import.invalid.is_empty() {
+    return Err("the file has invalid entries; fix or remove them to import it".into());
+  }
+  if dry_run {
+    println!("would record them as sent to {} server{}", import.servers.len(), if import.servers.len() == 1 { "" } else { "s" });
+    return Ok(());
+  }
+
+  let recorded = import.apply()?;
+  println!("imported {} item{} and recorded {} send{}", import.new.len(), if import.new.len() == 1 { "" } else { "s" }, recorded, if recorded == 1 { "" } else { "s" });
+  Ok(())
+}
+
+fn token_list(args: &Args) -> Result<()> {
+  args.expect(0, &[])?;
+  let tokens: Vec<AdminToken> = crate::CONNECTION.with(|c| {
+    use crate::database::schema::admin_tokens;
+    admin_tokens::table.order(admin_tokens::id).load(c).chain_err(|| "could not load admin tokens")
+  })?;
+  for token in tokens {
+    println!("{:>4}  {}  {}", token.id, token.created.format("%Y-%m-%d %H:%M"), token.name);
+  }
+  Ok(())
+}
+
+fn token_add(args: &Args) -> Result<()> {
+  args.expect(1, &[])?;
+  let token = admin::new_token()?;
+  let new_token = NewAdminToken {
+    name: args.positional[0].clone(),
+    token_hash: admin::hash_token(&token),
+    created: Utc::now().naive_utc(),
+  };
+  let added: AdminToken = crate::CONNECTION.with(|c| {
+    use crate::database::schema::admin_tokens;
+    c.transaction(|| {
+      insert_into(admin_tokens::table).values(&new_token).execute(c)?;
+      admin_tokens::table.order(admin_tokens::id.desc()).first(c)
+    })
+    .chain_err(|| "could not insert admin token")
+  })?;
+  // only the hash is stored, so this is the only chance to copy it
+  println!("added token {} ({}), which won't be shown again:", added.id, added.name);
+  println!("{}", token);
+  Ok(())
+}
+
+fn token_revoke(args: &Args) -> Result<()> {
+  args.expect(1, &[])?;
+  let id: i32 = args.positional[0].parse().chain_err(|| format!("invalid token id: {}", args.positional[0]))?;
+  let token: AdminToken = crate::CONNECTION.with(|c| {
+    use crate::database::schema::admin_tokens;
+    c.transaction(|| -> QueryResult<Option<AdminToken>> {
+      let token = admin_tokens::table.find(id).first(c).optional()?;
+      delete(admin_tokens::table.find(id)).execute(c)?;
+      Ok(token)
+    })
+    .chain_err(|| "could not revoke admin token")
+  })?
+  .ok_or_else(|| format!("no token with id {}", id))?;
+  println!("revoked token {} ({})", token.id, token.name);
+  Ok(())
+}
+
+fn find_email_server(id: &str) -> Result<Server> {
+  let server = find_server(id)?;
+  if server.sink != "email" {
+    return Err(format!("server {} uses the {} sink, not email", server.id, server.sink).into());
+  }
+  Ok(server)
+}
+
+fn subscriber_list(args: &Args) -> Result<()> {
+  args.expect(1, &[])?;
+  let server = find_email_server(&args.positional[0])?;
+  let subscribers: Vec<EmailSubscriber> = crate::CONNECTION.with(|c| {
+    use crate::database::schema::email_subscribers;
+    email_subscribers::table
+      .filter(email_subscribers::server_id.eq(server.id))
+      .order(email_subscribers::address)
+      .load(c)
+      .chain_err(|| "could not load email subscribers")
+  })?;
+  for subscriber in subscribers {
+    println!("{}", subscriber.address);
+  }
+  Ok(())
+}
+
+fn subscriber_add(args: &Args) -> Result<()> {
+  args.expect(2, &[])?;
+  let server = find_email_server(&args.positional[0])?;
+  let subscriber = NewEmailSubscriber {
+    server_id: server.id,
+    address: validation::email_address(&args.positional[1])?,
+  };
+  let added = crate::CONNECTION.with(|c| {
+    use crate::database::schema::email_subscribers;
+    insert_or_ignore_into(email_subscribers::table)
+      .values(&subscriber)
+      .execute(c)
+      .chain_err(|| "could not insert email subscriber")
+  })?;
+  if added == 0 {
+    println!("{} is already subscribed to server {}", subscriber.address, server.id);
+  } else {
+    println!("subscribed {} to server {}", subscriber.address, server.id);
+  }
+  Ok(())
+}
+
+fn subscriber_remove(args: &Args) -> Result<()> {
+  args.expect(2, &[])?;
+  let server = find_email_server(&args.positional[0])?;
+  let address = args.positional[1].trim();
+  let removed = crate::CONNECTION.with(|c| {
+    use crate::database::schema::email_subscribers;
+    delete(
+      email_subscribers::table
+        .filter(email_subscribers::server_id.eq(server.id))
+        .filter(email_subscribers::address.eq(address))
+    )
+    .execute(c)
+    .chain_err(|| "could not delete email subscriber")
+  })?;
+  if removed == 0 {
+    return Err(format!("{} isn't subscribed to server {}", address, server.id).into());
+  }
+  println!("unsubscribed {} from server {}", address, server.id);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn args(args: &[&str]) -> Args {
+    Args::parse(&args.iter().map(ToString::to_string).collect::<Vec<_>>()).unwrap()
+  }
+
+  #[test]
+  fn unknown_options_are_refused() {
+    assert!(args(&["3"]).expect(1, &[]).is_ok());
+    assert!(args(&["3", "--before", "2019-11-01"]).expect(1, &["before"]).is_ok());
+
+    let err = args(&["3", "--limit", "5"]).expect(1, &[]).unwrap_err();
+    assert_eq!(err.to_string(), "unknown option --limit");
+    let err = args(&["3", "--befor=2019-11-01"]).expect(1, &["before"]).unwrap_err();
+    assert_eq!(err.to_string(), "unknown option --befor");
+    assert!(args(&["3", "4"]).expect(1, &[]).is_err());
+  }
+
+  #[test]
+  fn subscribers_are_only_removed_from_email_servers() {
+    crate::database::test_database();
+    crate::CONNECTION.with(|c| {
+      use crate::database::schema::servers;
+      insert_into(servers::table).values(&NewServer::new("Discord", "https://discord.com/api/webhooks/1/a")).execute(c).unwrap();
+    });
+
+    let err = subscriber_remove(&args(&["1", "a@example.com"])).unwrap_err();
+    assert_eq!(err.to_string(), "server 1 uses the discord sink, not email");
+  }
+}
@@ -0,0 +1,199 @@
+use diesel::{
+  prelude::*,
+  connection::SimpleConnection,
+  dsl::sql,
+  insert_into,
+  sql_types::BigInt,
+  sqlite::SqliteConnection,
+};
+
+use crate::errors::*;
+
+// the table the diesel CLI records applied migrations in, so databases it set up keep working
+table! {
+  __diesel_schema_migrations (version) {
+    version -> Text,
+    run_on -> Timestamp,
+  }
+}
+
+macro_rules! migrations {
+  ($($name:expr),* $(,)?) => {
+    &[$(($name, include_str!(concat!("../../migrations/", $name, "/up.sql")))),*]
+  };
+}
+
+/// Every directory in `migrations/`, oldest first. A new migration has to be added here too, which a
+/// test checks.
+const MIGRATIONS: &[(&str, &str)] = migrations![
+  "20171008220637_create_news_items",
+  "20171008220936_create_servers",
+  "20171008221025_create_send_records",
+  "2018-02-04-180147_add_image",
+  "2018-02-04-184811_add_description",
+  "2018-02-07-051337_add_fields_column",
+  "2019-09-01-120000_add_digest_settings",
+  "2019-09-08-120000_add_quiet_hours",
+  "2019-09-15-120000_add_guild_settings",
+  "2019-09-22-120000_add_scheduled_events",
+  "2019-09-29-120000_create_reminders",
+  "2019-10-06-120000_add_sinks",
+  "2019-10-13-120000_add_remote_ids",
+  "2019-10-20-120000_create_email_subscribers",
+  "2019-10-27-120000_add_admin_api",
+  "2019-11-03-120000_create_send_failures",
+];
+
+/// The version diesel gives a migration directory: the digits before the first underscore.
+fn version(name: &str) -> String {
+  name.split('_').next().unwrap_or_default().replace('-', "")
+}
+
+pub struct Status {
+  /// Migrations in this build that haven't been applied, oldest first.
+  pub pending: Vec<&'static str>,
+  /// Versions applied to the database that this build doesn't know about.
+  pub unknown: Vec<String>,
+}
+
+impl Status {
+  /// Fails if the database was migrated by a newer build.
+  pub fn check_known(&self) -> Result<()> {
+    if self.unknown.is_empty() {
+      return Ok(());
+    }
+    Err(format!(
+      "the database has migrations this version doesn't know about ({}); it was probably used by a newer version",
+      self.unknown.join(", "),
+    ).into())
+  }
+
+  /// Fails unless the database is exactly at this build's schema.
+  pub fn check_current(&self) -> Result<()> {
+    self.check_known()?;
+    if self.pending.is_empty() {
+      return Ok(());
+    }
+    Err(format!("the database is missing {} migration(s), run `lodestone_news migrate`", self.pending.len()).into())
+  }
+}
+
+fn applied(c: &SqliteConnection) -> QueryResult<Vec<String>> {
+  // a new database doesn't have the table until the first migration is applied
+  let tables: i64 = sql::<BigInt>(
+    "select count(*) from sqlite_master where type = 'table' and name = '__diesel_schema_migrations'",
+  ).get_result(c)?;
+  if tables == 0 {
+    return Ok(Vec::new());
+  }
+  __diesel_schema_migrations::table
+    .select(__diesel_schema_migrations::version)
+    .load(c)
+}
+
+/// Compares the database with this build's migrations without changing it.
+pub fn status() -> Result<Status> {
+  let applied = crate::CONNECTION.with(applied).chain_err(|| "could not load applied migrations")?;
+  let known: Vec<String> = MIGRATIONS.iter().map(|(name, _)| version(name)).collect();
+  Ok(Status {
+    pending: MIGRATIONS.iter()
+      .filter(|(name, _)| !applied.contains(&version(name)))
+      .map(|(name, _)| *name)
+      .collect(),
+    unknown: applied.into_iter().filter(|v| !known.contains(v)).collect(),
+  })
+}
+
+/// Applies every pending migration, each in its own transaction, and returns their names.
+pub fn run() -> Result<Vec<&'static str>> {
+  let status = status()?;
+  status.check_known()?;
+  if status.pending.is_empty() {
+    return Ok(Vec::new());
+  }
+
+  crate::CONNECTION.with(|c| {
+    // older migrations rebuild tables by renaming them, which only works the way they expect with
+    // foreign keys off and the old rename behaviour. neither pragma can change inside a transaction.
+    c.batch_execute("PRAGMA foreign_keys = OFF; PRAGMA legacy_alter_table = ON;")
+      .chain_err(|| "could not prepare database for migrating")?;
+    let result = apply(c, &status.pending);
+    c.batch_execute("PRAGMA foreign_keys = ON; PRAGMA legacy_alter_table = OFF;")
+      .chain_err(|| "could not re-enable foreign keys")?;
+    result
+  })?;
+  Ok(status.pending)
+}
+
+fn apply(c: &SqliteConnection, pending: &[&'static str]) -> Result<()> {
+  c.batch_execute(
+    "create table if not exists __diesel_schema_migrations (
+      version varchar(50) primary key not null,
+      run_on timestamp not null default current_timestamp
+    );",
+  )
+  .chain_err(|| "could not create the migrations table")?;
+  for name in pending {
+    let sql = MIGRATIONS.iter().find(|(n, _)| n == name).map(|(_, sql)| *sql).unwrap_or_default();
+    c.transaction(|| -> QueryResult<()> {
+      c.batch_execute(sql)?;
+      insert_into(__diesel_schema_migrations::table)
+        .values(__diesel_schema_migrations::version.eq(version(name)))
+        .execute(c)?;
+      Ok(())
+    })
+    .chain_err(|| format!("could not apply migration {}", name))?;
+    info!("Applied migration {}", name);
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+
+  #[test]
+  fn every_migration_is_listed() {
+    let mut dirs: Vec<String> = fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/migrations")).unwrap()
+      .map(|entry| entry.unwrap())
+      .filter(|entry| entry.path().is_dir())
+      .map(|entry| entry.file_name().to_string_lossy().into_owned())
+      .collect();
+    dirs.sort_by_key(|name| version(name));
+    let listed: Vec<&str> = MIGRATIONS.iter().map(|(name, _)| *name).collect();
+    assert_eq!(listed, dirs);
+  }
+
+  #[test]
+  fn status_does_not_write() {
+    std::env::set_var("LN_DATABASE_LOCATION", ":memory:");
+    let fresh = status().unwrap();
+    assert_eq!(fresh.pending.len(), MIGRATIONS.len());
+    assert!(fresh.unknown.is_empty());
+    assert!(fresh.check_current().is_err());
+    let tables: i64 = crate::CONNECTION.with(|c| {
+      sql::<BigInt>("select count(*) from sqlite_master").get_result(c).unwrap()
+    });
+    assert_eq!(tables, 0);
+
+    assert_eq!(run().unwrap().len(), MIGRATIONS.len());
+    status().unwrap().check_current().unwrap();
+    assert!(run().unwrap().is_empty());
+  }
+
+  #[test]
+  fn unknown_versions_are_refused() {
+    crate::database::test_database();
+    crate::CONNECTION.with(|c| {
+      insert_into(__diesel_schema_migrations::table)
+        .values(__diesel_schema_migrations::version.eq("20991231000000"))
+        .execute(c)
+        .unwrap();
+    });
+    let newer = status().unwrap();
+    assert_eq!(newer.unknown, vec!["20991231000000".to_string()]);
+    assert!(newer.check_known().is_err());
+    assert!(run().is_err());
+  }
+}
@@ -1,2 +1,16 @@
+pub mod migrations;
 pub mod schema;
 pub mod models;
+
+use diesel::sql_types::{Nullable, Text};
+
+sql_function!(fn lower(x: Nullable<Text>) -> Nullable<Text>);
+
+/// Points this thread's connection at a new in-memory database with every migration applied.
+///
+/// Tests each run on their own thread, so they each get an empty database.
+#[cfg(test)]
+pub fn test_database() {
+  std::env::set_var("LN_DATABASE_LOCATION", ":memory:");
+  migrations::run().unwrap();
+}
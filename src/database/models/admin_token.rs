@@ -0,0 +1,15 @@
+use chrono::NaiveDateTime;
+
+use crate::database::schema::*;
+
+insertable! {
+  #[derive(Debug, Queryable, Identifiable)]
+  pub struct AdminToken,
+  #[derive(Debug, Insertable)]
+  #[table_name = "admin_tokens"]
+  pub struct NewAdminToken {
+    pub name: String,
+    pub token_hash: String,
+    pub created: NaiveDateTime,
+  }
+}
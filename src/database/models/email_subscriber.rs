@@ -0,0 +1,12 @@
+use crate::database::schema::*;
+
+insertable! {
+  #[derive(Debug, Queryable, Identifiable)]
+  pub struct EmailSubscriber,
+  #[derive(Debug, Insertable)]
+  #[table_name = "email_subscribers"]
+  pub struct NewEmailSubscriber {
+    pub server_id: i32,
+    pub address: String,
+  }
+}
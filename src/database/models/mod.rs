@@ -20,14 +20,29 @@ macro_rules! insertable {
         }
       }
     }
+
+    impl $new_name {
+      /// Gives the row an id without inserting it.
+      pub fn with_id(self, id: i32) -> $name {
+        $name {
+          id,
+          $($field_name: self.$field_name),+
+        }
+      }
+    }
   }
 }
 
 use std::error::Error;
 use std::fmt::{Display, Formatter, Error as FmtError};
 
+pub mod admin_token;
+pub mod email_subscriber;
 pub mod news_item;
+pub mod reminder;
 pub mod server;
+pub mod scheduled_event;
+pub mod send_failure;
 pub mod send_record;
 
 #[derive(Debug)]
@@ -21,10 +21,11 @@ use crate::database::{
 use std::{
   borrow::Borrow,
   error::Error,
+  str::FromStr,
 };
 
 insertable! {
-  #[derive(Debug, Queryable, Identifiable)]
+  #[derive(Debug, Clone, Queryable, Identifiable)]
   pub struct NewsItem,
   #[derive(Debug, Insertable)]
   #[table_name = "news_items"]
@@ -38,10 +39,46 @@ insertable! {
     pub kind: NewsKind,
     pub created: NaiveDateTime,
     pub tag: Option<String>,
+    pub maintenance_start: Option<NaiveDateTime>,
+    pub maintenance_end: Option<NaiveDateTime>,
   }
 }
 
-#[derive(Debug)]
+impl NewsItem {
+  pub fn is_urgent(&self) -> bool {
+    if let NewsKind::SpecialNotice = self.kind {
+      return true;
+    }
+    match self.tag {
+      Some(ref tag) => match tag.to_lowercase().as_str() {
+        "important" | "maintenance" => true,
+        _ => false,
+      },
+      None => false,
+    }
+  }
+
+  /// A news item with only a title, for tests.
+  #[cfg(test)]
+  pub fn example(id: i32, title: &str) -> NewsItem {
+    NewsItem {
+      id,
+      title: title.to_string(),
+      url: format!("https://na.finalfantasyxiv.com/lodestone/news/detail/{}", id),
+      description: None,
+      fields: None,
+      image: None,
+      lodestone_id: id.to_string(),
+      kind: NewsKind::News,
+      created: NaiveDateTime::from_timestamp(1_500_000_000, 0),
+      tag: None,
+      maintenance_start: None,
+      maintenance_end: None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum NewsKind {
   SpecialNotice,
   News,
@@ -66,6 +103,14 @@ impl NewsKind {
     }
   }
 
+  pub fn as_str(&self) -> &'static str {
+    match *self {
+      NewsKind::SpecialNotice => "special_notice",
+      NewsKind::News => "news",
+      NewsKind::Topic => "topic",
+    }
+  }
+
   pub fn color<S: Borrow<String>>(&self, tag: Option<S>) -> Option<u32> {
     if let Some(tag) = tag {
       match tag.borrow().to_lowercase().as_str() {
@@ -94,6 +139,19 @@ impl ToString for NewsKind {
   }
 }
 
+impl FromStr for NewsKind {
+  type Err = SqlError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.trim().to_lowercase().as_str() {
+      "news" => Ok(NewsKind::News),
+      "topic" | "topics" => Ok(NewsKind::Topic),
+      "special notice" | "special notices" | "specialnotice" | "special_notice" => Ok(NewsKind::SpecialNotice),
+      _ => Err(SqlError::new(format!("unknown news kind: {}", s))),
+    }
+  }
+}
+
 impl<DB> Queryable<SmallInt, DB> for NewsKind
   where DB: Backend + HasSqlType<SmallInt>,
         NewsKind: FromSql<SmallInt, DB>
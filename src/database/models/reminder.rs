@@ -0,0 +1,49 @@
+use chrono::NaiveDateTime;
+
+use crate::database::{
+  models::SqlError,
+  schema::*,
+};
+
+use std::str::FromStr;
+
+insertable! {
+  #[derive(Debug, Queryable, Identifiable)]
+  pub struct Reminder,
+  #[derive(Debug, Insertable)]
+  #[table_name = "reminders"]
+  pub struct NewReminder {
+    pub server_id: i32,
+    pub news_id: i32,
+    pub kind: String,
+    pub due: NaiveDateTime,
+    pub sent: bool,
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReminderKind {
+  Start,
+  End,
+}
+
+impl ReminderKind {
+  pub fn as_str(&self) -> &'static str {
+    match *self {
+      ReminderKind::Start => "start",
+      ReminderKind::End => "end",
+    }
+  }
+}
+
+impl FromStr for ReminderKind {
+  type Err = SqlError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "start" => Ok(ReminderKind::Start),
+      "end" => Ok(ReminderKind::End),
+      _ => Err(SqlError::new(format!("unknown reminder kind: {}", s))),
+    }
+  }
+}
@@ -0,0 +1,30 @@
+use chrono::NaiveDateTime;
+
+use crate::database::{
+  schema::*,
+  models::{
+    news_item::NewsItem,
+    server::Server,
+  },
+};
+
+#[derive(Debug, Queryable, Associations)]
+#[belongs_to(NewsItem, foreign_key = "news_id")]
+#[belongs_to(Server, foreign_key = "server_id")]
+pub struct ScheduledEvent {
+  pub server_id: i32,
+  pub news_id: i32,
+  pub event_id: String,
+  pub start_time: NaiveDateTime,
+  pub end_time: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "scheduled_events"]
+pub struct NewScheduledEvent {
+  pub server_id: i32,
+  pub news_id: i32,
+  pub event_id: String,
+  pub start_time: NaiveDateTime,
+  pub end_time: NaiveDateTime,
+}
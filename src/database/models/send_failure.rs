@@ -0,0 +1,31 @@
+use chrono::NaiveDateTime;
+
+use crate::database::{
+  schema::*,
+  models::{
+    news_item::NewsItem,
+    server::Server,
+  },
+};
+
+/// The last unsuccessful attempt to send an item to a server that hasn't been sent since.
+#[derive(Debug, Queryable, Associations)]
+#[belongs_to(NewsItem, foreign_key = "news_id")]
+#[belongs_to(Server, foreign_key = "server_id")]
+pub struct SendFailure {
+  pub server_id: i32,
+  pub news_id: i32,
+  pub status: Option<i32>,
+  pub error: String,
+  pub attempted: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "send_failures"]
+pub struct NewSendFailure {
+  pub server_id: i32,
+  pub news_id: i32,
+  pub status: Option<i32>,
+  pub error: String,
+  pub attempted: NaiveDateTime,
+}
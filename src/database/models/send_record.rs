@@ -12,6 +12,7 @@ use crate::database::{
 pub struct SendRecord {
   pub server_id: i32,
   pub news_id: i32,
+  pub remote_id: Option<String>,
 }
 
 #[derive(Debug, Insertable)]
@@ -19,4 +20,5 @@ pub struct SendRecord {
 pub struct NewSendRecord {
   pub server_id: i32,
   pub news_id: i32,
+  pub remote_id: Option<String>,
 }
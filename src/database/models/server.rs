@@ -1,15 +1,126 @@
-use chrono::NaiveDateTime;
+use chrono::{NaiveDateTime, Utc};
 
-use crate::database::schema::*;
+use chrono_tz::Tz;
+
+use diesel::{
+  prelude::*,
+  delete,
+};
+
+use serde::de::DeserializeOwned;
+
+use crate::{
+  database::{
+    models::news_item::{NewsItem, NewsKind},
+    schema::*,
+  },
+  errors::*,
+};
 
 insertable! {
   #[derive(Debug, Queryable, Identifiable)]
   pub struct Server,
-  #[derive(Debug, Insertable)]
+  #[derive(Debug, Insertable, AsChangeset)]
   #[table_name = "servers"]
+  #[changeset_options(treat_none_as_null = "true")]
   pub struct NewServer {
     pub title: String,
     pub url: String,
     pub created: NaiveDateTime,
+    pub digest_schedule: Option<String>,
+    pub digest_style: String,
+    pub timezone: Option<String>,
+    pub quiet_start: Option<String>,
+    pub quiet_end: Option<String>,
+    pub quiet_bypass: bool,
+    pub guild_id: Option<String>,
+    pub channel_id: Option<String>,
+    pub filter_kinds: Option<String>,
+    pub filter_tags: Option<String>,
+    pub bot_token: Option<String>,
+    pub scheduled_events: bool,
+    pub reminders: bool,
+    pub reminder_lead: i32,
+    pub sink: String,
+    pub config: Option<String>,
+    pub enabled: bool,
+  }
+}
+
+impl NewServer {
+  pub fn new<T: Into<String>, U: Into<String>>(title: T, url: U) -> Self {
+    NewServer {
+      title: title.into(),
+      url: url.into(),
+      created: Utc::now().naive_utc(),
+      digest_schedule: None,
+      digest_style: "embeds".into(),
+      timezone: None,
+      quiet_start: None,
+      quiet_end: None,
+      quiet_bypass: true,
+      guild_id: None,
+      channel_id: None,
+      filter_kinds: None,
+      filter_tags: None,
+      bot_token: None,
+      scheduled_events: false,
+      reminders: false,
+      reminder_lead: 60,
+      sink: "discord".into(),
+      config: None,
+      enabled: true,
+    }
+  }
+}
+
+impl Server {
+  pub fn timezone(&self) -> Result<Tz> {
+    match self.timezone {
+      Some(ref tz) => tz.parse().map_err(|e| format!("invalid time zone {}: {}", tz, e).into()),
+      None => Ok(Tz::UTC),
+    }
+  }
+
+  pub fn config<T: DeserializeOwned>(&self) -> Result<T> {
+    let config = self.config.as_ref().map(String::as_str).unwrap_or("{}");
+    serde_json::from_str(config).chain_err(|| format!("invalid {} config", self.sink))
+  }
+
+  /// Deletes the server along with everything recorded for it.
+  pub fn delete(&self) -> Result<()> {
+    crate::CONNECTION.with(|c| {
+      c.transaction(|| {
+        delete(email_subscribers::table.filter(email_subscribers::server_id.eq(self.id))).execute(c)?;
+        delete(reminders::table.filter(reminders::server_id.eq(self.id))).execute(c)?;
+        delete(scheduled_events::table.filter(scheduled_events::server_id.eq(self.id))).execute(c)?;
+        delete(send_failures::table.filter(send_failures::server_id.eq(self.id))).execute(c)?;
+        delete(send_records::table.filter(send_records::server_id.eq(self.id))).execute(c)?;
+        delete(servers::table.find(self.id)).execute(c)
+      })
+      .chain_err(|| "could not delete server")
+    })?;
+    Ok(())
+  }
+
+  pub fn accepts(&self, item: &NewsItem) -> bool {
+    if let Some(ref kinds) = self.filter_kinds {
+      let matches = kinds.split(',')
+        .filter_map(|k| k.parse::<NewsKind>().ok())
+        .any(|k| k == item.kind);
+      if !matches {
+        return false;
+      }
+    }
+    if let Some(ref tags) = self.filter_tags {
+      let tag = match item.tag {
+        Some(ref t) => t.to_lowercase(),
+        None => return false,
+      };
+      if !tags.split(',').any(|t| t.trim().to_lowercase() == tag) {
+        return false;
+      }
+    }
+    true
   }
 }
@@ -1,3 +1,20 @@
+table! {
+    admin_tokens (id) {
+        id -> Integer,
+        name -> Text,
+        token_hash -> Text,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    email_subscribers (id) {
+        id -> Integer,
+        server_id -> Integer,
+        address -> Text,
+    }
+}
+
 table! {
     news_items (id) {
         id -> Integer,
@@ -10,6 +27,39 @@ table! {
         kind -> SmallInt,
         created -> Timestamp,
         tag -> Nullable<Text>,
+        maintenance_start -> Nullable<Timestamp>,
+        maintenance_end -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    reminders (id) {
+        id -> Integer,
+        server_id -> Integer,
+        news_id -> Integer,
+        kind -> Text,
+        due -> Timestamp,
+        sent -> Bool,
+    }
+}
+
+table! {
+    scheduled_events (server_id, news_id) {
+        server_id -> Integer,
+        news_id -> Integer,
+        event_id -> Text,
+        start_time -> Timestamp,
+        end_time -> Timestamp,
+    }
+}
+
+table! {
+    send_failures (server_id, news_id) {
+        server_id -> Integer,
+        news_id -> Integer,
+        status -> Nullable<Integer>,
+        error -> Text,
+        attempted -> Timestamp,
     }
 }
 
@@ -17,6 +67,7 @@ table! {
     send_records (server_id, news_id) {
         server_id -> Integer,
         news_id -> Integer,
+        remote_id -> Nullable<Text>,
     }
 }
 
@@ -26,14 +77,43 @@ table! {
         title -> Text,
         url -> Text,
         created -> Timestamp,
+        digest_schedule -> Nullable<Text>,
+        digest_style -> Text,
+        timezone -> Nullable<Text>,
+        quiet_start -> Nullable<Text>,
+        quiet_end -> Nullable<Text>,
+        quiet_bypass -> Bool,
+        guild_id -> Nullable<Text>,
+        channel_id -> Nullable<Text>,
+        filter_kinds -> Nullable<Text>,
+        filter_tags -> Nullable<Text>,
+        bot_token -> Nullable<Text>,
+        scheduled_events -> Bool,
+        reminders -> Bool,
+        reminder_lead -> Integer,
+        sink -> Text,
+        config -> Nullable<Text>,
+        enabled -> Bool,
     }
 }
 
+joinable!(email_subscribers -> servers (server_id));
+joinable!(reminders -> news_items (news_id));
+joinable!(reminders -> servers (server_id));
+joinable!(scheduled_events -> news_items (news_id));
+joinable!(scheduled_events -> servers (server_id));
+joinable!(send_failures -> news_items (news_id));
+joinable!(send_failures -> servers (server_id));
 joinable!(send_records -> news_items (news_id));
 joinable!(send_records -> servers (server_id));
 
 allow_tables_to_appear_in_same_query!(
+    admin_tokens,
+    email_subscribers,
     news_items,
+    reminders,
+    scheduled_events,
+    send_failures,
     send_records,
     servers,
 );
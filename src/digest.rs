@@ -0,0 +1,145 @@
+use chrono::{Duration, NaiveDateTime, NaiveTime, Timelike};
+
+use crate::errors::*;
+
+use std::str::FromStr;
+
+// Discord allows at most ten embeds per message.
+pub const MAX_EMBEDS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DigestSchedule {
+  Hourly,
+  Daily(NaiveTime),
+  Items(usize),
+}
+
+impl DigestSchedule {
+  /// Returns whether the held items should be sent now.
+  ///
+  /// Hourly and daily schedules flush once a boundary has passed since the oldest held item was
+  /// published, so nothing has to be stored between runs.
+  pub fn is_due(&self, oldest: NaiveDateTime, held: usize, now: NaiveDateTime) -> bool {
+    match *self {
+      DigestSchedule::Hourly => {
+        let boundary = now.date().and_hms(now.hour(), 0, 0);
+        boundary > oldest
+      },
+      DigestSchedule::Daily(time) => {
+        let today = now.date().and_time(time);
+        let boundary = if today <= now { today } else { today - Duration::days(1) };
+        boundary > oldest
+      },
+      DigestSchedule::Items(n) => held >= n,
+    }
+  }
+}
+
+impl FromStr for DigestSchedule {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    let mut parts = s.split_whitespace();
+    let schedule = match (parts.next(), parts.next()) {
+      (Some("hourly"), None) => DigestSchedule::Hourly,
+      (Some("daily"), Some(time)) => {
+        let time = NaiveTime::parse_from_str(time, "%H:%M")
+          .chain_err(|| format!("invalid daily digest time: {}", time))?;
+        DigestSchedule::Daily(time)
+      },
+      (Some("items"), Some(n)) => match n.parse() {
+        Ok(n) if n > 0 => DigestSchedule::Items(n),
+        _ => return Err(format!("invalid digest item count: {}", n).into()),
+      },
+      _ => return Err(format!("unknown digest schedule: {}", s).into()),
+    };
+    if parts.next().is_some() {
+      return Err(format!("unknown digest schedule: {}", s).into());
+    }
+    Ok(schedule)
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DigestStyle {
+  Embeds,
+  Summary,
+}
+
+impl FromStr for DigestStyle {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s {
+      "embeds" => Ok(DigestStyle::Embeds),
+      "summary" => Ok(DigestStyle::Summary),
+      _ => Err(format!("unknown digest style: {}", s).into()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use chrono::NaiveDate;
+
+  fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+    NaiveDate::from_ymd(2019, 9, day).and_hms(hour, minute, 0)
+  }
+
+  #[test]
+  fn parses_schedules() {
+    assert_eq!("hourly".parse::<DigestSchedule>().unwrap(), DigestSchedule::Hourly);
+    assert_eq!("daily 08:30".parse::<DigestSchedule>().unwrap(), DigestSchedule::Daily(NaiveTime::from_hms(8, 30, 0)));
+    assert_eq!(" items  5 ".parse::<DigestSchedule>().unwrap(), DigestSchedule::Items(5));
+  }
+
+  #[test]
+  fn rejects_invalid_schedules() {
+    for s in &["", "weekly", "hourly 1", "daily", "daily 25:00", "daily 8am", "items", "items 0", "items -1", "items 2 3"] {
+      assert!(s.parse::<DigestSchedule>().is_err(), "{:?} was accepted", s);
+    }
+  }
+
+  #[test]
+  fn hourly_is_due_after_the_next_hour() {
+    let schedule = DigestSchedule::Hourly;
+    assert!(!schedule.is_due(at(1, 10, 5), 1, at(1, 10, 59)));
+    assert!(schedule.is_due(at(1, 10, 5), 1, at(1, 11, 0)));
+    // an item published exactly on the hour waits for the next one
+    assert!(!schedule.is_due(at(1, 11, 0), 1, at(1, 11, 30)));
+  }
+
+  #[test]
+  fn daily_is_due_once_the_time_passes() {
+    let schedule = DigestSchedule::Daily(NaiveTime::from_hms(8, 0, 0));
+    assert!(!schedule.is_due(at(1, 9, 0), 1, at(1, 23, 59)));
+    assert!(!schedule.is_due(at(1, 9, 0), 1, at(2, 7, 59)));
+    assert!(schedule.is_due(at(1, 9, 0), 1, at(2, 8, 0)));
+    assert!(schedule.is_due(at(1, 7, 0), 1, at(1, 8, 0)));
+  }
+
+  #[test]
+  fn daily_at_midnight_crosses_the_day_boundary() {
+    let schedule = DigestSchedule::Daily(NaiveTime::from_hms(0, 0, 0));
+    assert!(!schedule.is_due(at(1, 23, 0), 1, at(1, 23, 59)));
+    assert!(schedule.is_due(at(1, 23, 0), 1, at(2, 0, 0)));
+    // items held over more than a day are due straight away
+    assert!(schedule.is_due(at(1, 12, 0), 1, at(3, 12, 0)));
+  }
+
+  #[test]
+  fn items_is_due_at_the_count() {
+    let schedule = DigestSchedule::Items(3);
+    assert!(!schedule.is_due(at(1, 0, 0), 2, at(1, 0, 0)));
+    assert!(schedule.is_due(at(1, 0, 0), 3, at(1, 0, 0)));
+  }
+
+  #[test]
+  fn parses_styles() {
+    assert_eq!("embeds".parse::<DigestStyle>().unwrap(), DigestStyle::Embeds);
+    assert_eq!("summary".parse::<DigestStyle>().unwrap(), DigestStyle::Summary);
+    assert!("Summary".parse::<DigestStyle>().is_err());
+  }
+}
@@ -1,132 +1,287 @@
 use crate::{
   database::models::{
     news_item::NewsItem,
-    send_record::NewSendRecord,
     server::Server,
   },
-  errors::*,
+  digest::{DigestStyle, MAX_EMBEDS},
+  metrics,
+  sink::{Outcome, Sink, format::truncate},
 };
 
 use chrono::{Utc, Duration, DateTime};
 
-use diesel::{
-  prelude::*,
-  dsl::sql,
-  insert_into,
-};
-
 use reqwest::Client;
 
 use serde_json;
 
-use std::{
-  io::Read,
-  thread::sleep,
-};
+use std::thread::sleep;
+
+pub mod events;
 
-pub struct DiscordSender {
+// Discord limits embed descriptions to 2048 characters.
+const MAX_DESCRIPTION: usize = 2048;
+// Discord limits the combined text of every embed in a message to 6000 characters.
+const MAX_MESSAGE_TEXT: usize = 6000;
+
+pub struct DiscordSink {
   client: Client,
 }
 
-impl Default for DiscordSender {
+impl Default for DiscordSink {
   fn default() -> Self {
     Self::new()
   }
 }
 
-impl DiscordSender {
+impl DiscordSink {
   pub fn new() -> Self {
-    DiscordSender {
+    DiscordSink {
       client: Client::new(),
     }
   }
 
-  pub fn send_new_news(&self) -> Result<()> {
-    let to_send: Vec<(Server, NewsItem)> = crate::CONNECTION.with(|c| {
-      use crate::database::schema::{servers, news_items};
-      sql::<(servers::SqlType, news_items::SqlType)>("select
-        servers.*, news_items.*
-        from servers, news_items
-        where (servers.id, news_items.id) not in (select server_id, news_id from send_records)
-        and news_items.created >= servers.created;")
-        .load(c)
-        .chain_err(|| "could not load items to send")
-    })?;
-
-    let mut successful_sends = Vec::new();
-
-    for (server, item) in to_send {
-      info!("Sending {} ({}) to {} ({})", item.title, item.id, server.title, server.id);
-      let mut embed = serde_json::json!({
-        "type": "rich",
-        "timestamp": DateTime::<Utc>::from_utc(item.created, Utc).to_rfc3339(),
-        "color": item.kind.color(item.tag.as_ref()),
-        "title": item.title,
-        "url": item.url,
-        "description": item.description,
-        "fields": [],
+  pub fn embed(item: &NewsItem) -> serde_json::Value {
+    let mut embed = serde_json::json!({
+      "type": "rich",
+      "timestamp": DateTime::<Utc>::from_utc(item.created, Utc).to_rfc3339(),
+      "color": item.kind.color(item.tag.as_ref()),
+      "title": item.title,
+      "url": item.url,
+      "description": item.description,
+      "fields": [],
+    });
+    if let Some(ref image) = item.image {
+      embed["image"] = serde_json::json!({
+        "url": image,
       });
-      if let Some(ref image) = item.image {
-        embed["image"] = serde_json::json!({
-          "url": image,
-        });
-      }
-      if let Some(ref fields) = item.fields {
-        if let Ok(f) = serde_json::from_str::<Vec<serde_json::Value>>(fields) {
-          embed["fields"].as_array_mut().unwrap().extend(f);
-        }
+    }
+    if let Some(ref fields) = item.fields {
+      if let Ok(f) = serde_json::from_str::<Vec<serde_json::Value>>(fields) {
+        embed["fields"].as_array_mut().unwrap().extend(f);
       }
+    }
+    embed["fields"].as_array_mut().unwrap().push(serde_json::json!({
+      "name": "Kind",
+      "value": item.kind.to_string(),
+      "inline": true,
+    }));
+    if let Some(ref tag) = item.tag {
       embed["fields"].as_array_mut().unwrap().push(serde_json::json!({
-        "name": "Kind",
-        "value": item.kind.to_string(),
+        "name": "Tag",
+        "value": tag,
         "inline": true,
       }));
-      if let Some(ref tag) = item.tag {
-        embed["fields"].as_array_mut().unwrap().push(serde_json::json!({
-          "name": "Tag",
-          "value": tag,
-          "inline": true,
-        }));
-      }
-      let data = serde_json::json!({
-        "embeds": [embed],
-      });
-      let res = self.client.post(&server.url)
-        .json(&data)
-        .send();
-      let mut data = match res {
-        Ok(r) => r,
-        Err(e) => {
-          warn!("Error sending news item {} to server {}: {}", item.id, server.id, e);
-          continue;
-        },
-      };
-      let mut content = String::new();
-      if let Err(e) = data.read_to_string(&mut content) {
-        warn!("Could not read webhook response when sending item {} to sever {}: {}", item.id, server.id, e);
-        continue;
-      }
-      if !data.status().is_success() {
-        warn!("Webhook send was not successful for item {} on server {}. Content below:", item.id, server.id);
-        warn!("{}", content);
-      } else {
-        trace!("Webhook send successful for item {} on server {}", item.id, server.id);
-        successful_sends.push(NewSendRecord {
-          server_id: server.id,
-          news_id: item.id,
-        });
-      }
-      sleep(Duration::seconds(1).to_std().unwrap());
     }
+    embed
+  }
+
+  fn digest_messages(style: DigestStyle, items: &[NewsItem]) -> Vec<(serde_json::Value, &[NewsItem])> {
+    match style {
+      DigestStyle::Embeds => {
+        let mut messages = Vec::new();
+        let mut start = 0;
+        let mut embeds = Vec::new();
+        let mut text = 0;
+        for (i, item) in items.iter().enumerate() {
+          let embed = DiscordSink::embed(item);
+          let len = DiscordSink::embed_text(&embed);
+          if !embeds.is_empty() && (embeds.len() == MAX_EMBEDS || text + len > MAX_MESSAGE_TEXT) {
+            messages.push((serde_json::json!({ "embeds": embeds.split_off(0) }), &items[start..i]));
+            text = 0;
+            start = i;
+          }
+          text += len;
+          embeds.push(embed);
+        }
+        if !embeds.is_empty() {
+          messages.push((serde_json::json!({ "embeds": embeds }), &items[start..]));
+        }
+        messages
+      },
+      DigestStyle::Summary => {
+        let mut messages = Vec::new();
+        let mut start = 0;
+        let mut lines: Vec<String> = Vec::new();
+        for (i, item) in items.iter().enumerate() {
+          let line = DiscordSink::summary_line(item);
+          let len: usize = lines.iter().map(|l| l.chars().count() + 1).sum();
+          if !lines.is_empty() && len + line.chars().count() > MAX_DESCRIPTION {
+            messages.push((DiscordSink::summary(&lines), &items[start..i]));
+            lines.clear();
+            start = i;
+          }
+          lines.push(line);
+        }
+        if !lines.is_empty() {
+          messages.push((DiscordSink::summary(&lines), &items[start..]));
+        }
+        messages
+      },
+    }
+  }
 
-    crate::CONNECTION.with(|c| {
-      use crate::database::schema::send_records;
-      insert_into(send_records::table)
-        .values(&successful_sends)
-        .execute(c)
-        .chain_err(|| "could not update send records")
-    })?;
+  /// The length of the text in an embed that counts towards `MAX_MESSAGE_TEXT`.
+  fn embed_text(embed: &serde_json::Value) -> usize {
+    let len = |v: &serde_json::Value| v.as_str().map(|s| s.chars().count()).unwrap_or(0);
+    let fields: usize = embed["fields"].as_array()
+      .map(|f| f.iter().map(|f| len(&f["name"]) + len(&f["value"])).sum())
+      .unwrap_or(0);
+    len(&embed["title"]) + len(&embed["description"]) + len(&embed["footer"]["text"]) + len(&embed["author"]["name"]) + fields
+  }
+
+  fn summary_line(item: &NewsItem) -> String {
+    let line = |title: &str| match item.tag {
+      Some(ref tag) => format!("• [{}] [{}]({})", tag, title, item.url),
+      None => format!("• [{}]({})", title, item.url),
+    };
+    let full = line(&item.title);
+    let excess = match full.chars().count().checked_sub(MAX_DESCRIPTION) {
+      Some(0) | None => return full,
+      Some(e) => e,
+    };
+    // shorten the title so the link still works, unless the url alone is too long
+    match item.title.chars().count().checked_sub(excess) {
+      Some(keep) if keep > 1 => line(&truncate(&item.title, keep)),
+      _ => truncate(&full, MAX_DESCRIPTION),
+    }
+  }
+
+  fn summary(lines: &[String]) -> serde_json::Value {
+    serde_json::json!({
+      "embeds": [{
+        "type": "rich",
+        "timestamp": Utc::now().to_rfc3339(),
+        "title": "Lodestone news digest",
+        "description": lines.join("\n"),
+      }],
+    })
+  }
+
+  pub fn post(&self, url: &str, data: &serde_json::Value) -> Outcome {
+    let res = self.client.post(url)
+      .json(data)
+      .send();
+    let status = match res {
+      Ok(ref r) => r.status().as_u16().to_string(),
+      Err(_) => "error".to_string(),
+    };
+    metrics::inc(&metrics::WEBHOOK_SENDS, &[("status", &status)]);
+    let outcome = Outcome::from_response(res, |_| None);
+    // pause after failures too, so an unreachable webhook isn't retried back to back
+    let wait = Duration::seconds(1);
+    metrics::inc(&metrics::THROTTLE_WAITS, &[]);
+    metrics::add(&metrics::THROTTLE_WAIT_SECONDS, &[], wait.num_milliseconds() as f64 / 1000.0);
+    sleep(wait.to_std().unwrap());
+    outcome
+  }
+}
+
+impl Sink for DiscordSink {
+  fn send(&self, item: &NewsItem, destination: &Server) -> Outcome {
+    self.post(&destination.url, &self.preview(item, destination))
+  }
+
+  fn send_digest(&self, style: DigestStyle, items: &[NewsItem], destination: &Server) -> Vec<(Vec<i32>, Outcome)> {
+    DiscordSink::digest_messages(style, items).into_iter()
+      .map(|(data, chunk)| {
+        let ids = chunk.iter().map(|i| i.id).collect();
+        (ids, self.post(&destination.url, &data))
+      })
+      .collect()
+  }
+
+  fn preview(&self, item: &NewsItem, _destination: &Server) -> serde_json::Value {
+    serde_json::json!({
+      "embeds": [DiscordSink::embed(item)],
+    })
+  }
+
+  fn preview_digest(&self, style: DigestStyle, items: &[NewsItem], _destination: &Server) -> Vec<(Vec<i32>, serde_json::Value)> {
+    DiscordSink::digest_messages(style, items).into_iter()
+      .map(|(data, chunk)| (chunk.iter().map(|i| i.id).collect(), data))
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn items(n: i32, description: usize) -> Vec<NewsItem> {
+    (1..=n)
+      .map(|i| NewsItem {
+        description: Some("x".repeat(description)),
+        ..NewsItem::example(i, "Title")
+      })
+      .collect()
+  }
+
+  fn chunk_ids(messages: &[(serde_json::Value, &[NewsItem])]) -> Vec<Vec<i32>> {
+    messages.iter().map(|(_, chunk)| chunk.iter().map(|i| i.id).collect()).collect()
+  }
+
+  #[test]
+  fn embeds_are_limited_to_ten_per_message() {
+    let items = items(23, 10);
+    let messages = DiscordSink::digest_messages(DigestStyle::Embeds, &items);
+    let sizes: Vec<usize> = messages.iter().map(|(m, _)| m["embeds"].as_array().unwrap().len()).collect();
+    assert_eq!(sizes, vec![10, 10, 3]);
+  }
+
+  #[test]
+  fn embeds_are_limited_by_total_text() {
+    let items = items(5, 2000);
+    let messages = DiscordSink::digest_messages(DigestStyle::Embeds, &items);
+    assert_eq!(chunk_ids(&messages), vec![vec![1, 2], vec![3, 4], vec![5]]);
+    for (message, _) in &messages {
+      let text: usize = message["embeds"].as_array().unwrap().iter().map(DiscordSink::embed_text).sum();
+      assert!(text <= MAX_MESSAGE_TEXT, "{} characters", text);
+    }
+  }
+
+  #[test]
+  fn embed_text_counts_fields() {
+    let item = NewsItem {
+      description: Some("abc".into()),
+      tag: Some("Maintenance".into()),
+      ..NewsItem::example(1, "Title")
+    };
+    // title, description, "Kind"/"News" and "Tag"/"Maintenance"
+    assert_eq!(DiscordSink::embed_text(&DiscordSink::embed(&item)), 5 + 3 + 4 + 4 + 3 + 11);
+  }
+
+  #[test]
+  fn summary_lines_are_split_across_messages() {
+    let items: Vec<NewsItem> = (1..=40).map(|i| NewsItem::example(i, &"t".repeat(100))).collect();
+    let messages = DiscordSink::digest_messages(DigestStyle::Summary, &items);
+    assert!(messages.len() > 1);
+    for (message, _) in &messages {
+      assert!(message["embeds"][0]["description"].as_str().unwrap().chars().count() <= MAX_DESCRIPTION);
+    }
+    let ids: Vec<i32> = chunk_ids(&messages).into_iter().flatten().collect();
+    assert_eq!(ids, (1..=40).collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn long_summary_lines_are_truncated() {
+    let item = NewsItem::example(1, &"t".repeat(3000));
+    let line = DiscordSink::summary_line(&item);
+    assert_eq!(line.chars().count(), MAX_DESCRIPTION);
+    assert!(line.ends_with(&format!("…]({})", item.url)));
+
+    let item = NewsItem {
+      url: format!("https://example.com/{}", "u".repeat(3000)),
+      ..NewsItem::example(1, "Title")
+    };
+    assert_eq!(DiscordSink::summary_line(&item).chars().count(), MAX_DESCRIPTION);
+  }
 
-    Ok(())
+  #[test]
+  fn short_summary_lines_are_unchanged() {
+    let item = NewsItem {
+      tag: Some("Maintenance".into()),
+      ..NewsItem::example(1, "Title")
+    };
+    assert_eq!(DiscordSink::summary_line(&item), format!("• [Maintenance] [Title]({})", item.url));
   }
 }
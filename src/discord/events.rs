@@ -0,0 +1,223 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+use diesel::{
+  dsl::sql,
+  prelude::*,
+  insert_into,
+  sql_types::Timestamp,
+  update,
+};
+
+use reqwest::{Client, Method};
+
+use crate::{
+  database::models::{
+    news_item::NewsItem,
+    scheduled_event::{NewScheduledEvent, ScheduledEvent},
+    server::Server,
+  },
+  errors::*,
+  sink::format::truncate,
+};
+
+use std::io::Read;
+
+const API_BASE: &str = "https://discord.com/api/v9";
+
+// guild scheduled event constants from the Discord API
+const GUILD_ONLY: u8 = 2;
+const EXTERNAL: u8 = 3;
+
+pub struct GuildEventClient {
+  client: Client,
+}
+
+impl Default for GuildEventClient {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl GuildEventClient {
+  pub fn new() -> Self {
+    GuildEventClient {
+      client: Client::new(),
+    }
+  }
+
+  pub fn sync_events(&self) -> Result<()> {
+    let now = Utc::now().naive_utc();
+    let pairs: Vec<(Server, NewsItem)> = crate::CONNECTION.with(|c| {
+      use crate::database::schema::{servers, news_items};
+      sql::<(servers::SqlType, news_items::SqlType)>("select
+        servers.*, news_items.*
+        from servers, news_items
+        where servers.sink = 'discord'
+        and servers.enabled
+        and servers.scheduled_events
+        and servers.bot_token is not null
+        and servers.guild_id is not null
+        and news_items.maintenance_start is not null
+        and news_items.maintenance_end > ")
+        .bind::<Timestamp, _>(now)
+        .sql(" and news_items.created >= servers.created")
+        .load(c)
+        .chain_err(|| "could not load maintenance windows")
+    })?;
+
+    for (server, item) in pairs {
+      if !server.accepts(&item) {
+        continue;
+      }
+      let (start, end) = match (item.maintenance_start, item.maintenance_end) {
+        (Some(s), Some(e)) => (s, e),
+        _ => continue,
+      };
+
+      let existing: Option<ScheduledEvent> = crate::CONNECTION.with(|c| {
+        use crate::database::schema::scheduled_events;
+        scheduled_events::table
+          .find((server.id, item.id))
+          .first(c)
+          .optional()
+          .chain_err(|| "could not load scheduled event")
+      })?;
+
+      match existing {
+        Some(ref e) if e.start_time == start && e.end_time == end => {},
+        Some(e) => {
+          info!("Updating scheduled event {} for {} ({}) on {} ({})", e.event_id, item.title, item.id, server.title, server.id);
+          if let Err(err) = self.send_event(&server, &item, start, end, Some(&e.event_id)) {
+            warn!("Could not update scheduled event {} on server {}: {}", e.event_id, server.id, err);
+            continue;
+          }
+          crate::CONNECTION.with(|c| {
+            use crate::database::schema::scheduled_events;
+            update(scheduled_events::table.find((server.id, item.id)))
+              .set((scheduled_events::start_time.eq(start), scheduled_events::end_time.eq(end)))
+              .execute(c)
+              .chain_err(|| "could not update scheduled event")
+          })?;
+        },
+        None => {
+          // discord rejects events that have already started
+          if start <= now {
+            continue;
+          }
+          info!("Creating scheduled event for {} ({}) on {} ({})", item.title, item.id, server.title, server.id);
+          let event_id = match self.send_event(&server, &item, start, end, None) {
+            Ok(id) => id,
+            Err(e) => {
+              warn!("Could not create scheduled event for item {} on server {}: {}", item.id, server.id, e);
+              continue;
+            },
+          };
+          crate::CONNECTION.with(|c| {
+            use crate::database::schema::scheduled_events;
+            insert_into(scheduled_events::table)
+              .values(&NewScheduledEvent {
+                server_id: server.id,
+                news_id: item.id,
+                event_id,
+                start_time: start,
+                end_time: end,
+              })
+              .execute(c)
+              .chain_err(|| "could not insert scheduled event")
+          })?;
+        },
+      }
+    }
+
+    Ok(())
+  }
+
+  fn send_event(&self, server: &Server, item: &NewsItem, start: NaiveDateTime, end: NaiveDateTime, event_id: Option<&str>) -> Result<String> {
+    let (guild_id, token) = match (&server.guild_id, &server.bot_token) {
+      (Some(g), Some(t)) => (g, t),
+      _ => return Err("server has no guild id or bot token".into()),
+    };
+
+    let data = serde_json::json!({
+      "name": truncate(&item.title, 100),
+      "description": truncate(item.description.as_ref().map(String::as_str).unwrap_or(&item.url), 1000),
+      "privacy_level": GUILD_ONLY,
+      "entity_type": EXTERNAL,
+      "entity_metadata": {
+        "location": truncate(&item.url, 100),
+      },
+      "scheduled_start_time": DateTime::<Utc>::from_utc(start, Utc).to_rfc3339(),
+      "scheduled_end_time": DateTime::<Utc>::from_utc(end, Utc).to_rfc3339(),
+    });
+
+    let (method, url) = match event_id {
+      Some(id) => (Method::PATCH, format!("{}/guilds/{}/scheduled-events/{}", API_BASE, guild_id, id)),
+      None => (Method::POST, format!("{}/guilds/{}/scheduled-events", API_BASE, guild_id)),
+    };
+
+    let mut res = self.client.request(method, &url)
+      .header("Authorization", format!("Bot {}", token))
+      .json(&data)
+      .send()
+      .chain_err(|| "could not send scheduled event")?;
+    let mut content = String::new();
+    res.read_to_string(&mut content).chain_err(|| "could not read scheduled event response")?;
+    if !res.status().is_success() {
+      return Err(format!("discord returned {}: {}", res.status(), content).into());
+    }
+
+    let event: serde_json::Value = serde_json::from_str(&content).chain_err(|| "invalid scheduled event response")?;
+    event["id"].as_str()
+      .map(ToString::to_string)
+      .chain_err(|| "scheduled event response had no id")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use chrono::Duration;
+
+  use crate::database::models::server::NewServer;
+
+  #[test]
+  fn ended_maintenance_is_left_alone() {
+    crate::database::test_database();
+
+    let now = Utc::now().naive_utc();
+    let (start, end) = (now - Duration::hours(9), now - Duration::hours(1));
+    crate::CONNECTION.with(|c| {
+      use crate::database::schema::{news_items, scheduled_events, servers};
+      let server = NewServer {
+        scheduled_events: true,
+        bot_token: Some("token".into()),
+        guild_id: Some("100".into()),
+        created: now - Duration::days(2),
+        ..NewServer::new("Test", "https://discord.com/api/webhooks/1/test")
+      };
+      insert_into(servers::table).values(&server).execute(c).unwrap();
+      let item = NewsItem {
+        tag: Some("Maintenance".into()),
+        created: now - Duration::days(1),
+        maintenance_start: Some(start),
+        maintenance_end: Some(end),
+        ..NewsItem::example(1, "All Worlds Maintenance")
+      };
+      insert_into(news_items::table).values(&item.into_new()).execute(c).unwrap();
+      // an event created before the times were last changed
+      insert_into(scheduled_events::table)
+        .values(&NewScheduledEvent { server_id: 1, news_id: 1, event_id: "1".into(), start_time: start - Duration::hours(1), end_time: end })
+        .execute(c)
+        .unwrap();
+    });
+
+    GuildEventClient::new().sync_events().unwrap();
+
+    let event: ScheduledEvent = crate::CONNECTION.with(|c| {
+      use crate::database::schema::scheduled_events;
+      scheduled_events::table.find((1, 1)).first(c).unwrap()
+    });
+    assert_eq!(event.start_time, start - Duration::hours(1));
+  }
+}
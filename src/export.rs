@@ -0,0 +1,64 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+use serde_derive::Serialize;
+
+use crate::{
+  database::models::news_item::NewsItem,
+  lodestone::Field,
+  sink::format,
+};
+
+/// Bumped whenever a field is removed or changes meaning. New fields may be added freely.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The stable JSON form of a news item shared by everything that hands items to other programs.
+#[derive(Debug, Serialize)]
+pub struct ExportedItem {
+  pub version: u32,
+  pub id: i32,
+  pub lodestone_id: String,
+  pub kind: &'static str,
+  pub tag: Option<String>,
+  pub title: String,
+  pub url: String,
+  pub description: Option<String>,
+  pub image: Option<String>,
+  pub fields: Vec<Field>,
+  pub created: DateTime<Utc>,
+  pub maintenance: Option<Maintenance>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Maintenance {
+  pub start: DateTime<Utc>,
+  pub end: DateTime<Utc>,
+}
+
+fn utc(time: NaiveDateTime) -> DateTime<Utc> {
+  DateTime::from_utc(time, Utc)
+}
+
+impl<'a> From<&'a NewsItem> for ExportedItem {
+  fn from(item: &'a NewsItem) -> Self {
+    ExportedItem {
+      version: SCHEMA_VERSION,
+      id: item.id,
+      lodestone_id: item.lodestone_id.clone(),
+      kind: item.kind.as_str(),
+      tag: item.tag.clone(),
+      title: item.title.clone(),
+      url: item.url.clone(),
+      description: item.description.clone(),
+      image: item.image.clone(),
+      fields: format::fields(item),
+      created: utc(item.created),
+      maintenance: match (item.maintenance_start, item.maintenance_end) {
+        (Some(start), Some(end)) => Some(Maintenance {
+          start: utc(start),
+          end: utc(end),
+        }),
+        _ => None,
+      },
+    }
+  }
+}
@@ -0,0 +1,357 @@
+use chrono::{DateTime, Utc};
+
+use diesel::{
+  prelude::*,
+  sqlite::Sqlite,
+};
+
+use crate::{
+  database::{
+    lower,
+    models::news_item::{NewsItem, NewsKind},
+    schema::news_items,
+  },
+  errors::*,
+  sink::format::{self, escape_html},
+};
+
+use std::{
+  env,
+  fs,
+  path::Path,
+};
+
+const TITLE: &str = "Lodestone news";
+const HOME_PAGE: &str = "https://na.finalfantasyxiv.com/lodestone/news/";
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeedFormat {
+  Rss,
+  Atom,
+  Json,
+}
+
+impl FeedFormat {
+  pub fn content_type(&self) -> &'static str {
+    match *self {
+      FeedFormat::Rss => "application/rss+xml; charset=utf-8",
+      FeedFormat::Atom => "application/atom+xml; charset=utf-8",
+      FeedFormat::Json => "application/feed+json; charset=utf-8",
+    }
+  }
+
+  pub fn file_name(&self) -> &'static str {
+    match *self {
+      FeedFormat::Rss => "feed.rss",
+      FeedFormat::Atom => "feed.atom",
+      FeedFormat::Json => "feed.json",
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct FeedFilter {
+  pub kinds: Vec<NewsKind>,
+  pub tags: Vec<String>,
+  pub region: Option<String>,
+  pub limit: Option<i64>,
+}
+
+impl FeedFilter {
+  /// Builds a filter from query parameters. `kind` and `tag` may be repeated or comma-separated.
+  pub fn from_query(query: &[(String, String)]) -> std::result::Result<FeedFilter, String> {
+    let mut filter = FeedFilter::default();
+    for (key, value) in query {
+      match key.as_str() {
+        "kind" => for kind in value.split(',') {
+          filter.kinds.push(kind.parse().map_err(|e| format!("{}", e))?);
+        },
+        "tag" => filter.tags.extend(value.split(',').map(|t| t.trim().to_lowercase())),
+        "region" => filter.region = Some(value.to_lowercase()),
+        "limit" => filter.limit = Some(value.parse().map_err(|_| format!("invalid limit: {}", value))?),
+        _ => {},
+      }
+    }
+    Ok(filter)
+  }
+
+  /// The filtered items, newest first, to be narrowed down further or loaded.
+  pub fn query(&self) -> news_items::BoxedQuery<'static, Sqlite> {
+    let mut query = news_items::table
+      .order((news_items::created.desc(), news_items::id.desc()))
+      .into_boxed();
+    if !self.kinds.is_empty() {
+      query = query.filter(news_items::kind.eq_any(self.kinds.clone()));
+    }
+    if !self.tags.is_empty() {
+      query = query.filter(lower(news_items::tag).eq_any(self.tags.clone()));
+    }
+    if let Some(ref region) = self.region {
+      // items don't store a region, but their urls are on the regional site
+      query = query.filter(news_items::url.like(format!("https://{}.%", region)));
+    }
+    query
+  }
+
+  pub fn limit(&self) -> i64 {
+    self.limit.unwrap_or(DEFAULT_LIMIT).max(1).min(MAX_LIMIT)
+  }
+
+  pub fn load(&self) -> Result<Vec<NewsItem>> {
+    crate::CONNECTION.with(|c| {
+      self.query()
+        .limit(self.limit())
+        .load(c)
+        .chain_err(|| "could not load feed items")
+    })
+  }
+}
+
+pub fn render(format: FeedFormat, items: &[NewsItem]) -> String {
+  let self_url = env::var("LN_PUBLIC_URL").ok()
+    .map(|u| format!("{}/{}", u.trim_end_matches('/'), format.file_name()));
+  let updated = items.first()
+    .map(utc)
+    .unwrap_or_else(Utc::now);
+  match format {
+    FeedFormat::Rss => rss(items, self_url, updated),
+    FeedFormat::Atom => atom(items, self_url, updated),
+    FeedFormat::Json => json(items, self_url),
+  }
+}
+
+pub fn export<P: AsRef<Path>>(dir: P) -> Result<()> {
+  let dir = dir.as_ref();
+  fs::create_dir_all(dir).chain_err(|| format!("could not create {}", dir.display()))?;
+  let items = FeedFilter::default().load()?;
+  for &format in &[FeedFormat::Rss, FeedFormat::Atom, FeedFormat::Json] {
+    let path = dir.join(format.file_name());
+    // write then rename so a web server never serves half a file
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, render(format, &items)).chain_err(|| format!("could not write {}", tmp.display()))?;
+    fs::rename(&tmp, &path).chain_err(|| format!("could not write {}", path.display()))?;
+  }
+  debug!("Exported feeds to {}", dir.display());
+  Ok(())
+}
+
+fn utc(item: &NewsItem) -> DateTime<Utc> {
+  DateTime::from_utc(item.created, Utc)
+}
+
+fn categories(item: &NewsItem) -> Vec<String> {
+  let mut categories = vec![item.kind.to_string()];
+  categories.extend(item.tag.clone());
+  categories
+}
+
+fn image_type(url: &str) -> &'static str {
+  let url = url.to_lowercase();
+  if url.ends_with(".png") {
+    "image/png"
+  } else if url.ends_with(".gif") {
+    "image/gif"
+  } else {
+    "image/jpeg"
+  }
+}
+
+fn content(item: &NewsItem) -> String {
+  let mut html = String::new();
+  if let Some(ref image) = item.image {
+    html.push_str(&format!("<p><img src=\"{}\" alt=\"{}\"></p>", escape_html(image), escape_html(&item.title)));
+  }
+  if let Some(ref description) = item.description {
+    html.push_str(&format!("<p>{}</p>", format::html(description)));
+  }
+  for field in format::fields(item) {
+    html.push_str(&format!("<h3>{}</h3><p>{}</p>", escape_html(&field.name), format::html(&field.value)));
+  }
+  html
+}
+
+fn rss(items: &[NewsItem], self_url: Option<String>, updated: DateTime<Utc>) -> String {
+  let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+  xml.push_str("<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\"><channel>");
+  xml.push_str(&format!("<title>{}</title><link>{}</link>", TITLE, HOME_PAGE));
+  xml.push_str("<description>News from the FINAL FANTASY XIV Lodestone</description>");
+  xml.push_str(&format!("<lastBuildDate>{}</lastBuildDate>", updated.to_rfc2822()));
+  if let Some(url) = self_url {
+    xml.push_str(&format!("<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>", escape_html(&url)));
+  }
+  for item in items {
+    xml.push_str("<item>");
+    xml.push_str(&format!("<title>{}</title>", escape_html(&item.title)));
+    xml.push_str(&format!("<link>{0}</link><guid isPermaLink=\"true\">{0}</guid>", escape_html(&item.url)));
+    xml.push_str(&format!("<pubDate>{}</pubDate>", utc(item).to_rfc2822()));
+    for category in categories(item) {
+      xml.push_str(&format!("<category>{}</category>", escape_html(&category)));
+    }
+    xml.push_str(&format!("<description>{}</description>", escape_html(&content(item))));
+    if let Some(ref image) = item.image {
+      xml.push_str(&format!("<enclosure url=\"{}\" length=\"0\" type=\"{}\"/>", escape_html(image), image_type(image)));
+    }
+    xml.push_str("</item>");
+  }
+  xml.push_str("</channel></rss>\n");
+  xml
+}
+
+fn atom(items: &[NewsItem], self_url: Option<String>, updated: DateTime<Utc>) -> String {
+  let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+  xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">");
+  xml.push_str(&format!("<title>{}</title><id>{}</id>", TITLE, HOME_PAGE));
+  xml.push_str(&format!("<updated>{}</updated>", updated.to_rfc3339()));
+  xml.push_str(&format!("<link href=\"{}\"/>", HOME_PAGE));
+  if let Some(url) = self_url {
+    xml.push_str(&format!("<link href=\"{}\" rel=\"self\"/>", escape_html(&url)));
+  }
+  xml.push_str("<author><name>Square Enix</name></author>");
+  for item in items {
+    let published = utc(item).to_rfc3339();
+    xml.push_str("<entry>");
+    xml.push_str(&format!("<title>{}</title>", escape_html(&item.title)));
+    xml.push_str(&format!("<id>{0}</id><link href=\"{0}\"/>", escape_html(&item.url)));
+    xml.push_str(&format!("<published>{0}</published><updated>{0}</updated>", published));
+    for category in categories(item) {
+      xml.push_str(&format!("<category term=\"{}\"/>", escape_html(&category)));
+    }
+    if let Some(ref image) = item.image {
+      xml.push_str(&format!("<link rel=\"enclosure\" href=\"{}\" type=\"{}\"/>", escape_html(image), image_type(image)));
+    }
+    xml.push_str(&format!("<content type=\"html\">{}</content>", escape_html(&content(item))));
+    xml.push_str("</entry>");
+  }
+  xml.push_str("</feed>\n");
+  xml
+}
+
+fn json(items: &[NewsItem], self_url: Option<String>) -> String {
+  let items: Vec<serde_json::Value> = items.iter()
+    .map(|item| {
+      let mut json = serde_json::json!({
+        "id": item.url,
+        "url": item.url,
+        "title": item.title,
+        "content_html": content(item),
+        "date_published": utc(item).to_rfc3339(),
+        "tags": categories(item),
+      });
+      if let Some(ref description) = item.description {
+        json["summary"] = description.as_str().into();
+      }
+      if let Some(ref image) = item.image {
+        json["image"] = image.as_str().into();
+        json["attachments"] = serde_json::json!([{
+          "url": image,
+          "mime_type": image_type(image),
+        }]);
+      }
+      json
+    })
+    .collect();
+  let mut feed = serde_json::json!({
+    "version": "https://jsonfeed.org/version/1.1",
+    "title": TITLE,
+    "home_page_url": HOME_PAGE,
+    "items": items,
+  });
+  if let Some(url) = self_url {
+    feed["feed_url"] = url.into();
+  }
+  feed.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn query(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect()
+  }
+
+  fn items() -> Vec<NewsItem> {
+    let mut first = NewsItem::example(2, "Maintenance & <Updates>");
+    first.kind = NewsKind::SpecialNotice;
+    first.tag = Some("Maintenance".to_string());
+    first.image = Some("https://example.com/banner.PNG".to_string());
+    first.description = Some("See [the notes](https://example.com/?a=1&b=2).\nThanks".to_string());
+    let second = NewsItem::example(1, "Plain");
+    vec![first, second]
+  }
+
+  #[test]
+  fn documented_kinds_are_accepted() {
+    let filter = FeedFilter::from_query(&query(&[("kind", "news,topic"), ("kind", "special_notice")])).unwrap();
+    assert_eq!(filter.kinds, vec![NewsKind::News, NewsKind::Topic, NewsKind::SpecialNotice]);
+    for kind in &[NewsKind::News, NewsKind::Topic, NewsKind::SpecialNotice] {
+      assert_eq!(kind.as_str().parse::<NewsKind>().unwrap(), *kind);
+    }
+    assert!(FeedFilter::from_query(&query(&[("kind", "nonsense")])).is_err());
+    assert!(FeedFilter::from_query(&query(&[("limit", "many")])).is_err());
+  }
+
+  #[test]
+  fn limit_is_clamped() {
+    let limit = |l: Option<i64>| FeedFilter { limit: l, ..Default::default() }.limit();
+    assert_eq!(limit(None), DEFAULT_LIMIT);
+    assert_eq!(limit(Some(0)), 1);
+    assert_eq!(limit(Some(10_000)), MAX_LIMIT);
+  }
+
+  #[test]
+  fn content_renders_description_as_html() {
+    assert_eq!(
+      content(&items()[0]),
+      "<p><img src=\"https://example.com/banner.PNG\" alt=\"Maintenance &amp; &lt;Updates&gt;\"></p>\
+       <p>See <a href=\"https://example.com/?a=1&amp;b=2\">the notes</a>.<br>Thanks</p>",
+    );
+  }
+
+  #[test]
+  fn rss_escapes_and_lists_items() {
+    let updated = utc(&items()[0]);
+    let xml = rss(&items(), Some("https://example.com/feed.rss".to_string()), updated);
+    assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\""));
+    assert!(xml.contains("<atom:link href=\"https://example.com/feed.rss\" rel=\"self\" type=\"application/rss+xml\"/>"));
+    assert!(xml.contains("<title>Maintenance &amp; &lt;Updates&gt;</title>"));
+    assert!(xml.contains("<pubDate>Fri, 14 Jul 2017 02:40:00 +0000</pubDate>"));
+    assert!(xml.contains("<category>Special notice</category><category>Maintenance</category>"));
+    assert!(xml.contains("<enclosure url=\"https://example.com/banner.PNG\" length=\"0\" type=\"image/png\"/>"));
+    // the html content is escaped once more inside the description
+    assert!(xml.contains("&lt;a href=&quot;https://example.com/?a=1&amp;amp;b=2&quot;&gt;the notes&lt;/a&gt;"));
+    assert_eq!(xml.matches("<item>").count(), 2);
+    assert!(xml.ends_with("</channel></rss>\n"));
+  }
+
+  #[test]
+  fn atom_entries_have_ids_and_dates() {
+    let updated = utc(&items()[0]);
+    let xml = atom(&items(), None, updated);
+    assert!(xml.contains("<updated>2017-07-14T02:40:00+00:00</updated>"));
+    assert!(!xml.contains("rel=\"self\""));
+    assert!(xml.contains(
+      "<id>https://na.finalfantasyxiv.com/lodestone/news/detail/1</id>\
+       <link href=\"https://na.finalfantasyxiv.com/lodestone/news/detail/1\"/>"
+    ));
+    assert!(xml.contains("<link rel=\"enclosure\" href=\"https://example.com/banner.PNG\" type=\"image/png\"/>"));
+    assert_eq!(xml.matches("<entry>").count(), 2);
+  }
+
+  #[test]
+  fn json_feed_items() {
+    let feed: serde_json::Value = serde_json::from_str(&json(&items(), Some("https://example.com/feed.json".to_string()))).unwrap();
+    assert_eq!(feed["version"], "https://jsonfeed.org/version/1.1");
+    assert_eq!(feed["feed_url"], "https://example.com/feed.json");
+    let first = &feed["items"][0];
+    assert_eq!(first["title"], "Maintenance & <Updates>");
+    assert_eq!(first["tags"], serde_json::json!(["Special notice", "Maintenance"]));
+    assert_eq!(first["attachments"][0]["mime_type"], "image/png");
+    assert_eq!(first["date_published"], "2017-07-14T02:40:00+00:00");
+    let second = &feed["items"][1];
+    assert!(second.get("summary").is_none());
+    assert!(second.get("image").is_none());
+  }
+}
@@ -0,0 +1,85 @@
+use chrono::{DateTime, Utc};
+
+use diesel::prelude::*;
+
+use crate::errors::*;
+
+use std::{
+  collections::BTreeMap,
+  env,
+  sync::Mutex,
+};
+
+pub const SCRAPER: &str = "scraper";
+pub const SENDER: &str = "sender";
+
+// a little over six missed scrapes
+const DEFAULT_MAX_SCRAPE_AGE: i64 = 900;
+
+#[derive(Debug, Clone, Default)]
+pub struct ThreadStatus {
+  pub alive: bool,
+  pub last_success: Option<DateTime<Utc>>,
+}
+
+lazy_static! {
+  static ref STARTED: DateTime<Utc> = Utc::now();
+  static ref THREADS: Mutex<BTreeMap<&'static str, ThreadStatus>> = Mutex::new(BTreeMap::new());
+}
+
+/// Marks a thread as alive until the guard is dropped, which also happens if the thread panics.
+pub struct Alive(&'static str);
+
+impl Drop for Alive {
+  fn drop(&mut self) {
+    if let Some(status) = THREADS.lock().unwrap().get_mut(self.0) {
+      status.alive = false;
+    }
+  }
+}
+
+pub fn alive(thread: &'static str) -> Alive {
+  lazy_static::initialize(&STARTED);
+  THREADS.lock().unwrap().entry(thread).or_default().alive = true;
+  Alive(thread)
+}
+
+pub fn succeeded(thread: &'static str) {
+  THREADS.lock().unwrap().entry(thread).or_default().last_success = Some(Utc::now());
+}
+
+pub fn threads() -> BTreeMap<&'static str, ThreadStatus> {
+  THREADS.lock().unwrap().clone()
+}
+
+/// How many seconds a scrape may be overdue before the process counts as unhealthy.
+pub fn max_scrape_age() -> i64 {
+  env::var("LN_MAX_SCRAPE_AGE").ok()
+    .and_then(|s| s.parse().ok())
+    .unwrap_or(DEFAULT_MAX_SCRAPE_AGE)
+}
+
+/// Seconds since the last successful scrape, or since starting if there hasn't been one.
+pub fn scrape_age() -> i64 {
+  let last = THREADS.lock().unwrap().get(SCRAPER)
+    .and_then(|s| s.last_success)
+    .unwrap_or(*STARTED);
+  (Utc::now() - last).num_seconds()
+}
+
+/// Checks that every table can be read with the columns this build expects.
+pub fn check_schema() -> Result<()> {
+  crate::CONNECTION.with(|c| -> QueryResult<()> {
+    use crate::database::schema::*;
+    admin_tokens::table.limit(0).execute(c)?;
+    email_subscribers::table.limit(0).execute(c)?;
+    news_items::table.limit(0).execute(c)?;
+    reminders::table.limit(0).execute(c)?;
+    scheduled_events::table.limit(0).execute(c)?;
+    send_failures::table.limit(0).execute(c)?;
+    send_records::table.limit(0).execute(c)?;
+    servers::table.limit(0).execute(c)?;
+    Ok(())
+  })
+  .map_err(|e| format!("database doesn't match the schema this version expects: {}", e).into())
+}
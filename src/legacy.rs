@@ -0,0 +1,232 @@
+use chrono::NaiveDateTime;
+
+use diesel::{
+  prelude::*,
+  insert_into,
+  insert_or_ignore_into,
+};
+
+use serde_derive::Deserialize;
+
+use crate::{
+  database::models::{
+    news_item::{NewNewsItem, NewsKind},
+    send_record::NewSendRecord,
+  },
+  errors::*,
+};
+
+use std::{
+  collections::{BTreeMap, HashSet},
+  fs::File,
+  io::BufReader,
+  path::Path,
+};
+
+// sqlite allows at most 999 parameters in a query, so ids are looked up in chunks
+const CHUNK: usize = 500;
+
+/// The state file written by the old version of the bot, `lodestone_news.json`.
+#[derive(Debug, Deserialize)]
+struct LegacyFile {
+  items: BTreeMap<String, LegacyItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LegacyItem {
+  title: String,
+  url: String,
+  kind: String,
+  time: i64,
+  tag: Option<String>,
+}
+
+/// What importing a legacy file would change.
+#[derive(Debug, Default)]
+pub struct Import {
+  /// Items not in the database yet, oldest first.
+  pub new: Vec<NewNewsItem>,
+  /// Items skipped because an item with the same Lodestone id is already in the database.
+  pub existing: usize,
+  /// Entries that couldn't be read, with the reason.
+  pub invalid: Vec<(String, String)>,
+  /// Destinations the new items will be recorded as sent to.
+  pub servers: Vec<i32>,
+}
+
+impl Import {
+  /// Reads a legacy file and compares it with the database, without changing anything.
+  pub fn plan<P: AsRef<Path>>(path: P) -> Result<Import> {
+    let path = path.as_ref();
+    let file = File::open(path).chain_err(|| format!("could not open {}", path.display()))?;
+    let legacy: LegacyFile = serde_json::from_reader(BufReader::new(file))
+      .chain_err(|| format!("could not read {}", path.display()))?;
+
+    let mut import = Import::default();
+    let mut items = Vec::with_capacity(legacy.items.len());
+    for (key, item) in legacy.items {
+      match convert(item) {
+        Ok(i) => items.push(i),
+        Err(e) => import.invalid.push((key, e)),
+      }
+    }
+    items.sort_by_key(|i| i.created);
+
+    let ids: Vec<&str> = items.iter().map(|i| i.lodestone_id.as_str()).collect();
+    let (existing, servers) = crate::CONNECTION.with(|c| -> QueryResult<_> {
+      use crate::database::schema::{news_items, servers};
+      let mut existing: Vec<String> = Vec::with_capacity(ids.len());
+      for chunk in ids.chunks(CHUNK) {
+        existing.extend(news_items::table
+          .select(news_items::lodestone_id)
+          .filter(news_items::lodestone_id.eq_any(chunk))
+          .load::<String>(c)?);
+      }
+      let servers: Vec<i32> = servers::table.select(servers::id).order(servers::id).load(c)?;
+      Ok((existing, servers))
+    })
+    .chain_err(|| "could not load existing items")?;
+    import.servers = servers;
+
+    // the old bot keyed items by url, so the same item can appear more than once
+    let mut seen: HashSet<String> = existing.into_iter().collect();
+    for item in items {
+      if seen.insert(item.lodestone_id.clone()) {
+        import.new.push(item);
+      } else {
+        import.existing += 1;
+      }
+    }
+
+    Ok(import)
+  }
+
+  /// Inserts the new items and records them as sent to every destination, returning the number of
+  /// send records added.
+  ///
+  /// The old bot sent every item it knew about, so none of them are sent again.
+  pub fn apply(&self) -> Result<usize> {
+    crate::CONNECTION.with(|c| {
+      use crate::database::schema::{news_items, send_records};
+      c.transaction(|| -> QueryResult<usize> {
+        insert_into(news_items::table).values(&self.new).execute(c)?;
+        let ids: Vec<&str> = self.new.iter().map(|i| i.lodestone_id.as_str()).collect();
+        let mut news_ids: Vec<i32> = Vec::with_capacity(ids.len());
+        for chunk in ids.chunks(CHUNK) {
+          news_ids.extend(news_items::table
+            .select(news_items::id)
+            .filter(news_items::lodestone_id.eq_any(chunk))
+            .load::<i32>(c)?);
+        }
+        let records: Vec<NewSendRecord> = self.servers.iter()
+          .flat_map(|&server_id| news_ids.iter().map(move |&news_id| NewSendRecord {
+            server_id,
+            news_id,
+            remote_id: None,
+          }))
+          .collect();
+        insert_or_ignore_into(send_records::table).values(&records).execute(c)
+      })
+      .chain_err(|| "could not import items")
+    })
+  }
+}
+
+fn convert(item: LegacyItem) -> std::result::Result<NewNewsItem, String> {
+  let kind: NewsKind = item.kind.parse().map_err(|_| format!("unknown kind {}", item.kind))?;
+  let lodestone_id = match item.url.trim_end_matches('/').rsplit('/').next() {
+    Some(id) if !id.is_empty() => id.to_string(),
+    _ => return Err(format!("no Lodestone id in url {}", item.url)),
+  };
+  let created = NaiveDateTime::from_timestamp_opt(item.time, 0).ok_or_else(|| format!("invalid time {}", item.time))?;
+  Ok(NewNewsItem {
+    title: item.title,
+    url: item.url,
+    description: None,
+    fields: None,
+    image: None,
+    lodestone_id,
+    kind,
+    created,
+    tag: item.tag,
+    maintenance_start: None,
+    maintenance_end: None,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::database::models::{news_item::NewsItem, server::NewServer};
+  use std::{env, fs};
+
+  fn legacy(kind: &str, url: &str) -> LegacyItem {
+    LegacyItem {
+      title: "Title".into(),
+      url: url.into(),
+      kind: kind.into(),
+      time: 1_500_000_000,
+      tag: Some("Maintenance".into()),
+    }
+  }
+
+  #[test]
+  fn convert_reads_kinds_and_ids() {
+    let url = "https://na.finalfantasyxiv.com/lodestone/news/detail/4f1c0e8d";
+    let item = convert(legacy("SpecialNotice", url)).unwrap();
+    assert_eq!(item.kind, NewsKind::SpecialNotice);
+    assert_eq!(item.lodestone_id, "4f1c0e8d");
+    assert_eq!(item.url, url);
+    assert_eq!(item.created, NaiveDateTime::from_timestamp(1_500_000_000, 0));
+    assert_eq!(item.tag, Some("Maintenance".into()));
+
+    assert_eq!(convert(legacy("News", url)).unwrap().kind, NewsKind::News);
+    let topic = convert(legacy("Topic", "https://na.finalfantasyxiv.com/lodestone/topics/detail/9a2b/")).unwrap();
+    assert_eq!(topic.kind, NewsKind::Topic);
+    assert_eq!(topic.lodestone_id, "9a2b");
+  }
+
+  #[test]
+  fn convert_refuses_bad_entries() {
+    assert_eq!(convert(legacy("Gossip", "https://example.com/1")).err(), Some("unknown kind Gossip".into()));
+    assert_eq!(convert(legacy("News", "")).err(), Some("no Lodestone id in url ".into()));
+    let mut item = legacy("News", "https://example.com/1");
+    item.time = 1 << 62;
+    assert!(convert(item).is_err());
+  }
+
+  #[test]
+  fn large_files_are_imported_in_chunks() {
+    // enough items for several chunks, with one already in the database
+    crate::database::test_database();
+    let items: BTreeMap<String, serde_json::Value> = (0..1200)
+      .map(|i| {
+        let url = format!("https://na.finalfantasyxiv.com/lodestone/news/detail/{}", i);
+        (url.clone(), serde_json::json!({
+          "title": format!("Item {}", i),
+          "url": url,
+          "kind": "News",
+          "time": 1_500_000_000 + i,
+          "tag": null,
+        }))
+      })
+      .collect();
+    let path = env::temp_dir().join(format!("lodestone_news_legacy_{}.json", std::process::id()));
+    fs::write(&path, serde_json::json!({ "items": items }).to_string()).unwrap();
+
+    crate::CONNECTION.with(|c| {
+      use crate::database::schema::{news_items, servers};
+      insert_into(servers::table).values(&NewServer::new("Test", "https://discord.com/api/webhooks/1/abc")).execute(c).unwrap();
+      insert_into(news_items::table).values(&NewsItem::example(7, "Already here").into_new()).execute(c).unwrap();
+    });
+
+    let import = Import::plan(&path).unwrap();
+    assert_eq!((import.new.len(), import.existing, import.invalid.len()), (1199, 1, 0));
+    assert_eq!(import.servers.len(), 1);
+    assert_eq!(import.apply().unwrap(), 1199);
+
+    let again = Import::plan(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!((again.new.len(), again.existing), (0, 1200));
+  }
+}
@@ -1,8 +1,9 @@
-use chrono::NaiveDateTime;
+use chrono::{Duration, NaiveDateTime, Utc};
 
 use diesel::{
   dsl::count,
   prelude::*,
+  update,
 };
 
 use reqwest::Client;
@@ -12,14 +13,22 @@ use scraper::{Html, Selector, ElementRef};
 use serde_derive::{Deserialize, Serialize};
 
 use crate::{
-  database::models::news_item::{NewsKind, NewNewsItem},
+  database::models::news_item::{NewsItem, NewsKind, NewNewsItem},
   errors::*,
   iter::NewsText,
+  maintenance,
+  metrics,
 };
 
-use std::io::Read;
+use std::{
+  collections::HashMap,
+  io::Read,
+  time::Instant,
+};
 
 const NEWS_URL: &str = "https://na.finalfantasyxiv.com/lodestone/news/";
+// how long maintenance articles are checked for changed times
+const MAINTENANCE_REFRESH_DAYS: i64 = 7;
 
 pub struct NewsScraper {
   client: Client
@@ -39,9 +48,91 @@ impl NewsScraper {
   }
 
   pub fn update_news(&self) -> Result<()> {
+    metrics::inc(&metrics::SCRAPES, &[]);
+    let news = match self.scrape() {
+      Ok(n) => n,
+      Err(e) => {
+        metrics::inc(&metrics::SCRAPE_FAILURES, &[]);
+        return Err(e);
+      },
+    };
+    metrics::set(&metrics::LAST_SCRAPE, &[], Utc::now().timestamp() as f64);
+    self.refresh_maintenance(&self.parse_titles(&news))
+  }
+
+  /// Downloads the news page and inserts any new items, returning the page.
+  fn scrape(&self) -> Result<String> {
     let news = self.download_news()?;
     let parsed = self.parse_news(&news);
-    NewsScraper::insert_new_news(parsed)
+    NewsScraper::insert_new_news(parsed)?;
+    Ok(news)
+  }
+
+  /// Downloads the news page and returns the items that aren't in the database yet, without
+  /// inserting them.
+  pub fn new_news(&self) -> Result<Vec<NewNewsItem>> {
+    let news = self.download_news()?;
+    Ok(self.parse_news(&news))
+  }
+
+  /// Downloads maintenance articles again to pick up changed times.
+  ///
+  /// Only articles whose title or tag on the news page (`listed`, by Lodestone id) has changed
+  /// since they were collected are downloaded. Articles whose times can't be read are not
+  /// downloaded again until then.
+  pub fn refresh_maintenance(&self, listed: &HashMap<String, (String, Option<String>)>) -> Result<()> {
+    let now = Utc::now().naive_utc();
+    let items: Vec<NewsItem> = crate::CONNECTION.with(|c| {
+      use crate::database::schema::news_items;
+      news_items::table
+        .filter(news_items::kind.ne(NewsKind::Topic))
+        .filter(news_items::created.gt(now - Duration::days(MAINTENANCE_REFRESH_DAYS)))
+        .filter(news_items::maintenance_end.is_null().or(news_items::maintenance_end.gt(now)))
+        .load(c)
+        .chain_err(|| "could not load maintenance items")
+    })?;
+
+    for item in items {
+      let (title, tag) = match listed.get(&item.lodestone_id) {
+        Some((title, tag)) => (title.clone(), tag.clone()),
+        None => (item.title.clone(), item.tag.clone()),
+      };
+      if title == item.title && tag == item.tag {
+        continue;
+      }
+      if !maintenance::is_maintenance(&title, tag.as_ref().map(String::as_str)) {
+        continue;
+      }
+
+      let (description, fields) = match self.parse_news_fields(&item.url) {
+        Ok(x) => x,
+        Err(e) => {
+          warn!("could not refresh fields for {}: {}", item.id, e);
+          continue;
+        },
+      };
+      let window = maintenance::parse_window(&fields);
+      let fields = serde_json::to_string(&fields).chain_err(|| "could not serialize fields")?;
+      let (start, end) = (window.map(|w| w.0), window.map(|w| w.1));
+
+      info!("Maintenance details for {} ({}) changed", item.title, item.id);
+      crate::CONNECTION.with(|c| {
+        use crate::database::schema::news_items;
+        update(&item)
+          .set((
+            news_items::title.eq(title),
+            news_items::tag.eq(tag),
+            news_items::description.eq(description),
+            news_items::fields.eq(fields),
+            news_items::maintenance_start.eq(start),
+            news_items::maintenance_end.eq(end),
+          ))
+          .execute(c)
+          .chain_err(|| "could not update maintenance details")
+      })?;
+    }
+
+    Ok(())
   }
 
   pub fn insert_new_news(items: Vec<NewNewsItem>) -> Result<()> {
@@ -69,9 +160,46 @@ impl NewsScraper {
         .chain_err(|| "could not insert new items")
     })?;
     info!("Added {} new item{}", new_items.len(), if new_items.len() == 1 { "" } else { "s" });
+    crate::broadcast::publish();
     Ok(())
   }
 
+  /// Splits a news or special notice title into its text and its `[Tag]`, if it has one.
+  fn title_and_tag(title: ElementRef) -> (String, Option<String>) {
+    let tag: Option<String> = title.first_child()
+      .and_then(ElementRef::wrap)
+      .map(|c| c.text().collect::<String>())
+      .map(|tag| tag[1..tag.len() - 1].to_string());
+
+    let text_iter = title.text();
+    let title: String = if tag.is_some() {
+      text_iter.skip(1).collect()
+    } else {
+      text_iter.collect()
+    };
+    (title, tag)
+  }
+
+  /// The title and tag of every news item and special notice on the news page, by Lodestone id,
+  /// without downloading any articles.
+  pub fn parse_titles(&self, news: &str) -> HashMap<String, (String, Option<String>)> {
+    let html = Html::parse_document(news);
+    let selector = Selector::parse(
+      "div.news__content.parts__space--add > ul:nth-of-type(1) > li, div.news__content.parts__space--add > ul:nth-of-type(2) > li",
+    ).unwrap();
+    let title_selector = Selector::parse("p.news__list--title").unwrap();
+    html.select(&selector)
+      .filter_map(|li| {
+        let id = li.first_child()
+          .and_then(|v| v.value().as_element())
+          .and_then(|e| e.attr("href"))
+          .and_then(|h| h.rsplit('/').next())?;
+        let (title, tag) = NewsScraper::title_and_tag(li.select(&title_selector).next()?);
+        Some((id.to_string(), (title.trim().to_string(), tag.map(|t| t.trim().to_string()))))
+      })
+      .collect()
+  }
+
   pub fn download_news(&self) -> Result<String> {
     info!("Downloading news");
     let mut response = self.client.get(NEWS_URL).send().chain_err(|| "could not download news")?;
@@ -108,6 +236,7 @@ impl NewsScraper {
         Some(c) => c,
         None => {
           warn!("could not get news item child");
+          metrics::inc(&metrics::PARSE_WARNINGS, &[("reason", "missing_child")]);
           continue;
         },
       };
@@ -116,6 +245,7 @@ impl NewsScraper {
         Some(h) => h,
         None => {
           warn!("invalid link in news item");
+          metrics::inc(&metrics::PARSE_WARNINGS, &[("reason", "invalid_link")]);
           continue;
         },
       };
@@ -126,6 +256,7 @@ impl NewsScraper {
         Some(i) => i,
         None => {
           warn!("invalid href in news item");
+          metrics::inc(&metrics::PARSE_WARNINGS, &[("reason", "invalid_href")]);
           continue;
         },
       };
@@ -142,50 +273,50 @@ impl NewsScraper {
         Ok(i) if i > 0 => continue,
         Err(e) => {
           warn!("could not check if id was in database: {}", e);
+          metrics::inc(&metrics::PARSE_WARNINGS, &[("reason", "database")]);
           continue;
         },
         _ => {},
       }
 
-      let (title, tag, image, description, fields) = match kind {
+      let (title, tag, image, description, fields, window) = match kind {
         NewsKind::News | NewsKind::SpecialNotice => {
           let title = match li.select(&title_selector).next() {
             Some(t) => t,
             None => {
               warn!("missing title in news item");
+              metrics::inc(&metrics::PARSE_WARNINGS, &[("reason", "missing_title")]);
               continue;
             },
           };
 
-          let tag: Option<String> = title.first_child()
-            .and_then(ElementRef::wrap)
-            .map(|c| c.text().collect::<String>())
-            .map(|tag| tag[1..tag.len() - 1].to_string());
-
-          let text_iter = title.text();
-          let title: String = if tag.is_some() {
-            text_iter.skip(1).collect()
-          } else {
-            text_iter.collect()
-          };
+          let (title, tag) = NewsScraper::title_and_tag(title);
 
           let (desc, fields) = match self.parse_news_fields(&url) {
             Ok(x) => x,
             Err(e) => {
               warn!("could not parse fields: {}", e);
+              metrics::inc(&metrics::PARSE_WARNINGS, &[("reason", "fields")]);
               continue;
             },
           };
 
+          let window = if maintenance::is_maintenance(&title, tag.as_ref().map(String::as_str)) {
+            maintenance::parse_window(&fields)
+          } else {
+            None
+          };
+
           let fields = match serde_json::to_string(&fields).chain_err(|| "could not serialize") {
             Ok(f) => f,
             Err(e) => {
               warn!("could not parse/serialize fields: {}", e);
+              metrics::inc(&metrics::PARSE_WARNINGS, &[("reason", "serialize_fields")]);
               continue;
             },
           };
 
-          (title, tag, None, desc, Some(fields))
+          (title, tag, None, desc, Some(fields), window)
         },
         NewsKind::Topic => {
           let text = li.select(&title_selector).next()
@@ -198,9 +329,10 @@ impl NewsScraper {
           let description = li.select(&second_para_selector).next()
             .map(|v| NewsText::new(v.traverse(), " ").collect());
           match text {
-            Some(t) => (t, None, image, description, None),
+            Some(t) => (t, None, image, description, None, None),
             None => {
               warn!("invalid topic/special notice: no title");
+              metrics::inc(&metrics::PARSE_WARNINGS, &[("reason", "missing_topic_title")]);
               continue;
             },
           }
@@ -211,6 +343,7 @@ impl NewsScraper {
         Some(ts) => ts,
         None => {
           warn!("news item missing time script");
+          metrics::inc(&metrics::PARSE_WARNINGS, &[("reason", "missing_time")]);
           continue
         },
       };
@@ -220,6 +353,7 @@ impl NewsScraper {
         Some(time) => time,
         None => {
           warn!("invalid script in news item");
+          metrics::inc(&metrics::PARSE_WARNINGS, &[("reason", "invalid_time_script")]);
           continue;
         },
       };
@@ -227,6 +361,7 @@ impl NewsScraper {
         Ok(t) => t,
         Err(_) => {
           warn!("invalid time in time script");
+          metrics::inc(&metrics::PARSE_WARNINGS, &[("reason", "invalid_time")]);
           continue;
         },
       };
@@ -242,7 +377,10 @@ impl NewsScraper {
         kind,
         created: datetime,
         tag: tag.map(|x| x.trim().to_string()),
+        maintenance_start: window.map(|w| w.0),
+        maintenance_end: window.map(|w| w.1),
       };
+      metrics::inc(&metrics::ITEMS_PARSED, &[("kind", kind.as_str())]);
       items.push(news_item);
     }
 
@@ -250,9 +388,10 @@ impl NewsScraper {
   }
 
   fn parse_news_fields(&self, url: &str) -> Result<(Option<String>, Vec<Field>)> {
-    let mut response = self.client.get(url).send().chain_err(|| "could not download news item")?;
-    let mut content = String::new();
-    response.read_to_string(&mut content).chain_err(|| "could not read news item")?;
+    let start = Instant::now();
+    let content = self.download_news_item(url);
+    metrics::observe(&metrics::DETAIL_FETCH, &[], start.elapsed());
+    let content = content?;
 
     let detail_selector = Selector::parse("div.news__detail__wrapper").unwrap();
 
@@ -287,6 +426,13 @@ impl NewsScraper {
     }
     Ok((desc, fields))
   }
+
+  fn download_news_item(&self, url: &str) -> Result<String> {
+    let mut response = self.client.get(url).send().chain_err(|| "could not download news item")?;
+    let mut content = String::new();
+    response.read_to_string(&mut content).chain_err(|| "could not read news item")?;
+    Ok(content)
+  }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -294,3 +440,74 @@ pub struct Field {
   pub name: String,
   pub value: String,
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const NEWS: &str = r#"<html><body><div class="news__content parts__space--add">
+    <ul>
+      <li><a href="/lodestone/news/detail/notice1"><p class="news__list--title"><span>[Maintenance]</span> All Worlds Maintenance (Oct. 8)</p></a></li>
+    </ul>
+    <ul>
+      <li><a href="/lodestone/news/detail/news1"><p class="news__list--title">Patch Notes</p></a></li>
+      <li><a href="/lodestone/news/detail/news2"><p class="news__list--title"><span>[Follow-up]</span> Login Issues </p></a></li>
+    </ul>
+    <ul>
+      <li><p class="news__list--title"><a href="/lodestone/topics/detail/topic1">A Topic</a></p></li>
+    </ul>
+  </div></body></html>"#;
+
+  #[test]
+  fn parses_titles_of_news_and_notices() {
+    let titles = NewsScraper::new().parse_titles(NEWS);
+    assert_eq!(titles.len(), 3);
+    assert_eq!(titles["notice1"], ("All Worlds Maintenance (Oct. 8)".to_string(), Some("Maintenance".to_string())));
+    assert_eq!(titles["news1"], ("Patch Notes".to_string(), None));
+    assert_eq!(titles["news2"], ("Login Issues".to_string(), Some("Follow-up".to_string())));
+  }
+
+  #[test]
+  fn refreshes_maintenance_only_when_the_listing_changes() {
+    crate::database::test_database();
+
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    // an article without any times in it
+    let item = NewNewsItem {
+      url: format!("http://{}/lodestone/news/detail/1", server.server_addr()),
+      kind: NewsKind::SpecialNotice,
+      tag: Some("Maintenance".into()),
+      created: Utc::now().naive_utc(),
+      ..NewsItem::example(1, "All Worlds Maintenance").into_new()
+    };
+    crate::CONNECTION.with(|c| {
+      use crate::database::schema::news_items;
+      diesel::insert_into(news_items::table).values(&item).execute(c).unwrap();
+    });
+    let listing = |title: &str| {
+      let mut listed = HashMap::new();
+      listed.insert("1".to_string(), (title.to_string(), Some("Maintenance".to_string())));
+      listed
+    };
+    let scraper = NewsScraper::new();
+
+    scraper.refresh_maintenance(&listing("All Worlds Maintenance")).unwrap();
+    assert!(server.try_recv().unwrap().is_none(), "an unchanged article was downloaded again");
+
+    let responder = std::thread::spawn(move || {
+      let request = server.recv().unwrap();
+      let body = r#"<html><body><div class="news__detail__wrapper">Now with a new title.</div></body></html>"#;
+      request.respond(tiny_http::Response::from_string(body)).unwrap();
+    });
+    scraper.refresh_maintenance(&listing("All Worlds Maintenance (Extended)")).unwrap();
+    responder.join().unwrap();
+
+    let refreshed: NewsItem = crate::CONNECTION.with(|c| {
+      use crate::database::schema::news_items;
+      news_items::table.find(1).first(c).unwrap()
+    });
+    assert_eq!(refreshed.title, "All Worlds Maintenance (Extended)");
+    assert_eq!(refreshed.description, Some("Now with a new title.".to_string()));
+    assert_eq!(refreshed.maintenance_start, None);
+  }
+}
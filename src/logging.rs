@@ -42,7 +42,7 @@ fn colored_target(target: &str) -> String {
   colored.join("")
 }
 
-pub fn init_logger() -> Result<()> {
+pub fn init_logger(level: LevelFilter, stdout: bool) -> Result<()> {
   fern::Dispatch::new()
     .format(|out, message, record| {
       out.finish(format_args!(
@@ -53,8 +53,8 @@ pub fn init_logger() -> Result<()> {
         message,
       ))
     })
-    .level(if var("LN_DEBUG").is_ok() { LevelFilter::Debug } else { LevelFilter::Info })
-    .chain(io::stdout())
+    .level(if var("LN_DEBUG").is_ok() { LevelFilter::Debug } else { level })
+    .chain(if stdout { fern::Output::from(io::stdout()) } else { fern::Output::from(io::stderr()) })
     .apply()
     .chain_err(|| "could not set up logger")
 }
@@ -3,6 +3,7 @@
 #![recursion_limit = "1024"]
 
 #[macro_use] extern crate diesel;
+#[macro_use] extern crate lazy_static;
 #[macro_use] extern crate log;
 
 use diesel::{
@@ -15,6 +16,8 @@ use crossbeam_channel as chan;
 
 use chrono::Duration;
 
+use log::LevelFilter;
+
 use signal_hook::iterator::Signals;
 
 use self::errors::*;
@@ -22,33 +25,98 @@ use self::errors::*;
 use std::env;
 
 pub mod iter;
+pub mod broadcast;
+pub mod cli;
 pub mod database;
 pub mod lodestone;
 pub mod discord;
+pub mod digest;
 pub mod errors;
+pub mod export;
+pub mod feed;
+pub mod health;
+pub mod legacy;
 pub mod logging;
+pub mod maintenance;
+pub mod metrics;
+pub mod quiet;
+pub mod reminders;
+pub mod sender;
+pub mod sink;
+pub mod validation;
+pub mod web;
 
 thread_local! {
   pub static CONNECTION: SqliteConnection = {
     let location = env::var("LN_DATABASE_LOCATION").unwrap();
     let connection = SqliteConnection::establish(&location)
       .chain_err(|| format!("could not connect to sqlite database at {}", location)).unwrap();
-    connection.batch_execute("PRAGMA foreign_keys = ON;").chain_err(|| "could not enable foreign keys").unwrap();
+    // every web request runs on its own thread and connection, so writers wait for each other
+    // instead of failing, and readers don't block them at all
+    connection.batch_execute("PRAGMA foreign_keys = ON; PRAGMA busy_timeout = 5000; PRAGMA journal_mode = WAL;")
+      .chain_err(|| "could not configure database connection").unwrap();
     connection
   };
 }
 
 fn main() {
-  logging::init_logger().expect("Could not initialize logger");
+  let args: Vec<String> = env::args().skip(1).collect();
+  // other commands print their own output, so only warnings are logged, to stderr
+  let running = args.is_empty() || args[0] == "run";
+  let dry_run = args.iter().any(|a| a == "--dry-run");
+  logging::init_logger(
+    if running { LevelFilter::Info } else { LevelFilter::Warn },
+    running && !dry_run,
+  ).expect("Could not initialize logger");
 
   info!("Loading .env");
 
   dotenv::dotenv().ok();
 
+  if let Err(e) = cli::run(&args) {
+    eprintln!("error: {}", e);
+    for cause in e.iter().skip(1) {
+      eprintln!("caused by: {}", cause);
+    }
+    std::process::exit(1);
+  }
+}
+
+/// Scrapes, sends and syncs once, for running from cron or a timer.
+///
+/// A dry run only reads the database, and prints what would be sent instead of sending it.
+pub fn run_once(dry_run: bool) -> Result<()> {
+  if dry_run {
+    let new = lodestone::NewsScraper::new().new_news()?;
+    return sender::NewsSender::dry_run(new);
+  }
+
+  let mut failed = false;
+  let mut check = |action: &str, result: Result<()>| if let Err(e) = result {
+    warn!("Could not {}: {}", action, e);
+    failed = true;
+  };
+  check("update Lodestone news", lodestone::NewsScraper::new().update_news());
+  if let Ok(dir) = env::var("LN_FEED_DIR") {
+    check("export feeds", feed::export(&dir));
+  }
+  check("send news", sender::NewsSender::new().send_new_news());
+  check("sync Discord scheduled events", discord::events::GuildEventClient::new().sync_events());
+  check("send reminders", reminders::ReminderScheduler::new().run());
+
+  if failed {
+    return Err("not everything could be done, see the log above".into());
+  }
+  Ok(())
+}
+
+/// Runs the bot until it is interrupted.
+pub fn run() {
   info!("Creating channels and tickers");
 
   let ns_tick = chan::tick(Duration::seconds(150).to_std().unwrap());
   let ds_tick = chan::tick(Duration::minutes(5).to_std().unwrap());
+  let rs_tick = chan::tick(Duration::minutes(1).to_std().unwrap());
 
   let mut thread_handles = Vec::new();
 
@@ -69,9 +137,8 @@ fn main() {
         continue;
       }
 
-      for _ in 0..2 {
-        exit_tx.send(()).ok();
-      }
+      // dropping the sender disconnects every receiver, waking all threads
+      drop(exit_tx);
       break;
     }
   }));
@@ -80,10 +147,17 @@ fn main() {
 
   let ns_exit_rx = exit_rx.clone();
   thread_handles.push(std::thread::spawn(move || {
+    let _alive = health::alive(health::SCRAPER);
     let scraper = lodestone::NewsScraper::new();
     loop {
-      if let Err(e) = scraper.update_news() {
-        warn!("Could not update Lodestone news: {}", e);
+      match scraper.update_news() {
+        Ok(()) => health::succeeded(health::SCRAPER),
+        Err(e) => warn!("Could not update Lodestone news: {}", e),
+      }
+      if let Ok(dir) = env::var("LN_FEED_DIR") {
+        if let Err(e) = feed::export(&dir) {
+          warn!("Could not export feeds: {}", e);
+        }
       }
       #[allow(clippy::all)]
       {
@@ -95,10 +169,42 @@ fn main() {
     }
   }));
 
-  info!("Starting Discord thread");
+  if let Ok(address) = env::var("LN_WEB_ADDRESS") {
+    info!("Starting web thread");
+
+    let web_exit_rx = exit_rx.clone();
+    thread_handles.push(std::thread::spawn(move || {
+      if let Err(e) = web::serve(&address, web_exit_rx) {
+        error!("Could not run web server: {}", e);
+      }
+    }));
+  }
+
+  info!("Starting reminder thread");
+
+  let rs_exit_rx = exit_rx.clone();
+  thread_handles.push(std::thread::spawn(move || {
+    let rs = reminders::ReminderScheduler::new();
+    loop {
+      if let Err(e) = rs.run() {
+        warn!("Could not send reminders: {}", e);
+      }
+      #[allow(clippy::all)]
+      {
+        chan::select! {
+          recv(rs_tick) -> _ => {},
+          recv(rs_exit_rx) -> _ => break,
+        }
+      }
+    }
+  }));
+
+  info!("Starting sender thread");
 
   thread_handles.push(std::thread::spawn(move || {
-    let ds = discord::DiscordSender::new();
+    let _alive = health::alive(health::SENDER);
+    let ds = sender::NewsSender::new();
+    let events = discord::events::GuildEventClient::new();
     loop {
       #[allow(clippy::all)]
       {
@@ -107,8 +213,12 @@ fn main() {
           recv(exit_rx) -> _ => break,
         }
       }
-      if let Err(e) = ds.send_new_news() {
-        warn!("Could not send Discord news: {}", e);
+      match ds.send_new_news() {
+        Ok(()) => health::succeeded(health::SENDER),
+        Err(e) => warn!("Could not send news: {}", e),
+      }
+      if let Err(e) = events.sync_events() {
+        warn!("Could not sync Discord scheduled events: {}", e);
       }
     }
   }));
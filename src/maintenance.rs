@@ -0,0 +1,154 @@
+use chrono::{Duration, NaiveDateTime, NaiveTime};
+
+use crate::lodestone::Field;
+
+pub fn is_maintenance(title: &str, tag: Option<&str>) -> bool {
+  tag.map(|t| t.eq_ignore_ascii_case("maintenance")).unwrap_or(false)
+    || title.to_lowercase().contains("maintenance")
+}
+
+/// Finds the maintenance window in an article's fields, returning its start and end in UTC.
+///
+/// The Lodestone writes these as `Oct. 8, 2019 1:00 a.m. to 9:00 a.m. (PDT)`, sometimes with a
+/// second date when the window crosses midnight.
+pub fn parse_window(fields: &[Field]) -> Option<(NaiveDateTime, NaiveDateTime)> {
+  fields.iter()
+    .filter(|f| f.name.to_lowercase().contains("date"))
+    .flat_map(|f| f.value.lines())
+    .filter_map(parse_line)
+    .next()
+}
+
+fn parse_line(line: &str) -> Option<(NaiveDateTime, NaiveDateTime)> {
+  let open = line.rfind('(')?;
+  let close = open + line[open..].find(')')?;
+  let offset = utc_offset(line[open + 1..close].trim())?;
+
+  let range = line[..open]
+    .replace(" from ", " ")
+    .replace("a.m.", "AM")
+    .replace("p.m.", "PM");
+  let mut parts = range.splitn(2, " to ");
+  let start = parse_date_time(parts.next()?.trim())?;
+  let end = parts.next()?.trim();
+  let end = match parse_date_time(end) {
+    Some(e) => e,
+    None => start.date().and_time(NaiveTime::parse_from_str(end, "%I:%M %p").ok()?),
+  };
+  let end = if end <= start { end + Duration::days(1) } else { end };
+
+  let offset = Duration::hours(offset);
+  Some((start - offset, end - offset))
+}
+
+fn parse_date_time(s: &str) -> Option<NaiveDateTime> {
+  let mut words = s.splitn(2, ' ');
+  // months are written as "Oct.", "Sept." or "June", but chrono only reads three letters
+  let month: String = words.next()?.chars().filter(|c| c.is_alphabetic()).take(3).collect();
+  NaiveDateTime::parse_from_str(&format!("{} {}", month, words.next()?), "%b %d, %Y %I:%M %p").ok()
+}
+
+fn utc_offset(zone: &str) -> Option<i64> {
+  let hours = match zone {
+    "PDT" => -7,
+    "PST" => -8,
+    "EDT" => -4,
+    "EST" => -5,
+    "GMT" | "UTC" => 0,
+    "BST" | "CET" => 1,
+    "CEST" => 2,
+    "JST" => 9,
+    _ => return None,
+  };
+  Some(hours)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use chrono::NaiveDate;
+
+  fn at(month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+    NaiveDate::from_ymd(2019, month, day).and_hms(hour, minute, 0)
+  }
+
+  #[test]
+  fn detects_maintenance() {
+    assert!(is_maintenance("All Worlds Maintenance (Oct. 8)", None));
+    assert!(is_maintenance("Something", Some("Maintenance")));
+    assert!(is_maintenance("Something", Some("maintenance")));
+    assert!(!is_maintenance("Patch 5.1 Notes", Some("Important")));
+  }
+
+  #[test]
+  fn parses_a_window_on_one_day() {
+    assert_eq!(
+      parse_line("Oct. 8, 2019 1:00 a.m. to 9:00 a.m. (PDT)"),
+      Some((at(10, 8, 8, 0), at(10, 8, 16, 0))),
+    );
+    assert_eq!(
+      parse_line("Oct. 8, 2019 1:00 a.m. to 9:00 a.m. (JST)"),
+      Some((at(10, 7, 16, 0), at(10, 8, 0, 0))),
+    );
+  }
+
+  #[test]
+  fn parses_long_and_abbreviated_months() {
+    assert_eq!(
+      parse_line("Sept. 3, 2019 2:00 p.m. to 4:30 p.m. (GMT)"),
+      Some((at(9, 3, 14, 0), at(9, 3, 16, 30))),
+    );
+    assert_eq!(
+      parse_line("June 3, 2019 2:00 p.m. to 4:30 p.m. (BST)"),
+      Some((at(6, 3, 13, 0), at(6, 3, 15, 30))),
+    );
+  }
+
+  #[test]
+  fn parses_a_window_crossing_midnight() {
+    // without a second date, an end before the start is on the next day
+    assert_eq!(
+      parse_line("Nov. 4, 2019 11:00 p.m. to 3:00 a.m. (PST)"),
+      Some((at(11, 5, 7, 0), at(11, 5, 11, 0))),
+    );
+    assert_eq!(
+      parse_line("Nov. 4, 2019 11:00 p.m. to Nov. 5, 2019 3:00 a.m. (PST)"),
+      Some((at(11, 5, 7, 0), at(11, 5, 11, 0))),
+    );
+  }
+
+  #[test]
+  fn parses_from() {
+    assert_eq!(
+      parse_line("Oct. 8, 2019 from 1:00 a.m. to 9:00 a.m. (PDT)"),
+      Some((at(10, 8, 8, 0), at(10, 8, 16, 0))),
+    );
+  }
+
+  #[test]
+  fn rejects_incomplete_lines() {
+    for line in &[
+      "",
+      "Oct. 8, 2019 1:00 a.m. to 9:00 a.m.",
+      "Oct. 8, 2019 1:00 a.m. to 9:00 a.m. (XYZ)",
+      "Oct. 8, 2019 1:00 a.m. (PDT)",
+      "Oct. 8, 2019 to 9:00 a.m. (PDT)",
+      "Oct. 8, 2019 1:00 a.m. to sometime (PDT)",
+      "* Completion time is subject to change (PDT)",
+    ] {
+      assert_eq!(parse_line(line), None, "{:?} was parsed", line);
+    }
+  }
+
+  #[test]
+  fn finds_the_window_in_date_fields() {
+    let field = |name: &str, value: &str| Field { name: name.into(), value: value.into() };
+    let fields = vec![
+      field("Affected Service", "All Worlds"),
+      field("Date & Time", "* Times are approximate\nOct. 8, 2019 1:00 a.m. to 9:00 a.m. (PDT)"),
+    ];
+    assert_eq!(parse_window(&fields), Some((at(10, 8, 8, 0), at(10, 8, 16, 0))));
+    assert_eq!(parse_window(&fields[..1]), None);
+  }
+}
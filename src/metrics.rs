@@ -0,0 +1,319 @@
+use chrono::Utc;
+
+use diesel::{
+  dsl::sql,
+  prelude::*,
+  sql_types::{BigInt, Integer},
+};
+
+use crate::errors::*;
+
+use std::{
+  collections::BTreeMap,
+  fmt::Write,
+  sync::Mutex,
+  time::Duration,
+};
+
+pub struct Metric {
+  pub name: &'static str,
+  pub help: &'static str,
+  pub kind: MetricKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetricKind {
+  Counter,
+  Gauge,
+  Histogram,
+}
+
+impl MetricKind {
+  fn as_str(self) -> &'static str {
+    match self {
+      MetricKind::Counter => "counter",
+      MetricKind::Gauge => "gauge",
+      MetricKind::Histogram => "histogram",
+    }
+  }
+}
+
+pub const SCRAPES: Metric = Metric {
+  name: "lodestone_news_scrapes_total",
+  help: "Attempts to scrape the Lodestone news page.",
+  kind: MetricKind::Counter,
+};
+
+pub const SCRAPE_FAILURES: Metric = Metric {
+  name: "lodestone_news_scrape_failures_total",
+  help: "Scrapes of the Lodestone news page that failed.",
+  kind: MetricKind::Counter,
+};
+
+pub const LAST_SCRAPE: Metric = Metric {
+  name: "lodestone_news_last_scrape_success_timestamp_seconds",
+  help: "Unix time of the last successful scrape.",
+  kind: MetricKind::Gauge,
+};
+
+pub const ITEMS_PARSED: Metric = Metric {
+  name: "lodestone_news_items_parsed_total",
+  help: "New items parsed from the news page, by kind.",
+  kind: MetricKind::Counter,
+};
+
+pub const PARSE_WARNINGS: Metric = Metric {
+  name: "lodestone_news_parse_warnings_total",
+  help: "Items skipped while parsing the news page, by reason.",
+  kind: MetricKind::Counter,
+};
+
+pub const DETAIL_FETCH: Metric = Metric {
+  name: "lodestone_news_detail_fetch_seconds",
+  help: "Time taken to download an item's detail page.",
+  kind: MetricKind::Histogram,
+};
+
+pub const WEBHOOK_SENDS: Metric = Metric {
+  name: "lodestone_news_webhook_sends_total",
+  help: "Discord webhook requests, by response status.",
+  kind: MetricKind::Counter,
+};
+
+pub const THROTTLE_WAITS: Metric = Metric {
+  name: "lodestone_news_webhook_throttle_waits_total",
+  help: "Fixed pauses after Discord webhook requests, made to stay under the rate limit.",
+  kind: MetricKind::Counter,
+};
+
+pub const THROTTLE_WAIT_SECONDS: Metric = Metric {
+  name: "lodestone_news_webhook_throttle_wait_seconds_total",
+  help: "Time spent in the fixed pauses after Discord webhook requests.",
+  kind: MetricKind::Counter,
+};
+
+const METRICS: &[&Metric] = &[
+  &SCRAPES,
+  &SCRAPE_FAILURES,
+  &LAST_SCRAPE,
+  &ITEMS_PARSED,
+  &PARSE_WARNINGS,
+  &DETAIL_FETCH,
+  &WEBHOOK_SENDS,
+  &THROTTLE_WAITS,
+  &THROTTLE_WAIT_SECONDS,
+];
+
+const BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+enum Value {
+  Scalar(f64),
+  Histogram {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+  },
+}
+
+lazy_static! {
+  // (metric name, rendered labels) to value
+  static ref VALUES: Mutex<BTreeMap<(&'static str, String), Value>> = Mutex::new(BTreeMap::new());
+}
+
+fn labels(labels: &[(&str, &str)]) -> String {
+  labels.iter()
+    .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+pub fn inc(metric: &Metric, l: &[(&str, &str)]) {
+  add(metric, l, 1.0);
+}
+
+pub fn add(metric: &Metric, l: &[(&str, &str)], value: f64) {
+  let mut values = VALUES.lock().unwrap();
+  if let Value::Scalar(ref mut v) = values.entry((metric.name, labels(l))).or_insert(Value::Scalar(0.0)) {
+    *v += value;
+  }
+}
+
+pub fn set(metric: &Metric, l: &[(&str, &str)], value: f64) {
+  VALUES.lock().unwrap().insert((metric.name, labels(l)), Value::Scalar(value));
+}
+
+pub fn get(metric: &Metric, l: &[(&str, &str)]) -> Option<f64> {
+  match VALUES.lock().unwrap().get(&(metric.name, labels(l))) {
+    Some(Value::Scalar(v)) => Some(*v),
+    _ => None,
+  }
+}
+
+pub fn observe(metric: &Metric, l: &[(&str, &str)], duration: Duration) {
+  let seconds = duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9;
+  let mut values = VALUES.lock().unwrap();
+  let value = values.entry((metric.name, labels(l))).or_insert_with(|| Value::Histogram {
+    buckets: vec![0; BUCKETS.len()],
+    sum: 0.0,
+    count: 0,
+  });
+  if let Value::Histogram { ref mut buckets, ref mut sum, ref mut count } = value {
+    for (bucket, le) in buckets.iter_mut().zip(BUCKETS) {
+      if seconds <= *le {
+        *bucket += 1;
+      }
+    }
+    *sum += seconds;
+    *count += 1;
+  }
+}
+
+fn series(out: &mut String, name: &str, labels: &str, value: f64) {
+  if labels.is_empty() {
+    writeln!(out, "{} {}", name, value).unwrap();
+  } else {
+    writeln!(out, "{}{{{}}} {}", name, labels, value).unwrap();
+  }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: MetricKind) {
+  writeln!(out, "# HELP {} {}", name, help).unwrap();
+  writeln!(out, "# TYPE {} {}", name, kind.as_str()).unwrap();
+}
+
+/// Renders every metric in the Prometheus text format.
+pub fn render() -> Result<String> {
+  let mut out = String::new();
+
+  {
+    let values = VALUES.lock().unwrap();
+    for metric in METRICS {
+      header(&mut out, metric.name, metric.help, metric.kind);
+      for ((_, l), value) in values.iter().filter(|((name, _), _)| *name == metric.name) {
+        match value {
+          Value::Scalar(v) => series(&mut out, metric.name, l, *v),
+          Value::Histogram { buckets, sum, count } => {
+            let bucket_name = format!("{}_bucket", metric.name);
+            let sep = if l.is_empty() { "" } else { "," };
+            for (n, le) in buckets.iter().zip(BUCKETS) {
+              series(&mut out, &bucket_name, &format!("{}{}le=\"{}\"", l, sep, le), *n as f64);
+            }
+            series(&mut out, &bucket_name, &format!("{}{}le=\"+Inf\"", l, sep), *count as f64);
+            series(&mut out, &format!("{}_sum", metric.name), l, *sum);
+            series(&mut out, &format!("{}_count", metric.name), l, *count as f64);
+          },
+        }
+      }
+    }
+  }
+
+  header(&mut out, "lodestone_news_seconds_since_last_scrape", "Seconds since the last successful scrape.", MetricKind::Gauge);
+  if let Some(last) = get(&LAST_SCRAPE, &[]) {
+    series(&mut out, "lodestone_news_seconds_since_last_scrape", "", Utc::now().timestamp() as f64 - last);
+  }
+
+  header(&mut out, "lodestone_news_backlog", "Items waiting to be sent, by server.", MetricKind::Gauge);
+  for (server, count) in backlog()? {
+    series(&mut out, "lodestone_news_backlog", &labels(&[("server", &server.to_string())]), count as f64);
+  }
+
+  Ok(out)
+}
+
+/// How many items are waiting to be sent to each enabled server.
+pub fn backlog() -> Result<Vec<(i32, i64)>> {
+  crate::CONNECTION.with(|c| {
+    // the same items the sender would pick up, including those held for digests or quiet hours
+    sql::<(Integer, BigInt)>("select
+      servers.id,
+      (select count(*) from news_items
+        where news_items.created >= servers.created
+        and (servers.id, news_items.id) not in (select server_id, news_id from send_records))
+      from servers
+      where servers.enabled
+      order by servers.id;")
+      .load(c)
+      .chain_err(|| "could not load backlog")
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use diesel::insert_into;
+
+  use crate::database::models::{
+    news_item::NewsItem,
+    server::NewServer,
+  };
+
+  #[test]
+  fn every_metric_has_help_and_type() {
+    crate::database::test_database();
+
+    let out = render().unwrap();
+    for metric in METRICS {
+      assert!(out.contains(&format!("# HELP {} {}\n# TYPE {} {}\n", metric.name, metric.help, metric.name, metric.kind.as_str())), "{}", metric.name);
+    }
+    assert!(out.contains("# TYPE lodestone_news_seconds_since_last_scrape gauge\n"));
+    assert!(out.contains("# TYPE lodestone_news_backlog gauge\n"));
+  }
+
+  #[test]
+  fn label_values_are_escaped() {
+    assert_eq!(labels(&[]), "");
+    assert_eq!(labels(&[("kind", "news"), ("reason", "a \"b\"\\c\nd")]), r#"kind="news",reason="a \"b\"\\c\nd""#);
+
+    inc(&PARSE_WARNINGS, &[("reason", "escaping \"test\"")]);
+    let out = render_for_test();
+    assert!(out.contains("lodestone_news_parse_warnings_total{reason=\"escaping \\\"test\\\"\"} 1\n"), "{}", out);
+  }
+
+  #[test]
+  fn histograms_have_cumulative_buckets() {
+    let l = [("test", "histograms")];
+    observe(&DETAIL_FETCH, &l, Duration::from_millis(300));
+    observe(&DETAIL_FETCH, &l, Duration::from_secs(3));
+
+    let out = render_for_test();
+    let lines: Vec<&str> = out.lines().filter(|l| l.contains("test=\"histograms\"")).collect();
+    assert_eq!(lines, vec![
+      "lodestone_news_detail_fetch_seconds_bucket{test=\"histograms\",le=\"0.1\"} 0",
+      "lodestone_news_detail_fetch_seconds_bucket{test=\"histograms\",le=\"0.25\"} 0",
+      "lodestone_news_detail_fetch_seconds_bucket{test=\"histograms\",le=\"0.5\"} 1",
+      "lodestone_news_detail_fetch_seconds_bucket{test=\"histograms\",le=\"1\"} 1",
+      "lodestone_news_detail_fetch_seconds_bucket{test=\"histograms\",le=\"2.5\"} 1",
+      "lodestone_news_detail_fetch_seconds_bucket{test=\"histograms\",le=\"5\"} 2",
+      "lodestone_news_detail_fetch_seconds_bucket{test=\"histograms\",le=\"10\"} 2",
+      "lodestone_news_detail_fetch_seconds_bucket{test=\"histograms\",le=\"+Inf\"} 2",
+      "lodestone_news_detail_fetch_seconds_sum{test=\"histograms\"} 3.3",
+      "lodestone_news_detail_fetch_seconds_count{test=\"histograms\"} 2",
+    ]);
+  }
+
+  #[test]
+  fn backlog_skips_disabled_servers() {
+    crate::database::test_database();
+
+    crate::CONNECTION.with(|c| {
+      use crate::database::schema::{news_items, servers};
+      let created = NewsItem::example(1, "").created;
+      let enabled = NewServer { created, ..NewServer::new("Enabled", "https://example.com/1") };
+      let disabled = NewServer { created, enabled: false, ..NewServer::new("Disabled", "https://example.com/2") };
+      insert_into(servers::table).values(&vec![enabled, disabled]).execute(c).unwrap();
+      let items = vec![NewsItem::example(1, "One").into_new(), NewsItem::example(2, "Two").into_new()];
+      insert_into(news_items::table).values(&items).execute(c).unwrap();
+    });
+
+    assert_eq!(backlog().unwrap(), vec![(1, 2)]);
+    let out = render().unwrap();
+    assert!(out.contains("lodestone_news_backlog{server=\"1\"} 2\n"), "{}", out);
+    assert!(!out.contains("server=\"2\""));
+  }
+
+  fn render_for_test() -> String {
+    crate::database::test_database();
+    render().unwrap()
+  }
+}
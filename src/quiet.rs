@@ -0,0 +1,156 @@
+use chrono::{DateTime, NaiveTime, Utc};
+
+use chrono_tz::Tz;
+
+use crate::{
+  database::models::{
+    news_item::NewsItem,
+    server::Server,
+  },
+  errors::*,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct QuietHours {
+  timezone: Tz,
+  start: NaiveTime,
+  end: NaiveTime,
+  bypass: bool,
+}
+
+impl QuietHours {
+  pub fn for_server(server: &Server) -> Result<Option<QuietHours>> {
+    let (start, end) = match (&server.quiet_start, &server.quiet_end) {
+      (Some(start), Some(end)) => (start, end),
+      (None, None) => return Ok(None),
+      _ => return Err("quiet hours need both a start and an end".into()),
+    };
+    let parse = |t: &str| NaiveTime::parse_from_str(t, "%H:%M")
+      .chain_err(|| format!("invalid quiet hours time: {}", t));
+    Ok(Some(QuietHours {
+      timezone: server.timezone()?,
+      start: parse(start)?,
+      end: parse(end)?,
+      bypass: server.quiet_bypass,
+    }))
+  }
+
+  pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+    let local = now.with_timezone(&self.timezone).time();
+    if self.start <= self.end {
+      local >= self.start && local < self.end
+    } else {
+      // the window wraps past midnight
+      local >= self.start || local < self.end
+    }
+  }
+
+  pub fn holds(&self, item: &NewsItem) -> bool {
+    !(self.bypass && item.is_urgent())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::database::models::{
+    news_item::NewsKind,
+    server::NewServer,
+  };
+
+  use chrono::TimeZone;
+
+  fn quiet(start: &str, end: &str, timezone: Option<&str>) -> QuietHours {
+    let server = NewServer {
+      quiet_start: Some(start.into()),
+      quiet_end: Some(end.into()),
+      timezone: timezone.map(Into::into),
+      ..NewServer::new("Test", "https://example.com")
+    };
+    QuietHours::for_server(&server.with_id(1)).unwrap().unwrap()
+  }
+
+  fn utc(hour: u32, minute: u32) -> DateTime<Utc> {
+    Utc.ymd(2019, 9, 8).and_hms(hour, minute, 0)
+  }
+
+  #[test]
+  fn needs_both_ends() {
+    let server = NewServer::new("Test", "https://example.com");
+    assert!(QuietHours::for_server(&server.with_id(1)).unwrap().is_none());
+
+    let server = NewServer {
+      quiet_start: Some("22:00".into()),
+      ..NewServer::new("Test", "https://example.com")
+    };
+    assert!(QuietHours::for_server(&server.with_id(1)).is_err());
+
+    let server = NewServer {
+      quiet_start: Some("22:00".into()),
+      quiet_end: Some("7am".into()),
+      ..NewServer::new("Test", "https://example.com")
+    };
+    assert!(QuietHours::for_server(&server.with_id(1)).is_err());
+  }
+
+  #[test]
+  fn window_within_a_day() {
+    let quiet = quiet("09:00", "17:00", None);
+    assert!(!quiet.is_active(utc(8, 59)));
+    assert!(quiet.is_active(utc(9, 0)));
+    assert!(quiet.is_active(utc(16, 59)));
+    assert!(!quiet.is_active(utc(17, 0)));
+  }
+
+  #[test]
+  fn window_crossing_midnight() {
+    let quiet = quiet("22:00", "07:00", None);
+    assert!(!quiet.is_active(utc(21, 59)));
+    assert!(quiet.is_active(utc(22, 0)));
+    assert!(quiet.is_active(utc(0, 0)));
+    assert!(quiet.is_active(utc(6, 59)));
+    assert!(!quiet.is_active(utc(7, 0)));
+    assert!(!quiet.is_active(utc(12, 0)));
+  }
+
+  #[test]
+  fn window_uses_the_server_timezone() {
+    // 22:00-07:00 in Tokyo (UTC+9) is 13:00-22:00 UTC
+    let quiet = quiet("22:00", "07:00", Some("Asia/Tokyo"));
+    assert!(!quiet.is_active(utc(12, 59)));
+    assert!(quiet.is_active(utc(13, 0)));
+    assert!(quiet.is_active(utc(21, 59)));
+    assert!(!quiet.is_active(utc(22, 0)));
+  }
+
+  #[test]
+  fn empty_window_is_never_active() {
+    let quiet = quiet("08:00", "08:00", None);
+    assert!(!quiet.is_active(utc(8, 0)));
+    assert!(!quiet.is_active(utc(20, 0)));
+  }
+
+  #[test]
+  fn urgent_items_bypass_unless_disabled() {
+    let news = NewsItem::example(1, "News");
+    let maintenance = NewsItem {
+      tag: Some("Maintenance".into()),
+      ..NewsItem::example(2, "Maintenance")
+    };
+    let notice = NewsItem {
+      kind: NewsKind::SpecialNotice,
+      ..NewsItem::example(3, "Notice")
+    };
+
+    let quiet = quiet("22:00", "07:00", None);
+    assert!(quiet.holds(&news));
+    assert!(!quiet.holds(&maintenance));
+    assert!(!quiet.holds(&notice));
+
+    let quiet = QuietHours { bypass: false, ..quiet };
+    assert!(quiet.holds(&news));
+    assert!(quiet.holds(&maintenance));
+    assert!(quiet.holds(&notice));
+  }
+}
@@ -0,0 +1,348 @@
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+
+use diesel::{
+  dsl::sql,
+  prelude::*,
+  insert_into,
+  sql_types::Timestamp,
+  update,
+};
+
+use crate::{
+  database::models::{
+    news_item::NewsItem,
+    reminder::{NewReminder, Reminder, ReminderKind},
+    server::Server,
+  },
+  discord::DiscordSink,
+  errors::*,
+  quiet::QuietHours,
+  sink::Outcome,
+};
+
+// reminders missed by more than this (e.g. while the bot was down) are dropped
+const STALE_MINUTES: i64 = 60;
+
+pub struct ReminderScheduler {
+  discord: DiscordSink,
+}
+
+impl Default for ReminderScheduler {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl ReminderScheduler {
+  pub fn new() -> Self {
+    ReminderScheduler {
+      discord: DiscordSink::new(),
+    }
+  }
+
+  pub fn run(&self) -> Result<()> {
+    self.schedule()?;
+    self.send_due()
+  }
+
+  pub fn schedule(&self) -> Result<()> {
+    let now = Utc::now().naive_utc();
+    let pairs: Vec<(Server, NewsItem)> = crate::CONNECTION.with(|c| {
+      use crate::database::schema::{servers, news_items};
+      sql::<(servers::SqlType, news_items::SqlType)>("select
+        servers.*, news_items.*
+        from servers, news_items
+        where servers.sink = 'discord'
+        and servers.enabled
+        and servers.reminders
+        and news_items.maintenance_start is not null
+        and news_items.maintenance_end > ")
+        .bind::<Timestamp, _>(now)
+        .sql(" and news_items.created >= servers.created")
+        .load(c)
+        .chain_err(|| "could not load maintenance windows")
+    })?;
+
+    for (server, item) in pairs {
+      if !server.accepts(&item) {
+        continue;
+      }
+      let (start, end) = match (item.maintenance_start, item.maintenance_end) {
+        (Some(s), Some(e)) => (s, e),
+        _ => continue,
+      };
+      let wanted = [
+        (ReminderKind::Start, start - Duration::minutes(i64::from(server.reminder_lead))),
+        (ReminderKind::End, end),
+      ];
+
+      for &(kind, due) in &wanted {
+        let existing: Option<Reminder> = crate::CONNECTION.with(|c| {
+          use crate::database::schema::reminders;
+          reminders::table
+            .filter(reminders::server_id.eq(server.id))
+            .filter(reminders::news_id.eq(item.id))
+            .filter(reminders::kind.eq(kind.as_str()))
+            .first(c)
+            .optional()
+            .chain_err(|| "could not load reminder")
+        })?;
+
+        match existing {
+          Some(ref r) if r.sent || r.due == due => {},
+          Some(r) => {
+            debug!("Moving {} reminder for item {} on server {} to {}", kind.as_str(), item.id, server.id, due);
+            crate::CONNECTION.with(|c| {
+              use crate::database::schema::reminders;
+              update(&r)
+                .set(reminders::due.eq(due))
+                .execute(c)
+                .chain_err(|| "could not update reminder")
+            })?;
+          },
+          None if due > now => {
+            debug!("Scheduling {} reminder for item {} on server {} at {}", kind.as_str(), item.id, server.id, due);
+            crate::CONNECTION.with(|c| {
+              use crate::database::schema::reminders;
+              insert_into(reminders::table)
+                .values(&NewReminder {
+                  server_id: server.id,
+                  news_id: item.id,
+                  kind: kind.as_str().to_string(),
+                  due,
+                  sent: false,
+                })
+                .execute(c)
+                .chain_err(|| "could not insert reminder")
+            })?;
+          },
+          None => {},
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  pub fn send_due(&self) -> Result<()> {
+    let now = Utc::now().naive_utc();
+    let due: Vec<(Reminder, Server, NewsItem)> = crate::CONNECTION.with(|c| {
+      use crate::database::schema::{reminders, servers, news_items};
+      reminders::table
+        .inner_join(servers::table)
+        .inner_join(news_items::table)
+        .filter(servers::enabled.eq(true))
+        .filter(reminders::sent.eq(false))
+        .filter(reminders::due.le(now))
+        .load(c)
+        .chain_err(|| "could not load due reminders")
+    })?;
+
+    for (reminder, server, item) in due {
+      let kind = match reminder.kind.parse::<ReminderKind>() {
+        Ok(k) => k,
+        Err(e) => {
+          warn!("Invalid reminder {}: {}", reminder.id, e);
+          continue;
+        },
+      };
+
+      let sent = if !server.reminders {
+        debug!("Dropping reminder {}: reminders are disabled on server {}", reminder.id, server.id);
+        true
+      } else if now - reminder.due > Duration::minutes(STALE_MINUTES) {
+        warn!("Dropping reminder {} for item {} on server {}: it is too late to send", reminder.id, item.id, server.id);
+        true
+      } else if ReminderScheduler::is_held(&server, &item, DateTime::from_utc(now, Utc)) {
+        // tried again every run until quiet hours end or the reminder goes stale
+        debug!("Holding reminder {} for quiet hours on server {}", reminder.id, server.id);
+        false
+      } else {
+        info!("Sending {} reminder for {} ({}) to {} ({})", kind.as_str(), item.title, item.id, server.title, server.id);
+        let data = ReminderScheduler::message(kind, &item, now);
+        match self.discord.post(&server.url, &data) {
+          Outcome::Sent(_) => true,
+          outcome => {
+            warn!("Could not send reminder {} to server {}: {:?}", reminder.id, server.id, outcome);
+            false
+          },
+        }
+      };
+
+      if sent {
+        crate::CONNECTION.with(|c| {
+          use crate::database::schema::reminders;
+          update(&reminder)
+            .set(reminders::sent.eq(true))
+            .execute(c)
+            .chain_err(|| "could not mark reminder as sent")
+        })?;
+      }
+    }
+
+    Ok(())
+  }
+
+  fn is_held(server: &Server, item: &NewsItem, now: DateTime<Utc>) -> bool {
+    match QuietHours::for_server(server) {
+      Ok(Some(q)) => q.is_active(now) && q.holds(item),
+      Ok(None) => false,
+      Err(e) => {
+        warn!("Invalid quiet hours for server {}: {}", server.id, e);
+        true
+      },
+    }
+  }
+
+  /// Describes how long is left until `start`, to the nearest minute.
+  fn time_until(start: NaiveDateTime, now: NaiveDateTime) -> Option<String> {
+    let minutes = ((start - now).num_seconds() + 30) / 60;
+    if minutes < 1 {
+      return None;
+    }
+    let plural = |n: i64| if n == 1 { "" } else { "s" };
+    let (hours, minutes) = (minutes / 60, minutes % 60);
+    Some(match (hours, minutes) {
+      (0, m) => format!("{} minute{}", m, plural(m)),
+      (h, 0) => format!("{} hour{}", h, plural(h)),
+      (h, m) => format!("{} hour{} {} minute{}", h, plural(h), m, plural(m)),
+    })
+  }
+
+  fn message(kind: ReminderKind, item: &NewsItem, now: NaiveDateTime) -> serde_json::Value {
+    let (description, time) = match kind {
+      ReminderKind::Start => {
+        // worded from the actual time left, since reminders can go out late or with no lead
+        let description = match item.maintenance_start.and_then(|s| ReminderScheduler::time_until(s, now)) {
+          Some(left) => format!("Maintenance begins in {}.", left),
+          None if item.maintenance_start.map(|s| s > now).unwrap_or(false) => "Maintenance is about to begin.".to_string(),
+          None => "Maintenance has begun.".to_string(),
+        };
+        (description, item.maintenance_start)
+      },
+      ReminderKind::End => ("Maintenance is scheduled to end now.".to_string(), item.maintenance_end),
+    };
+    serde_json::json!({
+      "embeds": [{
+        "type": "rich",
+        "title": item.title,
+        "url": item.url,
+        "color": item.kind.color(item.tag.as_ref()),
+        "description": description,
+        "timestamp": time.map(|t| DateTime::<Utc>::from_utc(t, Utc).to_rfc3339()),
+      }],
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::database::models::server::NewServer;
+
+  fn maintenance(start: NaiveDateTime) -> NewsItem {
+    NewsItem {
+      tag: Some("Maintenance".into()),
+      maintenance_start: Some(start),
+      maintenance_end: Some(start + Duration::hours(8)),
+      ..NewsItem::example(1, "All Worlds Maintenance")
+    }
+  }
+
+  fn description(item: &NewsItem, now: NaiveDateTime) -> String {
+    ReminderScheduler::message(ReminderKind::Start, item, now)["embeds"][0]["description"].as_str().unwrap().to_string()
+  }
+
+  #[test]
+  fn start_message_uses_the_time_left() {
+    let start = NewsItem::example(1, "").created + Duration::days(1);
+    let item = maintenance(start);
+    assert_eq!(description(&item, start - Duration::hours(2)), "Maintenance begins in 2 hours.");
+    assert_eq!(description(&item, start - Duration::minutes(60)), "Maintenance begins in 1 hour.");
+    assert_eq!(description(&item, start - Duration::minutes(90)), "Maintenance begins in 1 hour 30 minutes.");
+    // sent a little late
+    assert_eq!(description(&item, start - Duration::minutes(59) - Duration::seconds(40)), "Maintenance begins in 1 hour.");
+    assert_eq!(description(&item, start - Duration::minutes(1)), "Maintenance begins in 1 minute.");
+    assert_eq!(description(&item, start - Duration::seconds(20)), "Maintenance is about to begin.");
+    // with no lead time, or sent after the start
+    assert_eq!(description(&item, start), "Maintenance has begun.");
+    assert_eq!(description(&item, start + Duration::minutes(30)), "Maintenance has begun.");
+  }
+
+  #[test]
+  fn end_message() {
+    let item = maintenance(NewsItem::example(1, "").created);
+    let message = ReminderScheduler::message(ReminderKind::End, &item, item.created);
+    assert_eq!(message["embeds"][0]["description"], "Maintenance is scheduled to end now.");
+  }
+
+  #[test]
+  fn quiet_hours_hold_reminders_unless_bypassed() {
+    let item = maintenance(NewsItem::example(1, "").created);
+    let now = Utc::now().date().and_hms(12, 0, 0);
+    let server = |quiet: bool, bypass: bool| NewServer {
+      quiet_start: if quiet { Some("00:00".into()) } else { None },
+      quiet_end: if quiet { Some("23:59".into()) } else { None },
+      quiet_bypass: bypass,
+      ..NewServer::new("Test", "https://example.com")
+    }.with_id(1);
+
+    assert!(!ReminderScheduler::is_held(&server(false, false), &item, now));
+    assert!(!ReminderScheduler::is_held(&server(true, true), &item, now));
+    assert!(ReminderScheduler::is_held(&server(true, false), &item, now));
+  }
+
+  #[test]
+  fn schedules_reminders_only_for_accepted_items() {
+    crate::database::test_database();
+
+    let start = Utc::now().naive_utc() + Duration::days(1);
+    crate::CONNECTION.with(|c| {
+      use crate::database::schema::{news_items, servers};
+      let created = start - Duration::days(2);
+      let everything = NewServer { reminders: true, created, ..NewServer::new("Everything", "https://example.com/1") };
+      let topics = NewServer {
+        reminders: true,
+        created,
+        filter_kinds: Some("topic".into()),
+        ..NewServer::new("Topics", "https://example.com/2")
+      };
+      insert_into(servers::table).values(&vec![everything, topics]).execute(c).unwrap();
+      let item = NewsItem { created: start - Duration::days(1), ..maintenance(start) };
+      insert_into(news_items::table).values(&item.into_new()).execute(c).unwrap();
+    });
+
+    ReminderScheduler::new().schedule().unwrap();
+
+    let reminders: Vec<Reminder> = crate::CONNECTION.with(|c| {
+      use crate::database::schema::reminders;
+      reminders::table.order(reminders::kind).load(c).unwrap()
+    });
+    let scheduled: Vec<(i32, &str, NaiveDateTime)> = reminders.iter().map(|r| (r.server_id, r.kind.as_str(), r.due)).collect();
+    assert_eq!(scheduled, vec![(1, "end", start + Duration::hours(8)), (1, "start", start - Duration::hours(1))]);
+  }
+
+  #[test]
+  fn ended_maintenance_is_not_scheduled() {
+    crate::database::test_database();
+
+    let now = Utc::now().naive_utc();
+    crate::CONNECTION.with(|c| {
+      use crate::database::schema::{news_items, servers};
+      let server = NewServer { reminders: true, created: now - Duration::days(2), ..NewServer::new("Test", "https://example.com") };
+      insert_into(servers::table).values(&server).execute(c).unwrap();
+      // ended a minute ago
+      let item = NewsItem { created: now - Duration::days(1), ..maintenance(now - Duration::hours(8) - Duration::minutes(1)) };
+      insert_into(news_items::table).values(&item.into_new()).execute(c).unwrap();
+    });
+
+    ReminderScheduler::new().schedule().unwrap();
+
+    let count: i64 = crate::CONNECTION.with(|c| {
+      use crate::database::schema::reminders;
+      reminders::table.count().get_result(c).unwrap()
+    });
+    assert_eq!(count, 0);
+  }
+}
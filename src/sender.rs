@@ -0,0 +1,387 @@
+use crate::{
+  database::models::{
+    news_item::{NewNewsItem, NewsItem},
+    send_failure::NewSendFailure,
+    send_record::NewSendRecord,
+    server::Server,
+  },
+  digest::{DigestSchedule, DigestStyle},
+  errors::*,
+  quiet::QuietHours,
+  sink::{Outcome, Sink, Sinks},
+};
+
+use chrono::{Utc, TimeZone};
+
+use diesel::{
+  prelude::*,
+  delete,
+  dsl::sql,
+  replace_into,
+};
+
+// how many recent items to look through for one a server accepts when sending a test
+const TEST_CANDIDATES: i64 = 50;
+// how much of an error response to keep for the dashboard
+const MAX_ERROR: usize = 2000;
+
+pub struct NewsSender {
+  sinks: Sinks,
+  dry_run: bool,
+}
+
+impl Default for NewsSender {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl NewsSender {
+  pub fn new() -> Self {
+    NewsSender {
+      sinks: Sinks::new(),
+      dry_run: false,
+    }
+  }
+
+  /// Prints what would be sent as JSON lines if `new` had been collected, without sending or
+  /// recording anything.
+  pub fn dry_run(new: Vec<NewNewsItem>) -> Result<()> {
+    let sender = NewsSender {
+      sinks: Sinks::new(),
+      dry_run: true,
+    };
+    let mut pending = NewsSender::pending()?;
+
+    let (last_id, servers) = crate::CONNECTION.with(|c| -> QueryResult<_> {
+      use crate::database::schema::{news_items, servers};
+      let last_id: Option<i32> = news_items::table.select(news_items::id).order(news_items::id.desc()).first(c).optional()?;
+      let servers: Vec<Server> = servers::table.filter(servers::enabled).load(c)?;
+      Ok((last_id, servers))
+    })
+    .chain_err(|| "could not load servers")?;
+    // the ids the items would be given when inserted, in the same order
+    let new: Vec<NewsItem> = new.into_iter()
+      .enumerate()
+      .map(|(i, item)| item.with_id(last_id.unwrap_or(0) + 1 + i as i32))
+      .collect();
+    for server in servers {
+      let items: Vec<NewsItem> = new.iter().filter(|item| item.created >= server.created).cloned().collect();
+      if items.is_empty() {
+        continue;
+      }
+      match pending.iter_mut().find(|(s, _)| s.id == server.id) {
+        Some((_, pending)) => pending.extend(items),
+        None => pending.push((server, items)),
+      }
+    }
+    for (_, items) in &mut pending {
+      items.sort_by_key(|item| item.created);
+    }
+
+    sender.send_pending(pending);
+    Ok(())
+  }
+
+  pub fn send_new_news(&self) -> Result<()> {
+    let (sends, failures) = self.send_pending(NewsSender::pending()?);
+    NewsSender::record(&sends, &failures)
+  }
+
+  /// Every enabled server with the items it hasn't been sent yet, oldest first.
+  fn pending() -> Result<Vec<(Server, Vec<NewsItem>)>> {
+    let to_send: Vec<(Server, NewsItem)> = crate::CONNECTION.with(|c| {
+      use crate::database::schema::{servers, news_items};
+      sql::<(servers::SqlType, news_items::SqlType)>("select
+        servers.*, news_items.*
+        from servers, news_items
+        where servers.enabled
+        and (servers.id, news_items.id) not in (select server_id, news_id from send_records)
+        and news_items.created >= servers.created
+        order by news_items.created;")
+        .load(c)
+        .chain_err(|| "could not load items to send")
+    })?;
+
+    let mut pending: Vec<(Server, Vec<NewsItem>)> = Vec::new();
+    for (server, item) in to_send {
+      match pending.iter_mut().find(|(s, _)| s.id == server.id) {
+        Some((_, items)) => items.push(item),
+        None => pending.push((server, vec![item])),
+      }
+    }
+    Ok(pending)
+  }
+
+  /// Sends items to their servers, returning what to record.
+  fn send_pending(&self, pending: Vec<(Server, Vec<NewsItem>)>) -> (Vec<NewSendRecord>, Vec<NewSendFailure>) {
+    let now = Utc::now();
+    let mut successful_sends = Vec::new();
+    let mut failures = Vec::new();
+
+    for (server, items) in pending {
+      let sink = match self.sinks.get(&server.sink) {
+        Some(s) => s,
+        None => {
+          warn!("Unknown sink {} for server {}", server.sink, server.id);
+          continue;
+        },
+      };
+
+      let schedule = match server.digest_schedule {
+        Some(ref s) => match s.parse::<DigestSchedule>() {
+          Ok(s) => Some(s),
+          Err(e) => {
+            warn!("Invalid digest schedule for server {}: {}", server.id, e);
+            continue;
+          },
+        },
+        None => None,
+      };
+
+      // items a server has filtered out are recorded as handled so they are never picked up again
+      let (items, skipped): (Vec<NewsItem>, Vec<NewsItem>) = items.into_iter().partition(|item| server.accepts(item));
+      successful_sends.extend(skipped.iter().map(|item| NewSendRecord {
+        server_id: server.id,
+        news_id: item.id,
+        remote_id: None,
+      }));
+
+      let quiet = match QuietHours::for_server(&server) {
+        Ok(q) => q,
+        Err(e) => {
+          warn!("Invalid quiet hours for server {}: {}", server.id, e);
+          continue;
+        },
+      };
+
+      let (items, held): (Vec<NewsItem>, Vec<NewsItem>) = match quiet {
+        Some(ref q) if q.is_active(now) => items.into_iter().partition(|item| !q.holds(item)),
+        _ => (items, Vec::new()),
+      };
+      if !held.is_empty() {
+        debug!("Holding {} item(s) for quiet hours on server {}", held.len(), server.id);
+      }
+      if items.is_empty() {
+        continue;
+      }
+
+      let schedule = match schedule {
+        Some(s) => s,
+        None => {
+          for item in items {
+            info!("Sending {} ({}) to {} ({})", item.title, item.id, server.title, server.id);
+            let outcome = self.send(sink, &item, &server);
+            if let Some(remote_id) = NewsSender::check(&server, &[item.id], &outcome, &mut failures) {
+              successful_sends.push(NewSendRecord {
+                server_id: server.id,
+                news_id: item.id,
+                remote_id,
+              });
+            }
+          }
+          continue;
+        },
+      };
+
+      let timezone = match server.timezone() {
+        Ok(tz) => tz,
+        Err(e) => {
+          warn!("Invalid time zone for server {}: {}", server.id, e);
+          continue;
+        },
+      };
+
+      // items are ordered by creation, so the first is the oldest
+      let oldest = timezone.from_utc_datetime(&items[0].created).naive_local();
+      if !schedule.is_due(oldest, items.len(), now.with_timezone(&timezone).naive_local()) {
+        debug!("Holding {} item(s) for digest on server {}", items.len(), server.id);
+        continue;
+      }
+
+      let style = match server.digest_style.parse() {
+        Ok(s) => s,
+        Err(e) => {
+          warn!("Invalid digest style for server {}: {}", server.id, e);
+          continue;
+        },
+      };
+
+      info!("Sending digest of {} item(s) to {} ({})", items.len(), server.title, server.id);
+      for (ids, outcome) in self.send_digest(sink, style, &items, &server) {
+        if let Some(remote_id) = NewsSender::check(&server, &ids, &outcome, &mut failures) {
+          successful_sends.extend(ids.iter().map(|&id| NewSendRecord {
+            server_id: server.id,
+            news_id: id,
+            remote_id: remote_id.clone(),
+          }));
+        }
+      }
+    }
+
+    (successful_sends, failures)
+  }
+
+  fn send(&self, sink: &dyn Sink, item: &NewsItem, server: &Server) -> Outcome {
+    if self.dry_run {
+      NewsSender::print(server, &[item], sink.preview(item, server));
+      return Outcome::Sent(None);
+    }
+    sink.send(item, server)
+  }
+
+  fn send_digest(&self, sink: &dyn Sink, style: DigestStyle, items: &[NewsItem], server: &Server) -> Vec<(Vec<i32>, Outcome)> {
+    if !self.dry_run {
+      return sink.send_digest(style, items, server);
+    }
+    sink.preview_digest(style, items, server).into_iter()
+      .map(|(ids, body)| {
+        let sent: Vec<&NewsItem> = items.iter().filter(|item| ids.contains(&item.id)).collect();
+        NewsSender::print(server, &sent, body);
+        (ids, Outcome::Sent(None))
+      })
+      .collect()
+  }
+
+  /// Prints a message that would be sent, giving its items by Lodestone id since new items aren't in
+  /// the database.
+  fn print(server: &Server, items: &[&NewsItem], body: serde_json::Value) {
+    let items: Vec<&str> = items.iter().map(|item| item.lodestone_id.as_str()).collect();
+    println!("{}", serde_json::json!({
+      "server": server.id,
+      "sink": server.sink,
+      "items": items,
+      "body": body,
+    }));
+  }
+
+  /// Sends an item to a server again, whether or not it has been sent before, and records the outcome.
+  pub fn resend(&self, server: &Server, item: &NewsItem) -> Result<Outcome> {
+    let sink = self.sinks.get(&server.sink).ok_or_else(|| format!("unknown sink: {}", server.sink))?;
+    info!("Resending {} ({}) to {} ({})", item.title, item.id, server.title, server.id);
+    let outcome = sink.resend(item, server);
+    let mut failures = Vec::new();
+    let sends: Vec<NewSendRecord> = NewsSender::check(server, &[item.id], &outcome, &mut failures).into_iter()
+      .map(|remote_id| NewSendRecord {
+        server_id: server.id,
+        news_id: item.id,
+        remote_id,
+      })
+      .collect();
+    NewsSender::record(&sends, &failures)?;
+    Ok(outcome)
+  }
+
+  /// Saves successful sends, clearing any earlier failures for them, and the latest failures.
+  fn record(sends: &[NewSendRecord], failures: &[NewSendFailure]) -> Result<()> {
+    crate::CONNECTION.with(|c| {
+      use crate::database::schema::{send_failures, send_records};
+      c.transaction(|| {
+        // resent items already have a record
+        replace_into(send_records::table).values(sends).execute(c)?;
+        for send in sends {
+          delete(send_failures::table.find((send.server_id, send.news_id))).execute(c)?;
+        }
+        replace_into(send_failures::table).values(failures).execute(c)
+      })
+      .chain_err(|| "could not update send records")
+    })?;
+    Ok(())
+  }
+
+  /// Sends the newest item a server accepts without recording it, to check the server's settings.
+  pub fn send_test(&self, server: &Server) -> Result<(NewsItem, Outcome)> {
+    let sink = self.sinks.get(&server.sink).ok_or_else(|| format!("unknown sink: {}", server.sink))?;
+    let items: Vec<NewsItem> = crate::CONNECTION.with(|c| {
+      use crate::database::schema::news_items;
+      news_items::table
+        .order(news_items::created.desc())
+        .limit(TEST_CANDIDATES)
+        .load(c)
+        .chain_err(|| "could not load items")
+    })?;
+    let item = match items.iter().position(|i| server.accepts(i)) {
+      Some(i) => items.into_iter().nth(i),
+      None => items.into_iter().next(),
+    };
+    let item = item.ok_or("there are no items to send")?;
+    info!("Sending test item {} to {} ({})", item.id, server.title, server.id);
+    let outcome = sink.send(&item, server);
+    Ok((item, outcome))
+  }
+
+  /// Logs the outcome of a send, returning the remote id to record if it was successful and adding
+  /// to `failures` if it wasn't.
+  fn check(server: &Server, ids: &[i32], outcome: &Outcome, failures: &mut Vec<NewSendFailure>) -> Option<Option<String>> {
+    let id_list: Vec<String> = ids.iter().map(ToString::to_string).collect();
+    let id_list = id_list.join(", ");
+    let (status, error) = match *outcome {
+      Outcome::Sent(ref remote_id) => {
+        trace!("Send successful for item(s) {} on server {}", id_list, server.id);
+        return Some(remote_id.clone());
+      },
+      Outcome::Rejected { status, ref body } => {
+        warn!("Send was not successful ({}) for item(s) {} on server {}. Content below:", status, id_list, server.id);
+        warn!("{}", body);
+        (Some(i32::from(status)), body.chars().take(MAX_ERROR).collect())
+      },
+      Outcome::Failed(ref e) => {
+        warn!("Error sending item(s) {} to server {}: {}", id_list, server.id, e);
+        (None, e.clone())
+      },
+    };
+    let attempted = Utc::now().naive_utc();
+    failures.extend(ids.iter().map(|&id| NewSendFailure {
+      server_id: server.id,
+      news_id: id,
+      status,
+      error: error.clone(),
+      attempted,
+    }));
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::database::models::server::NewServer;
+  use chrono::NaiveDateTime;
+  use diesel::{dsl::count_star, insert_into};
+
+  #[test]
+  fn dry_runs_leave_the_database_alone() {
+    crate::database::test_database();
+    let server = NewServer {
+      created: NaiveDateTime::from_timestamp(0, 0),
+      ..NewServer::new("Test", "https://discord.com/api/webhooks/1/abc")
+    };
+    let disabled = NewServer {
+      enabled: false,
+      ..NewServer::new("Disabled", "https://discord.com/api/webhooks/2/abc")
+    };
+    crate::CONNECTION.with(|c| {
+      use crate::database::schema::{news_items, servers};
+      insert_into(servers::table).values(&vec![server, disabled]).execute(c).unwrap();
+      insert_into(news_items::table).values(&NewsItem::example(1, "Stored").into_new()).execute(c).unwrap();
+    });
+
+    let pending = NewsSender::pending().unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].1.len(), 1);
+
+    let mut new = NewsItem::example(0, "New").into_new();
+    new.lodestone_id = "new".into();
+    NewsSender::dry_run(vec![new]).unwrap();
+
+    let (items, records, failures) = crate::CONNECTION.with(|c| {
+      use crate::database::schema::{news_items, send_failures, send_records};
+      (
+        news_items::table.select(count_star()).first::<i64>(c).unwrap(),
+        send_records::table.select(count_star()).first::<i64>(c).unwrap(),
+        send_failures::table.select(count_star()).first::<i64>(c).unwrap(),
+      )
+    });
+    assert_eq!((items, records, failures), (1, 0, 0));
+  }
+}
@@ -0,0 +1,226 @@
+use crate::{
+  database::models::{
+    news_item::NewsItem,
+    server::Server,
+  },
+  digest::DigestStyle,
+  discord::DiscordSink,
+  errors::*,
+  export::ExportedItem,
+  sink::{
+    email::EmailSink,
+    google_chat::GoogleChatSink,
+    gotify::GotifySink,
+    mastodon::MastodonSink,
+    matrix::MatrixSink,
+    ntfy::NtfySink,
+    slack::SlackSink,
+    teams::TeamsSink,
+    telegram::TelegramSink,
+    webhook::WebhookSink,
+  },
+};
+
+use reqwest::Response;
+
+use serde::de::DeserializeOwned;
+
+use std::io::Read;
+
+pub mod email;
+pub mod format;
+pub mod google_chat;
+pub mod gotify;
+pub mod mastodon;
+pub mod matrix;
+pub mod ntfy;
+pub mod slack;
+pub mod teams;
+pub mod telegram;
+pub mod webhook;
+
+#[derive(Debug)]
+pub enum Outcome {
+  /// The destination accepted the item, optionally returning an id for the message it created.
+  Sent(Option<String>),
+  /// The destination answered with an unsuccessful status.
+  Rejected { status: u16, body: String },
+  /// The request could not be made or its response could not be read.
+  Failed(String),
+}
+
+impl Outcome {
+  pub fn is_sent(&self) -> bool {
+    match *self {
+      Outcome::Sent(_) => true,
+      _ => false,
+    }
+  }
+
+  /// Reads a response into an outcome, using `id` to find the id of the created message.
+  pub fn from_response<F>(res: reqwest::Result<Response>, id: F) -> Outcome
+    where F: FnOnce(&str) -> Option<String>,
+  {
+    let mut res = match res {
+      Ok(r) => r,
+      Err(e) => return Outcome::Failed(e.to_string()),
+    };
+    let mut body = String::new();
+    if let Err(e) = res.read_to_string(&mut body) {
+      return Outcome::Failed(format!("could not read response: {}", e));
+    }
+    if !res.status().is_success() {
+      return Outcome::Rejected {
+        status: res.status().as_u16(),
+        body,
+      };
+    }
+    Outcome::Sent(id(&body))
+  }
+}
+
+pub trait Sink {
+  fn send(&self, item: &NewsItem, destination: &Server) -> Outcome;
+
+  /// Checks a destination's `config` before it is saved. Sinks without settings accept anything.
+  fn check_config(&self, _config: &str) -> Result<()> {
+    Ok(())
+  }
+
+  /// Sends several items at once, returning the ids of the items each message covered.
+  ///
+  /// Sinks without a digest format send every item on its own.
+  fn send_digest(&self, _style: DigestStyle, items: &[NewsItem], destination: &Server) -> Vec<(Vec<i32>, Outcome)> {
+    items.iter()
+      .map(|item| (vec![item.id], self.send(item, destination)))
+      .collect()
+  }
+
+  /// Sends an item again when asked to, even if it was sent before. Sinks that let the
+  /// destination drop repeated sends of an item override this to post a new message.
+  fn resend(&self, item: &NewsItem, destination: &Server) -> Outcome {
+    self.send(item, destination)
+  }
+
+  /// What `send` would post for an item, for dry runs. Defaults to the item in the export format.
+  fn preview(&self, item: &NewsItem, _destination: &Server) -> serde_json::Value {
+    serde_json::to_value(ExportedItem::from(item)).unwrap_or_default()
+  }
+
+  /// What `send_digest` would post, with the ids of the items each message covers.
+  fn preview_digest(&self, _style: DigestStyle, items: &[NewsItem], destination: &Server) -> Vec<(Vec<i32>, serde_json::Value)> {
+    items.iter()
+      .map(|item| (vec![item.id], self.preview(item, destination)))
+      .collect()
+  }
+}
+
+/// Checks that `config` has everything the sink's config type needs.
+pub fn check_config<T: DeserializeOwned>(sink: &str, config: &str) -> Result<()> {
+  serde_json::from_str::<T>(config)
+    .map(|_| ())
+    .map_err(|e| format!("invalid {} config: {}", sink, e).into())
+}
+
+pub struct Sinks {
+  discord: DiscordSink,
+  email: EmailSink,
+  google_chat: GoogleChatSink,
+  gotify: GotifySink,
+  mastodon: MastodonSink,
+  matrix: MatrixSink,
+  ntfy: NtfySink,
+  slack: SlackSink,
+  teams: TeamsSink,
+  telegram: TelegramSink,
+  webhook: WebhookSink,
+}
+
+impl Default for Sinks {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Sinks {
+  pub fn new() -> Self {
+    Sinks {
+      discord: DiscordSink::new(),
+      email: EmailSink::new(),
+      google_chat: GoogleChatSink::new(),
+      gotify: GotifySink::new(),
+      mastodon: MastodonSink::new(),
+      matrix: MatrixSink::new(),
+      ntfy: NtfySink::new(),
+      slack: SlackSink::new(),
+      teams: TeamsSink::new(),
+      telegram: TelegramSink::new(),
+      webhook: WebhookSink::new(),
+    }
+  }
+
+  pub fn get(&self, name: &str) -> Option<&dyn Sink> {
+    match name {
+      "discord" => Some(&self.discord),
+      "email" => Some(&self.email),
+      "google_chat" => Some(&self.google_chat),
+      "gotify" => Some(&self.gotify),
+      "mastodon" => Some(&self.mastodon),
+      "matrix" => Some(&self.matrix),
+      "ntfy" => Some(&self.ntfy),
+      "slack" => Some(&self.slack),
+      "teams" => Some(&self.teams),
+      "telegram" => Some(&self.telegram),
+      "webhook" => Some(&self.webhook),
+      _ => None,
+    }
+  }
+}
+
+/// A request received by `capture`.
+#[cfg(test)]
+pub struct Captured {
+  pub method: String,
+  pub url: String,
+  pub headers: Vec<(String, String)>,
+  pub body: Vec<u8>,
+}
+
+#[cfg(test)]
+impl Captured {
+  pub fn header(&self, name: &str) -> Option<&str> {
+    self.headers.iter()
+      .find(|(n, _)| n.eq_ignore_ascii_case(name))
+      .map(|(_, v)| v.as_str())
+  }
+
+  pub fn json(&self) -> serde_json::Value {
+    serde_json::from_slice(&self.body).unwrap()
+  }
+}
+
+/// Listens on a local port and answers one request with each of `responses` in turn, returning
+/// the base url to send to and a handle that yields the requests.
+#[cfg(test)]
+pub fn capture(responses: Vec<&'static str>) -> (String, std::thread::JoinHandle<Vec<Captured>>) {
+  let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+  let base = format!("http://{}", server.server_addr());
+  let handle = std::thread::spawn(move || {
+    responses.into_iter()
+      .map(|response| {
+        let mut request = server.recv().unwrap();
+        let mut body = Vec::new();
+        request.as_reader().read_to_end(&mut body).unwrap();
+        let captured = Captured {
+          method: request.method().to_string(),
+          url: request.url().to_string(),
+          headers: request.headers().iter().map(|h| (h.field.to_string(), h.value.to_string())).collect(),
+          body,
+        };
+        request.respond(tiny_http::Response::from_string(response)).unwrap();
+        captured
+      })
+      .collect()
+  });
+  (base, handle)
+}
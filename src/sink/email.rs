@@ -0,0 +1,293 @@
+use diesel::prelude::*;
+
+use lettre::{
+  ClientSecurity,
+  ClientTlsParameters,
+  SendableEmail,
+  SmtpClient,
+  Transport,
+  smtp::{
+    authentication::Credentials,
+    error::Error as SmtpError,
+  },
+};
+
+use lettre_email::EmailBuilder;
+
+use native_tls::TlsConnector;
+
+use serde_derive::Deserialize;
+
+use url::Url;
+
+use crate::{
+  database::models::{
+    news_item::NewsItem,
+    server::Server,
+  },
+  digest::DigestStyle,
+  errors::*,
+  sink::{
+    self,
+    Outcome,
+    Sink,
+    format::{self, escape_html},
+  },
+};
+
+#[derive(Debug, Deserialize)]
+struct EmailConfig {
+  from: String,
+  username: Option<String>,
+  password: Option<String>,
+}
+
+pub struct EmailSink;
+
+impl Default for EmailSink {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl EmailSink {
+  pub fn new() -> Self {
+    EmailSink
+  }
+
+  /// Renders items as a plain text body and an HTML body.
+  pub fn bodies(style: DigestStyle, items: &[NewsItem]) -> (String, String) {
+    let mut text = String::new();
+    let mut html = String::from("<!DOCTYPE html><html><body>");
+
+    for item in items {
+      let mut footer = item.kind.to_string();
+      if let Some(ref tag) = item.tag {
+        footer.push_str(&format!(" · {}", tag));
+      }
+
+      text.push_str(&format!("{}\n{}\n{}\n", item.title, footer, item.url));
+      html.push_str(&format!(
+        "<h2><a href=\"{}\">{}</a></h2><p><small>{}</small></p>",
+        escape_html(&item.url),
+        escape_html(&item.title),
+        escape_html(&footer),
+      ));
+
+      if style == DigestStyle::Embeds {
+        if let Some(ref image) = item.image {
+          html.push_str(&format!("<p><img src=\"{}\" alt=\"\" style=\"max-width: 100%\"></p>", escape_html(image)));
+        }
+        if let Some(ref description) = item.description {
+          text.push_str(&format!("\n{}\n", format::plain(description)));
+          html.push_str(&format!("<p>{}</p>", format::html(description)));
+        }
+        for field in format::fields(item) {
+          text.push_str(&format!("\n{}\n{}\n", field.name, format::plain(&field.value)));
+          html.push_str(&format!("<h3>{}</h3><p>{}</p>", escape_html(&field.name), format::html(&field.value)));
+        }
+      }
+
+      text.push('\n');
+      html.push_str("<hr>");
+    }
+
+    html.push_str("</body></html>");
+    (text, html)
+  }
+
+  fn subject(items: &[NewsItem]) -> String {
+    if items.len() == 1 {
+      items[0].title.clone()
+    } else {
+      format!("Lodestone news: {} items", items.len())
+    }
+  }
+
+  fn subscribers(server: &Server) -> Result<Vec<String>> {
+    crate::CONNECTION.with(|c| {
+      use crate::database::schema::email_subscribers;
+      email_subscribers::table
+        .filter(email_subscribers::server_id.eq(server.id))
+        .select(email_subscribers::address)
+        .load(c)
+        .chain_err(|| "could not load email subscribers")
+    })
+  }
+
+  /// Builds a client from a `smtps://`, `smtp://` (STARTTLS) or `smtp+insecure://` url.
+  fn client(url: &str, config: &EmailConfig) -> Result<SmtpClient> {
+    let url = Url::parse(url).chain_err(|| format!("invalid smtp url: {}", url))?;
+    let host = url.host_str().ok_or_else(|| format!("smtp url has no host: {}", url))?.to_string();
+    let tls = || -> Result<ClientTlsParameters> {
+      let connector = TlsConnector::new().chain_err(|| "could not create tls connector")?;
+      Ok(ClientTlsParameters::new(host.clone(), connector))
+    };
+    let (security, port) = match url.scheme() {
+      "smtps" => (ClientSecurity::Wrapper(tls()?), 465),
+      "smtp" => (ClientSecurity::Required(tls()?), 587),
+      "smtp+insecure" => (ClientSecurity::None, 25),
+      scheme => return Err(format!("unknown smtp scheme: {}", scheme).into()),
+    };
+    let mut client = SmtpClient::new((host.as_str(), url.port().unwrap_or(port)), security)
+      .chain_err(|| format!("could not resolve {}", host))?;
+    if let (Some(username), Some(password)) = (config.username.clone(), config.password.clone()) {
+      client = client.credentials(Credentials::new(username, password));
+    }
+    Ok(client)
+  }
+
+  /// Builds one email to every subscriber, addressed to the sender with the subscribers hidden from
+  /// each other.
+  fn email(config: &EmailConfig, style: DigestStyle, items: &[NewsItem], subscribers: &[String]) -> std::result::Result<SendableEmail, String> {
+    let (text, html) = EmailSink::bodies(style, items);
+    let mut builder = EmailBuilder::new()
+      .from(config.from.as_str())
+      .to(config.from.as_str())
+      .subject(EmailSink::subject(items))
+      .alternative(html, text);
+    for address in subscribers {
+      builder = builder.bcc(address.as_str());
+    }
+    builder.build()
+      .map(Into::into)
+      .map_err(|e| format!("could not build email: {}", e))
+  }
+
+  fn send_items(&self, style: DigestStyle, items: &[NewsItem], destination: &Server) -> Outcome {
+    let config: EmailConfig = match destination.config() {
+      Ok(c) => c,
+      Err(e) => return Outcome::Failed(e.to_string()),
+    };
+    let subscribers = match EmailSink::subscribers(destination) {
+      Ok(s) => s,
+      Err(e) => return Outcome::Failed(e.to_string()),
+    };
+    if subscribers.is_empty() {
+      // leave the items unsent so they go out once someone subscribes
+      return Outcome::Failed("there are no email subscribers".into());
+    }
+    let email = match EmailSink::email(&config, style, items, &subscribers) {
+      Ok(e) => e,
+      Err(e) => return Outcome::Failed(e),
+    };
+    let message_id = email.message_id().to_string();
+
+    let mut transport = match EmailSink::client(&destination.url, &config) {
+      Ok(c) => c.transport(),
+      Err(e) => return Outcome::Failed(e.to_string()),
+    };
+    let res = transport.send(email);
+    transport.close();
+    match res {
+      Ok(_) => Outcome::Sent(Some(message_id)),
+      Err(SmtpError::Transient(r)) | Err(SmtpError::Permanent(r)) => Outcome::Rejected {
+        status: r.code.to_string().parse().unwrap_or_default(),
+        body: r.message.join("\n"),
+      },
+      Err(e) => Outcome::Failed(e.to_string()),
+    }
+  }
+}
+
+impl Sink for EmailSink {
+  fn check_config(&self, config: &str) -> Result<()> {
+    sink::check_config::<EmailConfig>("email", config)
+  }
+
+  fn send(&self, item: &NewsItem, destination: &Server) -> Outcome {
+    self.send_items(DigestStyle::Embeds, std::slice::from_ref(item), destination)
+  }
+
+  fn send_digest(&self, style: DigestStyle, items: &[NewsItem], destination: &Server) -> Vec<(Vec<i32>, Outcome)> {
+    let ids = items.iter().map(|item| item.id).collect();
+    vec![(ids, self.send_items(style, items, destination))]
+  }
+
+  fn preview(&self, item: &NewsItem, destination: &Server) -> serde_json::Value {
+    self.preview_digest(DigestStyle::Embeds, std::slice::from_ref(item), destination).remove(0).1
+  }
+
+  fn preview_digest(&self, style: DigestStyle, items: &[NewsItem], _destination: &Server) -> Vec<(Vec<i32>, serde_json::Value)> {
+    let ids = items.iter().map(|item| item.id).collect();
+    let (text, _) = EmailSink::bodies(style, items);
+    vec![(ids, serde_json::json!({
+      "subject": EmailSink::subject(items),
+      "text": text,
+    }))]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::database::models::server::NewServer;
+
+  fn config() -> EmailConfig {
+    EmailConfig {
+      from: "news@example.com".into(),
+      username: None,
+      password: None,
+    }
+  }
+
+  fn items() -> Vec<NewsItem> {
+    let mut first = NewsItem::example(1, "Patch & Notes");
+    first.tag = Some("Maintenance".into());
+    first.description = Some("See [here](https://example.com).".into());
+    vec![first, NewsItem::example(2, "Second")]
+  }
+
+  #[test]
+  fn subject_names_a_single_item() {
+    let items = items();
+    assert_eq!(EmailSink::subject(&items[..1]), "Patch & Notes");
+    assert_eq!(EmailSink::subject(&items), "Lodestone news: 2 items");
+  }
+
+  #[test]
+  fn bodies_include_descriptions_only_for_embeds() {
+    let (text, html) = EmailSink::bodies(DigestStyle::Embeds, &items());
+    assert!(text.starts_with("Patch & Notes\nNews · Maintenance\nhttps://na.finalfantasyxiv.com/lodestone/news/detail/1\n"));
+    assert!(text.contains("See here (https://example.com)."));
+    assert!(html.contains("<h2><a href=\"https://na.finalfantasyxiv.com/lodestone/news/detail/1\">Patch &amp; Notes</a></h2>"));
+    assert!(html.contains("<a href=\"https://example.com\">here</a>"));
+
+    let (text, html) = EmailSink::bodies(DigestStyle::Summary, &items());
+    assert!(!text.contains("See here"));
+    assert!(!html.contains("example.com"));
+    assert_eq!(html.matches("<hr>").count(), 2);
+  }
+
+  #[test]
+  fn subscribers_are_only_in_the_envelope() {
+    let subscribers = vec!["a@example.com".to_string(), "b@example.org".to_string()];
+    let email = EmailSink::email(&config(), DigestStyle::Summary, &items(), &subscribers).unwrap();
+
+    let envelope = email.envelope();
+    assert_eq!(envelope.from().map(ToString::to_string), Some("news@example.com".to_string()));
+    let to: Vec<String> = envelope.to().iter().map(ToString::to_string).collect();
+    assert!(to.contains(&"a@example.com".to_string()));
+    assert!(to.contains(&"b@example.org".to_string()));
+
+    let message = email.message_to_string().unwrap();
+    assert!(message.contains("Subject: Lodestone news: 2 items"));
+    assert!(message.contains("To: <news@example.com>"));
+    assert!(!message.contains("a@example.com"));
+    assert!(!message.contains("b@example.org"));
+  }
+
+  #[test]
+  fn no_subscribers_is_a_failure() {
+    crate::database::test_database();
+    let server = NewServer {
+      sink: "email".into(),
+      config: Some(r#"{"from":"news@example.com"}"#.into()),
+      ..NewServer::new("Mail", "smtp+insecure://127.0.0.1:1")
+    }.with_id(1);
+    match EmailSink::new().send(&items()[0], &server) {
+      Outcome::Failed(e) => assert_eq!(e, "there are no email subscribers"),
+      o => panic!("unexpected outcome {:?}", o),
+    }
+  }
+}
@@ -0,0 +1,151 @@
+use crate::{
+  database::models::news_item::{NewsItem, NewsKind},
+  lodestone::Field,
+};
+
+pub fn truncate(s: &str, max: usize) -> String {
+  if s.chars().count() <= max {
+    return s.to_string();
+  }
+  let mut truncated: String = s.chars().take(max - 1).collect();
+  truncated.push('…');
+  truncated
+}
+
+pub fn fields(item: &NewsItem) -> Vec<Field> {
+  item.fields.as_ref()
+    .and_then(|f| serde_json::from_str(f).ok())
+    .unwrap_or_default()
+}
+
+/// Rewrites the `[text](url)` links that the scraper produces, passing everything else through
+/// `text` so it can be escaped for the target format.
+pub fn rewrite_links<F, G>(s: &str, text: F, link: G) -> String
+  where F: Fn(&str) -> String,
+        G: Fn(&str, &str) -> String,
+{
+  let mut out = String::with_capacity(s.len());
+  // everything before `done` has been written, and links are looked for from `from`
+  let (mut done, mut from) = (0, 0);
+  while let Some(mid) = s[from..].find("](").map(|m| from + m) {
+    // the link text starts at the nearest bracket, so bracketed text before a link stays text
+    let open = s[done..mid].rfind('[').map(|o| done + o);
+    let close = match s[mid..].find(')') {
+      Some(c) => mid + c,
+      None => break,
+    };
+    match open {
+      Some(open) => {
+        out.push_str(&text(&s[done..open]));
+        out.push_str(&link(&s[open + 1..mid], &s[mid + 2..close]));
+        done = close + 1;
+        from = done;
+      },
+      None => from = mid + 2,
+    }
+  }
+  out.push_str(&text(&s[done..]));
+  out
+}
+
+/// Renders scraped text without markup, writing links as `text (url)`.
+pub fn plain(s: &str) -> String {
+  rewrite_links(s, ToString::to_string, |t, u| format!("{} ({})", t, u))
+}
+
+/// How urgent an item is, on ntfy's scale of 1 (min) to 5 (max).
+pub fn priority(item: &NewsItem) -> u8 {
+  let tag = item.tag.as_ref().map(|t| t.to_lowercase());
+  match tag.as_ref().map(String::as_str) {
+    Some("important") => return 5,
+    Some("maintenance") => return 4,
+    _ => {},
+  }
+  match item.kind {
+    NewsKind::SpecialNotice => 4,
+    NewsKind::News => 3,
+    NewsKind::Topic => 2,
+  }
+}
+
+pub fn escape_html(s: &str) -> String {
+  s.replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+/// Renders scraped text as HTML, turning newlines into `<br>` and links into anchors.
+pub fn html(s: &str) -> String {
+  rewrite_links(
+    s,
+    |t| escape_html(t).replace('\n', "<br>"),
+    |t, u| format!("<a href=\"{}\">{}</a>", escape_html(u), escape_html(t)),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn markdown(s: &str) -> String {
+    rewrite_links(s, |t| t.to_uppercase(), |t, u| format!("<{}|{}>", u, t))
+  }
+
+  #[test]
+  fn truncates_by_characters() {
+    assert_eq!(truncate("short", 5), "short");
+    assert_eq!(truncate("longer", 5), "long…");
+    assert_eq!(truncate("ああああああ", 3), "ああ…");
+  }
+
+  #[test]
+  fn rewrites_links_and_text_separately() {
+    assert_eq!(markdown("no links"), "NO LINKS");
+    assert_eq!(markdown("see [this](https://a.example) now"), "SEE <https://a.example|this> NOW");
+    assert_eq!(markdown("[a](u1)[b](u2)"), "<u1|a><u2|b>");
+    assert_eq!(markdown("[a](u1) and [b](u2)."), "<u1|a> AND <u2|b>.");
+  }
+
+  #[test]
+  fn leaves_incomplete_links_as_text() {
+    assert_eq!(markdown("[not a link"), "[NOT A LINK");
+    assert_eq!(markdown("[text] (u)"), "[TEXT] (U)");
+    assert_eq!(markdown("[text](u"), "[TEXT](U");
+    assert_eq!(markdown("[a](u1) then [b](u2"), "<u1|a> THEN [B](U2");
+  }
+
+  #[test]
+  fn bracketed_text_before_a_link_stays_text() {
+    assert_eq!(markdown("[Patch 5.1] see [here](u)"), "[PATCH 5.1] SEE <u|here>");
+    assert_eq!(markdown("[x] [a](u) [y]"), "[X] <u|a> [Y]");
+    assert_eq!(markdown("a](b) [c](u)"), "A](B) <u|c>");
+  }
+
+  #[test]
+  fn renders_plain_text() {
+    assert_eq!(plain("see [this](https://a.example)"), "see this (https://a.example)");
+  }
+
+  #[test]
+  fn renders_html() {
+    assert_eq!(
+      html("1 < 2\n[a & b](https://a.example/?x=\"1\"&y=2)"),
+      "1 &lt; 2<br><a href=\"https://a.example/?x=&quot;1&quot;&amp;y=2\">a &amp; b</a>",
+    );
+  }
+
+  #[test]
+  fn priority_follows_tag_then_kind() {
+    let item = |kind: NewsKind, tag: Option<&str>| NewsItem {
+      kind,
+      tag: tag.map(Into::into),
+      ..NewsItem::example(1, "Title")
+    };
+    assert_eq!(priority(&item(NewsKind::Topic, Some("Important"))), 5);
+    assert_eq!(priority(&item(NewsKind::News, Some("maintenance"))), 4);
+    assert_eq!(priority(&item(NewsKind::SpecialNotice, None)), 4);
+    assert_eq!(priority(&item(NewsKind::News, Some("Recovery"))), 3);
+    assert_eq!(priority(&item(NewsKind::Topic, None)), 2);
+  }
+}
@@ -0,0 +1,179 @@
+use reqwest::Client;
+
+use crate::{
+  database::models::{
+    news_item::NewsItem,
+    server::Server,
+  },
+  sink::{
+    Outcome,
+    Sink,
+    format::{self, escape_html},
+  },
+};
+
+pub struct GoogleChatSink {
+  client: Client,
+}
+
+impl Default for GoogleChatSink {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl GoogleChatSink {
+  pub fn new() -> Self {
+    GoogleChatSink {
+      client: Client::new(),
+    }
+  }
+
+  pub fn message(item: &NewsItem) -> serde_json::Value {
+    let mut footer = escape_html(&item.kind.to_string());
+    if let Some(ref tag) = item.tag {
+      footer.push_str(&format!(" · {}", escape_html(tag)));
+    }
+    // cards have no accent colour, but text widgets can colour their text
+    if let Some(color) = item.kind.color(item.tag.as_ref()) {
+      footer = format!("<font color=\"#{:06x}\">{}</font>", color, footer);
+    }
+
+    let mut widgets = vec![serde_json::json!({
+      "decoratedText": {
+        "text": footer,
+      },
+    })];
+    if let Some(ref image) = item.image {
+      widgets.push(serde_json::json!({
+        "image": {
+          "imageUrl": image,
+          "altText": item.title,
+        },
+      }));
+    }
+    if let Some(ref description) = item.description {
+      widgets.push(serde_json::json!({
+        "textParagraph": {
+          "text": format::html(description),
+        },
+      }));
+    }
+
+    let mut sections = vec![serde_json::json!({
+      "widgets": widgets,
+    })];
+
+    let fields: Vec<serde_json::Value> = format::fields(item).iter()
+      .map(|f| serde_json::json!({
+        "decoratedText": {
+          "topLabel": f.name,
+          "text": format::html(&f.value),
+          "wrapText": true,
+        },
+      }))
+      .collect();
+    if !fields.is_empty() {
+      sections.push(serde_json::json!({
+        "widgets": fields,
+      }));
+    }
+
+    sections.push(serde_json::json!({
+      "widgets": [{
+        "buttonList": {
+          "buttons": [{
+            "text": "Read on the Lodestone",
+            "onClick": {
+              "openLink": {
+                "url": item.url,
+              },
+            },
+          }],
+        },
+      }],
+    }));
+
+    serde_json::json!({
+      "cardsV2": [{
+        "cardId": format!("lodestone-{}", item.lodestone_id),
+        "card": {
+          "header": {
+            "title": item.title,
+          },
+          "sections": sections,
+        },
+      }],
+    })
+  }
+}
+
+impl Sink for GoogleChatSink {
+  fn send(&self, item: &NewsItem, destination: &Server) -> Outcome {
+    let res = self.client.post(&destination.url)
+      .json(&GoogleChatSink::message(item))
+      .send();
+    Outcome::from_response(res, |body| {
+      serde_json::from_str::<serde_json::Value>(body).ok()
+        .and_then(|v| v["name"].as_str().map(ToString::to_string))
+    })
+  }
+
+  fn preview(&self, item: &NewsItem, _destination: &Server) -> serde_json::Value {
+    GoogleChatSink::message(item)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::database::models::news_item::NewsKind;
+
+  #[test]
+  fn builds_a_card() {
+    let item = NewsItem {
+      kind: NewsKind::SpecialNotice,
+      tag: Some("Maintenance".into()),
+      description: Some("Worlds <1> & <2>, see [the notice](https://example.com).".into()),
+      fields: Some(r#"[{"name": "Date & Time", "value": "Oct. 8\n<PDT>"}]"#.into()),
+      image: Some("https://example.com/banner.png".into()),
+      ..NewsItem::example(1, "All Worlds Maintenance")
+    };
+    let message = GoogleChatSink::message(&item);
+    let card = &message["cardsV2"][0];
+    assert_eq!(card["cardId"], "lodestone-1");
+    assert_eq!(card["card"]["header"]["title"], "All Worlds Maintenance");
+
+    let sections = card["card"]["sections"].as_array().unwrap();
+    assert_eq!(sections.len(), 3);
+
+    let widgets = sections[0]["widgets"].as_array().unwrap();
+    assert_eq!(widgets.len(), 3);
+    assert_eq!(widgets[0]["decoratedText"]["text"], "<font color=\"#d3730c\">Special notice · Maintenance</font>");
+    assert_eq!(widgets[1]["image"], serde_json::json!({ "imageUrl": "https://example.com/banner.png", "altText": "All Worlds Maintenance" }));
+    assert_eq!(
+      widgets[2]["textParagraph"]["text"],
+      "Worlds &lt;1&gt; &amp; &lt;2&gt;, see <a href=\"https://example.com\">the notice</a>.",
+    );
+
+    assert_eq!(sections[1]["widgets"], serde_json::json!([{
+      "decoratedText": {
+        "topLabel": "Date & Time",
+        "text": "Oct. 8<br>&lt;PDT&gt;",
+        "wrapText": true,
+      },
+    }]));
+
+    let button = &sections[2]["widgets"][0]["buttonList"]["buttons"][0];
+    assert_eq!(button["onClick"]["openLink"]["url"], item.url.as_str());
+  }
+
+  #[test]
+  fn news_without_extras_is_uncoloured() {
+    let message = GoogleChatSink::message(&NewsItem::example(1, "Title"));
+    let sections = message["cardsV2"][0]["card"]["sections"].as_array().unwrap();
+    assert_eq!(sections.len(), 2);
+    assert_eq!(sections[0]["widgets"], serde_json::json!([{ "decoratedText": { "text": "News" } }]));
+  }
+}
@@ -0,0 +1,142 @@
+use reqwest::Client;
+
+use serde_derive::Deserialize;
+
+use crate::{
+  database::models::{
+    news_item::NewsItem,
+    server::Server,
+  },
+  errors::*,
+  sink::{
+    self,
+    Outcome,
+    Sink,
+    format,
+    ntfy::NtfySink,
+  },
+};
+
+#[derive(Debug, Deserialize)]
+struct GotifyConfig {
+  /// An application token.
+  token: String,
+}
+
+pub struct GotifySink {
+  client: Client,
+}
+
+impl Default for GotifySink {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl GotifySink {
+  pub fn new() -> Self {
+    GotifySink {
+      client: Client::new(),
+    }
+  }
+
+  pub fn message(item: &NewsItem) -> serde_json::Value {
+    let mut notification = serde_json::json!({
+      "click": {
+        "url": item.url,
+      },
+    });
+    if let Some(ref image) = item.image {
+      notification["bigImageUrl"] = image.as_str().into();
+    }
+
+    serde_json::json!({
+      "title": item.title,
+      "message": NtfySink::body(item),
+      // gotify's priorities run from 0 to 10 rather than 1 to 5
+      "priority": format::priority(item) * 2,
+      "extras": {
+        "client::notification": notification,
+      },
+    })
+  }
+}
+
+impl Sink for GotifySink {
+  fn check_config(&self, config: &str) -> Result<()> {
+    sink::check_config::<GotifyConfig>("gotify", config)
+  }
+
+  fn send(&self, item: &NewsItem, destination: &Server) -> Outcome {
+    let config: GotifyConfig = match destination.config() {
+      Ok(c) => c,
+      Err(e) => return Outcome::Failed(e.to_string()),
+    };
+
+    let res = self.client.post(&format!("{}/message", destination.url.trim_end_matches('/')))
+      .header("X-Gotify-Key", config.token)
+      .json(&GotifySink::message(item))
+      .send();
+    Outcome::from_response(res, |body| {
+      serde_json::from_str::<serde_json::Value>(body).ok()
+        .and_then(|v| v["id"].as_i64())
+        .map(|id| id.to_string())
+    })
+  }
+
+  fn preview(&self, item: &NewsItem, _destination: &Server) -> serde_json::Value {
+    GotifySink::message(item)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::database::models::{
+    news_item::NewsKind,
+    server::NewServer,
+  };
+
+  #[test]
+  fn extras_open_the_article_and_show_the_image() {
+    let item = NewsItem {
+      tag: Some("Important".into()),
+      image: Some("https://example.com/banner.png".into()),
+      ..NewsItem::example(1, "Login Issues")
+    };
+    let message = GotifySink::message(&item);
+    assert_eq!(message["title"], "Login Issues");
+    assert_eq!(message["priority"], 10);
+    assert_eq!(message["extras"], serde_json::json!({
+      "client::notification": {
+        "click": { "url": item.url },
+        "bigImageUrl": "https://example.com/banner.png",
+      },
+    }));
+
+    let message = GotifySink::message(&NewsItem { kind: NewsKind::Topic, ..NewsItem::example(2, "A Topic") });
+    assert_eq!(message["priority"], 4);
+    assert!(message["extras"]["client::notification"].get("bigImageUrl").is_none());
+  }
+
+  #[test]
+  fn sends_with_the_application_token() {
+    let (base, received) = sink::capture(vec![r#"{"id": 12}"#]);
+    let destination = NewServer {
+      sink: "gotify".into(),
+      config: Some(r#"{"token": "app_token"}"#.into()),
+      ..NewServer::new("Gotify", &format!("{}/", base))
+    }.with_id(6);
+
+    match GotifySink::new().send(&NewsItem::example(1, "Patch Notes"), &destination) {
+      Outcome::Sent(id) => assert_eq!(id, Some("12".to_string())),
+      o => panic!("unexpected outcome {:?}", o),
+    }
+
+    let request = received.join().unwrap().remove(0);
+    assert_eq!((request.method.as_str(), request.url.as_str()), ("POST", "/message"));
+    assert_eq!(request.header("X-Gotify-Key"), Some("app_token"));
+    assert_eq!(request.json()["extras"]["client::notification"]["click"]["url"], "https://na.finalfantasyxiv.com/lodestone/news/detail/1");
+  }
+}
@@ -0,0 +1,296 @@
+use chrono::Utc;
+
+use reqwest::{
+  Client,
+  StatusCode,
+  header::CONTENT_TYPE,
+  multipart::{Form, Part},
+};
+
+use serde_derive::Deserialize;
+
+use crate::{
+  database::models::{
+    news_item::NewsItem,
+    server::Server,
+  },
+  errors::*,
+  maintenance,
+  sink::{
+    self,
+    Outcome,
+    Sink,
+    format,
+  },
+};
+
+use std::{
+  thread,
+  time::Duration,
+};
+
+const MAX_STATUS: usize = 500;
+// mastodon counts every link as this many characters, however long it is
+const URL_LENGTH: usize = 23;
+const MAX_ALT_TEXT: usize = 1500;
+const MEDIA_POLLS: usize = 5;
+
+#[derive(Debug, Deserialize)]
+struct MastodonConfig {
+  access_token: String,
+  #[serde(default = "default_visibility")]
+  visibility: String,
+}
+
+fn default_visibility() -> String {
+  "public".into()
+}
+
+pub struct MastodonSink {
+  client: Client,
+}
+
+impl Default for MastodonSink {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl MastodonSink {
+  pub fn new() -> Self {
+    MastodonSink {
+      client: Client::new(),
+    }
+  }
+
+  /// The status text: the title and as much of the description as fits, then the article link.
+  pub fn status(item: &NewsItem) -> String {
+    let mut text = item.title.clone();
+    if let Some(ref description) = item.description {
+      // links in the description would each count as a full url, so only their text is kept
+      let description = format::rewrite_links(description, ToString::to_string, |t, _| t.to_string());
+      text.push_str(&format!("\n\n{}", description));
+    }
+    let text = format::truncate(&text, MAX_STATUS - URL_LENGTH - 2);
+    format!("{}\n\n{}", text, item.url)
+  }
+
+  /// Uploads an item's image, returning the media id.
+  fn upload(&self, base: &str, token: &str, item: &NewsItem, image: &str) -> Result<String> {
+    let mut res = self.client.get(image).send().chain_err(|| "could not download image")?;
+    if !res.status().is_success() {
+      return Err(format!("could not download image: {}", res.status()).into());
+    }
+    let mime = res.headers().get(CONTENT_TYPE)
+      .and_then(|h| h.to_str().ok())
+      .unwrap_or("image/jpeg")
+      .to_string();
+    let mut bytes = Vec::new();
+    res.copy_to(&mut bytes).chain_err(|| "could not download image")?;
+
+    let file_name = image.rsplit('/').next().unwrap_or("image").to_string();
+    let part = Part::bytes(bytes)
+      .file_name(file_name)
+      .mime_str(&mime)
+      .chain_err(|| format!("invalid image type: {}", mime))?;
+    let form = Form::new()
+      .part("file", part)
+      .text("description", format::truncate(&item.title, MAX_ALT_TEXT));
+
+    let mut res = self.client.post(&format!("{}/api/v2/media", base))
+      .bearer_auth(token)
+      .multipart(form)
+      .send()
+      .chain_err(|| "could not upload image")?;
+    if !res.status().is_success() {
+      return Err(format!("could not upload image: {}", res.status()).into());
+    }
+    let media: serde_json::Value = res.json().chain_err(|| "could not read media response")?;
+    let id = media["id"].as_str().ok_or("media response had no id")?.to_string();
+
+    // large files are processed in the background, and statuses can't use them until they're done
+    if res.status() == StatusCode::ACCEPTED {
+      for _ in 0..MEDIA_POLLS {
+        thread::sleep(Duration::from_secs(1));
+        let res = self.client.get(&format!("{}/api/v1/media/{}", base, id))
+          .bearer_auth(token)
+          .send()
+          .chain_err(|| "could not check image")?;
+        if res.status() == StatusCode::OK {
+          return Ok(id);
+        }
+      }
+      return Err("image was not processed in time".into());
+    }
+
+    Ok(id)
+  }
+
+  /// Posts an item as a status, sending `key` as the `Idempotency-Key`.
+  fn post(&self, item: &NewsItem, destination: &Server, key: &str) -> Outcome {
+    let config: MastodonConfig = match destination.config() {
+      Ok(c) => c,
+      Err(e) => return Outcome::Failed(e.to_string()),
+    };
+    let base = destination.url.trim_end_matches('/');
+
+    // a missing image shouldn't hold up the news itself
+    let media_ids: Vec<String> = match item.image {
+      Some(ref image) => match self.upload(base, &config.access_token, item, image) {
+        Ok(id) => vec![id],
+        Err(e) => {
+          warn!("Could not attach image for item {} on server {}: {}", item.id, destination.id, e);
+          Vec::new()
+        },
+      },
+      None => Vec::new(),
+    };
+
+    let mut data = serde_json::json!({
+      "status": MastodonSink::status(item),
+      "media_ids": media_ids,
+      "visibility": config.visibility,
+    });
+    if maintenance::is_maintenance(&item.title, item.tag.as_ref().map(String::as_str)) {
+      data["spoiler_text"] = "Maintenance".into();
+    }
+
+    let res = self.client.post(&format!("{}/api/v1/statuses", base))
+      .bearer_auth(&config.access_token)
+      .header("Idempotency-Key", key)
+      .json(&data)
+      .send();
+    Outcome::from_response(res, |body| {
+      serde_json::from_str::<serde_json::Value>(body).ok()
+        .and_then(|v| v["id"].as_str().map(ToString::to_string))
+    })
+  }
+}
+
+impl Sink for MastodonSink {
+  fn check_config(&self, config: &str) -> Result<()> {
+    sink::check_config::<MastodonConfig>("mastodon", config)
+  }
+
+  fn preview(&self, item: &NewsItem, _destination: &Server) -> serde_json::Value {
+    serde_json::json!({
+      "status": MastodonSink::status(item),
+      "media": item.image,
+    })
+  }
+
+  fn send(&self, item: &NewsItem, destination: &Server) -> Outcome {
+    // retries of the same delivery create a single status
+    self.post(item, destination, &format!("lodestone_news-{}-{}", destination.id, item.id))
+  }
+
+  // a resend needs its own key, or mastodon would return the first status instead of posting
+  fn resend(&self, item: &NewsItem, destination: &Server) -> Outcome {
+    let key = format!("lodestone_news-{}-{}-{}", destination.id, item.id, Utc::now().timestamp_millis());
+    self.post(item, destination, &key)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::database::models::server::NewServer;
+
+  fn destination(base: &str) -> Server {
+    NewServer {
+      sink: "mastodon".into(),
+      config: Some(r#"{"access_token": "secret", "visibility": "unlisted"}"#.into()),
+      ..NewServer::new("Mastodon", base)
+    }.with_id(4)
+  }
+
+  #[test]
+  fn status_fits_in_500_characters() {
+    let item = NewsItem {
+      description: Some(format!("See [the notes](https://example.com/{}). {}", "x".repeat(100), "Long text. ".repeat(60))),
+      ..NewsItem::example(1, &"Long title ".repeat(10))
+    };
+    let status = MastodonSink::status(&item);
+    let link = format!("\n\n{}", item.url);
+    assert!(status.ends_with(&link));
+
+    let text = &status[..status.len() - link.len()];
+    // the link counts as 23 characters whatever its length
+    assert_eq!(text.chars().count() + 2 + URL_LENGTH, MAX_STATUS);
+    assert!(text.ends_with('…'));
+    assert!(text.contains("See the notes. Long text."), "{}", text);
+    assert!(!text.contains("https://"));
+  }
+
+  #[test]
+  fn short_statuses_are_kept_whole() {
+    let item = NewsItem {
+      description: Some("Short.".into()),
+      ..NewsItem::example(1, "Title")
+    };
+    assert_eq!(MastodonSink::status(&item), format!("Title\n\nShort.\n\n{}", item.url));
+  }
+
+  #[test]
+  fn maintenance_is_behind_a_content_warning() {
+    let item = NewsItem {
+      tag: Some("Maintenance".into()),
+      ..NewsItem::example(1, "All Worlds Maintenance")
+    };
+    let (base, received) = sink::capture(vec![r#"{"id": "100"}"#, r#"{"id": "101"}"#]);
+    let sink = MastodonSink::new();
+
+    match sink.send(&item, &destination(&base)) {
+      Outcome::Sent(id) => assert_eq!(id, Some("100".to_string())),
+      o => panic!("unexpected outcome {:?}", o),
+    }
+    assert!(sink.send(&NewsItem::example(2, "Patch Notes"), &destination(&base)).is_sent());
+
+    let requests = received.join().unwrap();
+    assert_eq!(requests[0].url, "/api/v1/statuses");
+    assert_eq!(requests[0].header("Authorization"), Some("Bearer secret"));
+    assert_eq!(requests[0].header("Idempotency-Key"), Some("lodestone_news-4-1"));
+    let status = requests[0].json();
+    assert_eq!(status["spoiler_text"], "Maintenance");
+    assert_eq!(status["visibility"], "unlisted");
+    assert_eq!(status["media_ids"], serde_json::json!([]));
+    assert!(requests[1].json().get("spoiler_text").is_none());
+  }
+
+  #[test]
+  fn images_are_uploaded_first() {
+    let (base, received) = sink::capture(vec!["image bytes", r#"{"id": "42"}"#, r#"{"id": "100"}"#]);
+    let item = NewsItem {
+      image: Some(format!("{}/images/banner.png", base)),
+      ..NewsItem::example(1, "Patch Notes")
+    };
+
+    assert!(MastodonSink::new().send(&item, &destination(&base)).is_sent());
+
+    let requests = received.join().unwrap();
+    assert_eq!((requests[0].method.as_str(), requests[0].url.as_str()), ("GET", "/images/banner.png"));
+    assert_eq!((requests[1].method.as_str(), requests[1].url.as_str()), ("POST", "/api/v2/media"));
+    assert_eq!(requests[1].header("Authorization"), Some("Bearer secret"));
+    let upload = String::from_utf8_lossy(&requests[1].body);
+    assert!(upload.contains("filename=\"banner.png\""), "{}", upload);
+    assert!(upload.contains("image bytes"));
+    assert!(upload.contains("Patch Notes"));
+    assert_eq!((requests[2].method.as_str(), requests[2].url.as_str()), ("POST", "/api/v1/statuses"));
+    assert_eq!(requests[2].json()["media_ids"], serde_json::json!(["42"]));
+  }
+
+  #[test]
+  fn resends_use_a_new_idempotency_key() {
+    let item = NewsItem::example(1, "Patch Notes");
+    let (base, received) = sink::capture(vec!["{}", "{}"]);
+    let sink = MastodonSink::new();
+    assert!(sink.send(&item, &destination(&base)).is_sent());
+    assert!(sink.resend(&item, &destination(&base)).is_sent());
+
+    let requests = received.join().unwrap();
+    assert_eq!(requests[0].header("Idempotency-Key"), Some("lodestone_news-4-1"));
+    let key = requests[1].header("Idempotency-Key").unwrap();
+    assert!(key.starts_with("lodestone_news-4-1-"), "{}", key);
+  }
+}
@@ -0,0 +1,191 @@
+use chrono::Utc;
+
+use reqwest::{Client, Url};
+
+use serde_derive::Deserialize;
+
+use crate::{
+  database::models::{
+    news_item::NewsItem,
+    server::Server,
+  },
+  errors::*,
+  sink::{
+    self,
+    Outcome,
+    Sink,
+    format::{self, escape_html},
+  },
+};
+
+#[derive(Debug, Deserialize)]
+struct MatrixConfig {
+  access_token: String,
+  room_id: String,
+}
+
+pub struct MatrixSink {
+  client: Client,
+}
+
+impl Default for MatrixSink {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl MatrixSink {
+  pub fn new() -> Self {
+    MatrixSink {
+      client: Client::new(),
+    }
+  }
+
+  // the same server and item always map to the same transaction, so the homeserver drops retries
+  fn transaction_id(server_id: i32, news_id: i32) -> String {
+    format!("lodestone_news.{}.{}", server_id, news_id)
+  }
+
+  fn put(&self, item: &NewsItem, destination: &Server, txn_id: &str) -> Outcome {
+    let config: MatrixConfig = match destination.config() {
+      Ok(c) => c,
+      Err(e) => return Outcome::Failed(e.to_string()),
+    };
+    let url = match MatrixSink::send_url(&destination.url, &config.room_id, txn_id) {
+      Some(u) => u,
+      None => return Outcome::Failed(format!("invalid homeserver url: {}", destination.url)),
+    };
+
+    let res = self.client.put(url)
+      .bearer_auth(&config.access_token)
+      .json(&MatrixSink::message(item))
+      .send();
+    Outcome::from_response(res, |body| {
+      serde_json::from_str::<serde_json::Value>(body).ok()
+        .and_then(|v| v["event_id"].as_str().map(ToString::to_string))
+    })
+  }
+
+  fn send_url(homeserver: &str, room_id: &str, txn_id: &str) -> Option<Url> {
+    let mut url = Url::parse(homeserver).ok()?;
+    url.path_segments_mut().ok()?
+      .pop_if_empty()
+      .extend(&["_matrix", "client", "r0", "rooms", room_id, "send", "m.room.message", txn_id]);
+    Some(url)
+  }
+
+  pub fn message(item: &NewsItem) -> serde_json::Value {
+    let fields = format::fields(item);
+
+    let mut body = format!("{}\n{}", item.title, item.url);
+    let mut html = format!("<h4><a href=\"{}\">{}</a></h4>", escape_html(&item.url), escape_html(&item.title));
+
+    if let Some(ref description) = item.description {
+      body.push_str(&format!("\n\n{}", description));
+      html.push_str(&format!("<p>{}</p>", format::html(description)));
+    }
+    for field in &fields {
+      body.push_str(&format!("\n\n{}\n{}", field.name, field.value));
+      html.push_str(&format!("<p><strong>{}</strong><br>{}</p>", escape_html(&field.name), format::html(&field.value)));
+    }
+
+    let mut footer = item.kind.to_string();
+    if let Some(ref tag) = item.tag {
+      footer.push_str(&format!(" · {}", tag));
+    }
+    body.push_str(&format!("\n\n{}", footer));
+    html.push_str(&format!("<p><em>{}</em></p>", escape_html(&footer)));
+
+    // clients only display inline images from mxc:// urls, so link to the image instead
+    if let Some(ref image) = item.image {
+      body.push_str(&format!("\n{}", image));
+      html.push_str(&format!("<p><a href=\"{}\">View image</a></p>", escape_html(image)));
+    }
+
+    serde_json::json!({
+      "msgtype": "m.text",
+      "body": body,
+      "format": "org.matrix.custom.html",
+      "formatted_body": html,
+    })
+  }
+}
+
+impl Sink for MatrixSink {
+  fn check_config(&self, config: &str) -> Result<()> {
+    sink::check_config::<MatrixConfig>("matrix", config)
+  }
+
+  fn send(&self, item: &NewsItem, destination: &Server) -> Outcome {
+    self.put(item, destination, &MatrixSink::transaction_id(destination.id, item.id))
+  }
+
+  // a resend is a new transaction, or the homeserver would return the first message's event
+  fn resend(&self, item: &NewsItem, destination: &Server) -> Outcome {
+    let txn_id = format!("{}.{}", MatrixSink::transaction_id(destination.id, item.id), Utc::now().timestamp_millis());
+    self.put(item, destination, &txn_id)
+  }
+
+  fn preview(&self, item: &NewsItem, _destination: &Server) -> serde_json::Value {
+    MatrixSink::message(item)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::database::models::server::NewServer;
+
+  fn destination(homeserver: &str) -> Server {
+    NewServer {
+      sink: "matrix".into(),
+      config: Some(r#"{"access_token": "secret", "room_id": "!room:example.org"}"#.into()),
+      ..NewServer::new("Matrix", homeserver)
+    }.with_id(3)
+  }
+
+  #[test]
+  fn sends_to_the_room() {
+    let item = NewsItem {
+      description: Some("<b>1</b> & [more](https://example.com/?a&b)".into()),
+      fields: Some(r#"[{"name": "Date <UTC>", "value": "Oct. 8 & 9"}]"#.into()),
+      image: Some("https://example.com/a.png?x&y".into()),
+      ..NewsItem::example(7, "Patch <5.1> & more")
+    };
+    let (homeserver, received) = sink::capture(vec![r#"{"event_id": "$event"}"#]);
+
+    match MatrixSink::new().send(&item, &destination(&homeserver)) {
+      Outcome::Sent(id) => assert_eq!(id, Some("$event".to_string())),
+      o => panic!("unexpected outcome {:?}", o),
+    }
+
+    let request = received.join().unwrap().remove(0);
+    assert_eq!(request.method, "PUT");
+    assert_eq!(request.url, "/_matrix/client/r0/rooms/!room:example.org/send/m.room.message/lodestone_news.3.7");
+    assert_eq!(request.header("Authorization"), Some("Bearer secret"));
+
+    let message = request.json();
+    assert_eq!(message["msgtype"], "m.text");
+    let html = message["formatted_body"].as_str().unwrap();
+    assert!(html.contains(">Patch &lt;5.1&gt; &amp; more</a></h4>"), "{}", html);
+    assert!(html.contains("<p>&lt;b&gt;1&lt;/b&gt; &amp; <a href=\"https://example.com/?a&amp;b\">more</a></p>"), "{}", html);
+    assert!(html.contains("<p><strong>Date &lt;UTC&gt;</strong><br>Oct. 8 &amp; 9</p>"), "{}", html);
+    assert!(html.contains("<a href=\"https://example.com/a.png?x&amp;y\">View image</a>"), "{}", html);
+  }
+
+  #[test]
+  fn resends_are_new_transactions() {
+    let item = NewsItem::example(7, "Title");
+    let (homeserver, received) = sink::capture(vec!["{}", "{}"]);
+    let sink = MatrixSink::new();
+    assert!(sink.resend(&item, &destination(&homeserver)).is_sent());
+    std::thread::sleep(std::time::Duration::from_millis(2));
+    assert!(sink.resend(&item, &destination(&homeserver)).is_sent());
+
+    let urls: Vec<String> = received.join().unwrap().into_iter().map(|r| r.url).collect();
+    let prefix = "/_matrix/client/r0/rooms/!room:example.org/send/m.room.message/lodestone_news.3.7.";
+    assert!(urls.iter().all(|u| u.starts_with(prefix)), "{:?}", urls);
+    assert_ne!(urls[0], urls[1]);
+  }
+}
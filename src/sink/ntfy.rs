@@ -0,0 +1,176 @@
+use reqwest::Client;
+
+use serde_derive::Deserialize;
+
+use crate::{
+  database::models::{
+    news_item::NewsItem,
+    server::Server,
+  },
+  errors::*,
+  sink::{
+    self,
+    Outcome,
+    Sink,
+    format::{self, truncate},
+  },
+};
+
+// ntfy turns messages over 4096 bytes into attachments, and a character is at most four bytes
+const MAX_MESSAGE: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+struct NtfyConfig {
+  topic: String,
+  token: Option<String>,
+}
+
+pub struct NtfySink {
+  client: Client,
+}
+
+impl Default for NtfySink {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl NtfySink {
+  pub fn new() -> Self {
+    NtfySink {
+      client: Client::new(),
+    }
+  }
+
+  /// The plain text body shared by push notifications: the description followed by the fields.
+  pub fn body(item: &NewsItem) -> String {
+    let mut parts: Vec<String> = item.description.iter()
+      .map(|d| format::plain(d))
+      .collect();
+    parts.extend(format::fields(item).iter().map(|f| format!("{}: {}", f.name, format::plain(&f.value))));
+    if parts.is_empty() {
+      return item.url.clone();
+    }
+    truncate(&parts.join("\n\n"), MAX_MESSAGE)
+  }
+
+  pub fn message(topic: &str, item: &NewsItem) -> serde_json::Value {
+    let mut tags = vec![item.kind.as_str().to_string()];
+    tags.extend(item.tag.as_ref().map(|t| t.to_lowercase()));
+
+    let mut message = serde_json::json!({
+      "topic": topic,
+      "title": item.title,
+      "message": NtfySink::body(item),
+      "priority": format::priority(item),
+      "tags": tags,
+      "click": item.url,
+    });
+    if let Some(ref image) = item.image {
+      message["attach"] = image.as_str().into();
+    }
+    message
+  }
+}
+
+impl Sink for NtfySink {
+  fn check_config(&self, config: &str) -> Result<()> {
+    sink::check_config::<NtfyConfig>("ntfy", config)
+  }
+
+  fn send(&self, item: &NewsItem, destination: &Server) -> Outcome {
+    let config: NtfyConfig = match destination.config() {
+      Ok(c) => c,
+      Err(e) => return Outcome::Failed(e.to_string()),
+    };
+
+    // json messages are published to the server root, with the topic in the body
+    let mut req = self.client.post(destination.url.trim_end_matches('/'))
+      .json(&NtfySink::message(&config.topic, item));
+    if let Some(ref token) = config.token {
+      req = req.bearer_auth(token);
+    }
+    Outcome::from_response(req.send(), |body| {
+      serde_json::from_str::<serde_json::Value>(body).ok()
+        .and_then(|v| v["id"].as_str().map(ToString::to_string))
+    })
+  }
+
+  fn preview(&self, item: &NewsItem, destination: &Server) -> serde_json::Value {
+    let topic = destination.config::<NtfyConfig>().map(|c| c.topic).unwrap_or_default();
+    NtfySink::message(&topic, item)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::database::models::{
+    news_item::NewsKind,
+    server::NewServer,
+  };
+
+  fn destination(base: &str, config: &str) -> Server {
+    NewServer {
+      sink: "ntfy".into(),
+      config: Some(config.into()),
+      ..NewServer::new("ntfy", base)
+    }.with_id(5)
+  }
+
+  #[test]
+  fn publishes_priority_click_and_attachment() {
+    let item = NewsItem {
+      kind: NewsKind::SpecialNotice,
+      tag: Some("Maintenance".into()),
+      description: Some("See [the notice](https://example.com).".into()),
+      fields: Some(r#"[{"name": "Date", "value": "Oct. 8"}]"#.into()),
+      image: Some("https://example.com/banner.png".into()),
+      ..NewsItem::example(1, "All Worlds Maintenance")
+    };
+    let (base, received) = sink::capture(vec![r#"{"id": "abc"}"#]);
+
+    let outcome = NtfySink::new().send(&item, &destination(&format!("{}/", base), r#"{"topic": "ffxiv", "token": "tk_secret"}"#));
+    match outcome {
+      Outcome::Sent(id) => assert_eq!(id, Some("abc".to_string())),
+      o => panic!("unexpected outcome {:?}", o),
+    }
+
+    let request = received.join().unwrap().remove(0);
+    assert_eq!((request.method.as_str(), request.url.as_str()), ("POST", "/"));
+    assert_eq!(request.header("Authorization"), Some("Bearer tk_secret"));
+    let message = request.json();
+    assert_eq!(message["topic"], "ffxiv");
+    assert_eq!(message["title"], "All Worlds Maintenance");
+    assert_eq!(message["message"], "See the notice (https://example.com).\n\nDate: Oct. 8");
+    assert_eq!(message["priority"], 4);
+    assert_eq!(message["tags"], serde_json::json!(["special_notice", "maintenance"]));
+    assert_eq!(message["click"], item.url.as_str());
+    assert_eq!(message["attach"], "https://example.com/banner.png");
+  }
+
+  #[test]
+  fn plain_items_have_no_attachment() {
+    let (base, received) = sink::capture(vec!["{}"]);
+    let item = NewsItem::example(1, "Patch Notes");
+    assert!(NtfySink::new().send(&item, &destination(&base, r#"{"topic": "ffxiv"}"#)).is_sent());
+
+    let request = received.join().unwrap().remove(0);
+    assert_eq!(request.header("Authorization"), None);
+    let message = request.json();
+    // with nothing else to say, the body is the link
+    assert_eq!(message["message"], item.url.as_str());
+    assert_eq!(message["priority"], 3);
+    assert!(message.get("attach").is_none());
+  }
+
+  #[test]
+  fn long_bodies_are_truncated() {
+    let item = NewsItem {
+      description: Some("あ".repeat(2000)),
+      ..NewsItem::example(1, "Title")
+    };
+    assert_eq!(NtfySink::body(&item).chars().count(), MAX_MESSAGE);
+  }
+}
@@ -0,0 +1,167 @@
+use reqwest::Client;
+
+use crate::{
+  database::models::{
+    news_item::NewsItem,
+    server::Server,
+  },
+  sink::{
+    Outcome,
+    Sink,
+    format::{self, truncate},
+  },
+};
+
+// Block Kit limits
+const MAX_HEADER: usize = 150;
+const MAX_TEXT: usize = 3000;
+const MAX_FIELD: usize = 2000;
+const MAX_FIELDS: usize = 10;
+
+pub struct SlackSink {
+  client: Client,
+}
+
+impl Default for SlackSink {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl SlackSink {
+  pub fn new() -> Self {
+    SlackSink {
+      client: Client::new(),
+    }
+  }
+
+  pub fn blocks(item: &NewsItem) -> serde_json::Value {
+    let mut blocks = vec![serde_json::json!({
+      "type": "header",
+      "text": {
+        "type": "plain_text",
+        "text": truncate(&item.title, MAX_HEADER),
+      },
+    })];
+
+    if let Some(ref description) = item.description {
+      blocks.push(serde_json::json!({
+        "type": "section",
+        "text": {
+          "type": "mrkdwn",
+          "text": truncate(&mrkdwn(description), MAX_TEXT),
+        },
+      }));
+    }
+
+    let fields: Vec<serde_json::Value> = format::fields(item).iter()
+      .take(MAX_FIELDS)
+      .map(|f| serde_json::json!({
+        "type": "mrkdwn",
+        "text": truncate(&format!("*{}*\n{}", escape(&f.name), mrkdwn(&f.value)), MAX_FIELD),
+      }))
+      .collect();
+    if !fields.is_empty() {
+      blocks.push(serde_json::json!({
+        "type": "section",
+        "fields": fields,
+      }));
+    }
+
+    if let Some(ref image) = item.image {
+      blocks.push(serde_json::json!({
+        "type": "image",
+        "image_url": image,
+        "alt_text": truncate(&item.title, MAX_FIELD),
+      }));
+    }
+
+    let mut context = item.kind.to_string();
+    if let Some(ref tag) = item.tag {
+      context.push_str(" · ");
+      context.push_str(&escape(tag));
+    }
+    context.push_str(&format!(" · <{}|Read on the Lodestone>", item.url));
+    blocks.push(serde_json::json!({
+      "type": "context",
+      "elements": [{
+        "type": "mrkdwn",
+        "text": context,
+      }],
+    }));
+
+    serde_json::json!({
+      "text": item.title,
+      "blocks": blocks,
+    })
+  }
+}
+
+impl Sink for SlackSink {
+  fn send(&self, item: &NewsItem, destination: &Server) -> Outcome {
+    let res = self.client.post(&destination.url)
+      .json(&SlackSink::blocks(item))
+      .send();
+    Outcome::from_response(res, |_| None)
+  }
+
+  fn preview(&self, item: &NewsItem, _destination: &Server) -> serde_json::Value {
+    SlackSink::blocks(item)
+  }
+}
+
+fn escape(s: &str) -> String {
+  s.replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+}
+
+fn mrkdwn(s: &str) -> String {
+  format::rewrite_links(s, escape, |text, url| format!("<{}|{}>", url, escape(text)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::database::models::news_item::NewsKind;
+
+  #[test]
+  fn builds_blocks() {
+    let item = NewsItem {
+      kind: NewsKind::SpecialNotice,
+      tag: Some("Maintenance & <Recovery>".into()),
+      description: Some("Worlds <1> & <2> are down, see [the notice](https://example.com/a?b&c).".into()),
+      fields: Some(r#"[{"name": "Date & Time", "value": "Oct. 8 <PDT>"}]"#.into()),
+      image: Some("https://example.com/banner.png".into()),
+      ..NewsItem::example(1, "All Worlds Maintenance")
+    };
+    let payload = SlackSink::blocks(&item);
+    assert_eq!(payload["text"], "All Worlds Maintenance");
+
+    let blocks = payload["blocks"].as_array().unwrap();
+    let types: Vec<&str> = blocks.iter().map(|b| b["type"].as_str().unwrap()).collect();
+    assert_eq!(types, vec!["header", "section", "section", "image", "context"]);
+
+    assert_eq!(blocks[0]["text"]["text"], "All Worlds Maintenance");
+    assert_eq!(blocks[1]["text"]["type"], "mrkdwn");
+    assert_eq!(
+      blocks[1]["text"]["text"],
+      "Worlds &lt;1&gt; &amp; &lt;2&gt; are down, see <https://example.com/a?b&c|the notice>.",
+    );
+    assert_eq!(blocks[2]["fields"][0]["text"], "*Date &amp; Time*\nOct. 8 &lt;PDT&gt;");
+    assert_eq!(blocks[3]["image_url"], "https://example.com/banner.png");
+    assert_eq!(blocks[3]["alt_text"], "All Worlds Maintenance");
+    assert_eq!(
+      blocks[4]["elements"][0]["text"],
+      "Special notice · Maintenance &amp; &lt;Recovery&gt; · <https://na.finalfantasyxiv.com/lodestone/news/detail/1|Read on the Lodestone>",
+    );
+  }
+
+  #[test]
+  fn leaves_out_empty_blocks() {
+    let blocks = SlackSink::blocks(&NewsItem::example(1, "Title"));
+    let types: Vec<&str> = blocks["blocks"].as_array().unwrap().iter().map(|b| b["type"].as_str().unwrap()).collect();
+    assert_eq!(types, vec!["header", "context"]);
+  }
+}
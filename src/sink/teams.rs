@@ -0,0 +1,206 @@
+use reqwest::Client;
+
+use crate::{
+  database::models::{
+    news_item::NewsItem,
+    server::Server,
+  },
+  sink::{
+    Outcome,
+    Sink,
+    format,
+  },
+};
+
+pub struct TeamsSink {
+  client: Client,
+}
+
+impl Default for TeamsSink {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl TeamsSink {
+  pub fn new() -> Self {
+    TeamsSink {
+      client: Client::new(),
+    }
+  }
+
+  pub fn card(item: &NewsItem) -> serde_json::Value {
+    let mut footer = item.kind.to_string();
+    if let Some(ref tag) = item.tag {
+      footer.push_str(&format!(" · {}", tag));
+    }
+
+    let mut body = vec![serde_json::json!({
+      "type": "Container",
+      "style": style(item.kind.color(item.tag.as_ref())),
+      "bleed": true,
+      "items": [
+        {
+          "type": "TextBlock",
+          "text": item.title,
+          "size": "Large",
+          "weight": "Bolder",
+          "wrap": true,
+        },
+        {
+          "type": "TextBlock",
+          "text": footer,
+          "isSubtle": true,
+          "spacing": "None",
+        },
+      ],
+    })];
+
+    if let Some(ref image) = item.image {
+      body.push(serde_json::json!({
+        "type": "Image",
+        "url": image,
+        "altText": item.title,
+        "size": "Stretch",
+      }));
+    }
+
+    // the scraper's [text](url) links are already the markdown that text blocks understand
+    if let Some(ref description) = item.description {
+      body.push(serde_json::json!({
+        "type": "TextBlock",
+        "text": description,
+        "wrap": true,
+      }));
+    }
+
+    let facts: Vec<serde_json::Value> = format::fields(item).into_iter()
+      .map(|f| serde_json::json!({
+        "title": f.name,
+        "value": f.value,
+      }))
+      .collect();
+    if !facts.is_empty() {
+      body.push(serde_json::json!({
+        "type": "FactSet",
+        "facts": facts,
+      }));
+    }
+
+    serde_json::json!({
+      "type": "message",
+      "attachments": [{
+        "contentType": "application/vnd.microsoft.card.adaptive",
+        "content": {
+          "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+          "type": "AdaptiveCard",
+          "version": "1.4",
+          "body": body,
+          "actions": [{
+            "type": "Action.OpenUrl",
+            "title": "Read on the Lodestone",
+            "url": item.url,
+          }],
+          "msteams": {
+            "width": "Full",
+          },
+        },
+      }],
+    })
+  }
+}
+
+impl Sink for TeamsSink {
+  fn send(&self, item: &NewsItem, destination: &Server) -> Outcome {
+    let res = self.client.post(&destination.url)
+      .json(&TeamsSink::card(item))
+      .send();
+    Outcome::from_response(res, |_| None)
+  }
+
+  fn preview(&self, item: &NewsItem, _destination: &Server) -> serde_json::Value {
+    TeamsSink::card(item)
+  }
+}
+
+/// Adaptive Cards only have named container styles, so pick the one closest to a colour.
+fn style(color: Option<u32>) -> &'static str {
+  let color = match color {
+    Some(c) => c,
+    None => return "default",
+  };
+  let (r, g, b) = ((color >> 16) & 0xff, (color >> 8) & 0xff, color & 0xff);
+  if r > g && r > b {
+    if g >= r / 2 {
+      "warning"
+    } else {
+      "attention"
+    }
+  } else if g > r && g > b + 0x40 {
+    "good"
+  } else {
+    "accent"
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::database::models::news_item::NewsKind;
+
+  #[test]
+  fn builds_an_adaptive_card() {
+    let item = NewsItem {
+      kind: NewsKind::SpecialNotice,
+      tag: Some("Maintenance".into()),
+      description: Some("Worlds will be down, see [the notice](https://example.com).".into()),
+      fields: Some(r#"[{"name": "Date & Time", "value": "Oct. 8"}, {"name": "Affected Service", "value": "All Worlds"}]"#.into()),
+      image: Some("https://example.com/banner.png".into()),
+      ..NewsItem::example(1, "All Worlds Maintenance")
+    };
+    let message = TeamsSink::card(&item);
+    assert_eq!(message["type"], "message");
+    let attachment = &message["attachments"][0];
+    assert_eq!(attachment["contentType"], "application/vnd.microsoft.card.adaptive");
+
+    let card = &attachment["content"];
+    assert_eq!(card["type"], "AdaptiveCard");
+    assert_eq!(card["version"], "1.4");
+    assert_eq!(card["actions"][0]["type"], "Action.OpenUrl");
+    assert_eq!(card["actions"][0]["url"], item.url.as_str());
+
+    let body = card["body"].as_array().unwrap();
+    let types: Vec<&str> = body.iter().map(|b| b["type"].as_str().unwrap()).collect();
+    assert_eq!(types, vec!["Container", "Image", "TextBlock", "FactSet"]);
+    assert_eq!(body[0]["style"], "warning");
+    assert_eq!(body[0]["items"][0]["text"], "All Worlds Maintenance");
+    assert_eq!(body[0]["items"][1]["text"], "Special notice · Maintenance");
+    assert_eq!(body[1]["url"], "https://example.com/banner.png");
+    assert_eq!(body[2]["text"], "Worlds will be down, see [the notice](https://example.com).");
+    assert_eq!(body[3]["facts"], serde_json::json!([
+      { "title": "Date & Time", "value": "Oct. 8" },
+      { "title": "Affected Service", "value": "All Worlds" },
+    ]));
+  }
+
+  #[test]
+  fn leaves_out_empty_parts() {
+    let card = TeamsSink::card(&NewsItem::example(1, "Title"));
+    let body = card["attachments"][0]["content"]["body"].as_array().unwrap();
+    assert_eq!(body.len(), 1);
+    assert_eq!(body[0]["style"], "default");
+  }
+
+  #[test]
+  fn styles_follow_the_embed_colour() {
+    let styled = |kind: NewsKind, tag: Option<&str>| style(kind.color(tag.map(ToString::to_string)));
+    assert_eq!(styled(NewsKind::News, Some("Maintenance")), "warning");
+    assert_eq!(styled(NewsKind::News, Some("Important")), "attention");
+    assert_eq!(styled(NewsKind::News, Some("Recovery")), "good");
+    assert_eq!(styled(NewsKind::News, Some("Follow-up")), "accent");
+    assert_eq!(styled(NewsKind::SpecialNotice, None), "accent");
+    assert_eq!(styled(NewsKind::Topic, None), "accent");
+    assert_eq!(styled(NewsKind::News, None), "default");
+  }
+}
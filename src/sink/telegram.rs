@@ -0,0 +1,232 @@
+use reqwest::Client;
+
+use serde_derive::Deserialize;
+
+use crate::{
+  database::models::{
+    news_item::NewsItem,
+    server::Server,
+  },
+  errors::*,
+  sink::{
+    self,
+    Outcome,
+    Sink,
+    format::{self, escape_html},
+  },
+};
+
+// telegram counts these limits in utf-16 code units of the text left after parsing the html
+const MAX_CAPTION: usize = 1024;
+const MAX_MESSAGE: usize = 4096;
+
+#[derive(Debug, Deserialize)]
+struct TelegramConfig {
+  bot_token: String,
+  chat_id: String,
+}
+
+pub struct TelegramSink {
+  client: Client,
+}
+
+impl Default for TelegramSink {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl TelegramSink {
+  pub fn new() -> Self {
+    TelegramSink {
+      client: Client::new(),
+    }
+  }
+
+  /// Renders an item as Telegram HTML whose visible text fits in `limit`.
+  ///
+  /// Lengths are measured on the unrendered text, which is never shorter than what Telegram
+  /// counts, so truncation happens before any tags are added and can't split one.
+  pub fn message(item: &NewsItem, limit: usize) -> String {
+    let mut footer = item.kind.to_string();
+    if let Some(ref tag) = item.tag {
+      footer.push_str(&format!(" · {}", tag));
+    }
+    let footer = truncate(&footer, limit / 4);
+    let title = truncate(&item.title, limit - utf16_len(&footer) - 2);
+    let mut remaining = limit - utf16_len(&footer) - 2 - utf16_len(&title);
+
+    let mut text = format!("<b><a href=\"{}\">{}</a></b>", escape_html(&item.url), escape_html(&title));
+
+    let mut parts: Vec<(Option<&str>, &str)> = Vec::new();
+    if let Some(ref description) = item.description {
+      parts.push((None, description));
+    }
+    let fields = format::fields(item);
+    parts.extend(fields.iter().map(|f| (Some(f.name.as_str()), f.value.as_str())));
+
+    for (name, value) in parts {
+      let name_len = name.map(|n| utf16_len(n) + 1).unwrap_or(0);
+      // each part is separated from the last by a blank line
+      if remaining < 2 + name_len + 2 {
+        break;
+      }
+      let value_budget = remaining - 2 - name_len;
+      let value = truncate(value, value_budget);
+      remaining -= 2 + name_len + utf16_len(&value);
+
+      text.push_str("\n\n");
+      if let Some(name) = name {
+        text.push_str(&format!("<b>{}</b>\n", escape_html(name)));
+      }
+      text.push_str(&format::rewrite_links(
+        &value,
+        escape_html,
+        |t, u| format!("<a href=\"{}\">{}</a>", escape_html(u), escape_html(t)),
+      ));
+    }
+
+    text.push_str(&format!("\n\n<i>{}</i>", escape_html(&footer)));
+    text
+  }
+
+  /// The Bot API method and body for an item: a photo with a caption if it has an image.
+  fn request(item: &NewsItem, chat_id: &str) -> (&'static str, serde_json::Value) {
+    match item.image {
+      Some(ref image) => ("sendPhoto", serde_json::json!({
+        "chat_id": chat_id,
+        "photo": image,
+        "caption": TelegramSink::message(item, MAX_CAPTION),
+        "parse_mode": "HTML",
+      })),
+      None => ("sendMessage", serde_json::json!({
+        "chat_id": chat_id,
+        "text": TelegramSink::message(item, MAX_MESSAGE),
+        "parse_mode": "HTML",
+      })),
+    }
+  }
+}
+
+impl Sink for TelegramSink {
+  fn check_config(&self, config: &str) -> Result<()> {
+    sink::check_config::<TelegramConfig>("telegram", config)
+  }
+
+  fn send(&self, item: &NewsItem, destination: &Server) -> Outcome {
+    let config: TelegramConfig = match destination.config() {
+      Ok(c) => c,
+      Err(e) => return Outcome::Failed(e.to_string()),
+    };
+    let base = destination.url.trim_end_matches('/');
+    let (method, data) = TelegramSink::request(item, &config.chat_id);
+
+    let res = self.client.post(&format!("{}/bot{}/{}", base, config.bot_token, method))
+      .json(&data)
+      .send();
+    Outcome::from_response(res, |body| {
+      serde_json::from_str::<serde_json::Value>(body).ok()
+        .and_then(|v| v["result"]["message_id"].as_i64())
+        .map(|id| id.to_string())
+    })
+  }
+
+  fn preview(&self, item: &NewsItem, destination: &Server) -> serde_json::Value {
+    let chat_id = destination.config::<TelegramConfig>().map(|c| c.chat_id).unwrap_or_default();
+    TelegramSink::request(item, &chat_id).1
+  }
+}
+
+fn utf16_len(s: &str) -> usize {
+  s.encode_utf16().count()
+}
+
+fn truncate(s: &str, max: usize) -> String {
+  if utf16_len(s) <= max {
+    return s.to_string();
+  }
+  let mut len = 0;
+  let mut truncated: String = s.chars()
+    .take_while(|c| {
+      len += c.len_utf16();
+      len < max
+    })
+    .collect();
+  truncated.push('…');
+  truncated
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// The text Telegram counts: the message with its tags removed and entities decoded.
+  fn visible(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+      match c {
+        '<' => in_tag = true,
+        '>' => in_tag = false,
+        c if !in_tag => text.push(c),
+        _ => {},
+      }
+    }
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&amp;", "&")
+  }
+
+  fn long_item() -> NewsItem {
+    let mut item = NewsItem::example(1, &"Title & <more> ".repeat(20));
+    item.tag = Some("Maintenance".to_string());
+    item.description = Some("Visit [the forums](https://example.com/?a=1&b=2) for 🐱 details. ".repeat(100));
+    item.fields = Some(serde_json::json!([
+      { "name": "Date & Time", "value": "Oct. 1, 2019 1:00 to 5:00 (GMT)\n".repeat(50) },
+    ]).to_string());
+    item
+  }
+
+  #[test]
+  fn truncate_counts_utf16_code_units() {
+    assert_eq!(truncate("abc", 3), "abc");
+    assert_eq!(truncate("abcd", 3), "ab…");
+    // each cat is two code units, so only one fits next to the ellipsis
+    assert_eq!(truncate("🐱🐱🐱", 4), "🐱…");
+    assert!(utf16_len(&truncate(&"🐱".repeat(10), 5)) <= 5);
+  }
+
+  #[test]
+  fn short_messages_are_complete() {
+    let mut item = NewsItem::example(1, "A & B");
+    item.description = Some("See [here](https://example.com).".to_string());
+    assert_eq!(
+      TelegramSink::message(&item, MAX_MESSAGE),
+      "<b><a href=\"https://na.finalfantasyxiv.com/lodestone/news/detail/1\">A &amp; B</a></b>\n\n\
+       See <a href=\"https://example.com\">here</a>.\n\n<i>News</i>",
+    );
+  }
+
+  #[test]
+  fn long_messages_fit_the_limits() {
+    let item = long_item();
+    for &limit in &[MAX_CAPTION, MAX_MESSAGE] {
+      let message = TelegramSink::message(&item, limit);
+      assert!(utf16_len(&visible(&message)) <= limit, "{} > {}", utf16_len(&visible(&message)), limit);
+      assert!(message.ends_with("<i>News · Maintenance</i>"));
+    }
+  }
+
+  #[test]
+  fn items_with_images_are_sent_as_captioned_photos() {
+    let mut item = long_item();
+    let (method, body) = TelegramSink::request(&item, "@channel");
+    assert_eq!(method, "sendMessage");
+    assert_eq!(body["chat_id"], "@channel");
+    assert!(utf16_len(&visible(body["text"].as_str().unwrap())) > MAX_CAPTION);
+
+    item.image = Some("https://example.com/banner.png".to_string());
+    let (method, body) = TelegramSink::request(&item, "@channel");
+    assert_eq!(method, "sendPhoto");
+    assert_eq!(body["photo"], "https://example.com/banner.png");
+    assert!(utf16_len(&visible(body["caption"].as_str().unwrap())) <= MAX_CAPTION);
+  }
+}
@@ -0,0 +1,96 @@
+use chrono::Utc;
+
+use hmac::{Hmac, Mac};
+
+use reqwest::Client;
+
+use serde_derive::Deserialize;
+
+use sha2::Sha256;
+
+use crate::{
+  database::models::{
+    news_item::NewsItem,
+    server::Server,
+  },
+  export::ExportedItem,
+  errors::*,
+  sink::{self, Outcome, Sink},
+};
+
+#[derive(Debug, Deserialize)]
+struct WebhookConfig {
+  secret: String,
+}
+
+pub struct WebhookSink {
+  client: Client,
+}
+
+impl Default for WebhookSink {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl WebhookSink {
+  pub fn new() -> Self {
+    WebhookSink {
+      client: Client::new(),
+    }
+  }
+
+  /// Signs `{timestamp}.{body}` so a captured request can't be replayed with a new timestamp.
+  pub fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("hmac accepts any key length");
+    mac.input(timestamp.to_string().as_bytes());
+    mac.input(b".");
+    mac.input(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.result().code()))
+  }
+}
+
+impl Sink for WebhookSink {
+  fn check_config(&self, config: &str) -> Result<()> {
+    sink::check_config::<WebhookConfig>("webhook", config)
+  }
+
+  fn send(&self, item: &NewsItem, destination: &Server) -> Outcome {
+    let config: WebhookConfig = match destination.config() {
+      Ok(c) => c,
+      Err(e) => return Outcome::Failed(e.to_string()),
+    };
+    let body = match serde_json::to_string(&ExportedItem::from(item)) {
+      Ok(b) => b,
+      Err(e) => return Outcome::Failed(format!("could not serialize item: {}", e)),
+    };
+    let timestamp = Utc::now().timestamp();
+
+    let res = self.client.post(&destination.url)
+      .header("Content-Type", "application/json")
+      .header("X-Lodestone-Delivery", format!("{}-{}", destination.id, item.id))
+      .header("X-Lodestone-Timestamp", timestamp.to_string())
+      .header("X-Lodestone-Signature", WebhookSink::signature(&config.secret, timestamp, &body))
+      .body(body)
+      .send();
+    Outcome::from_response(res, |_| None)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn signature_covers_timestamp_and_body() {
+    // computed independently: printf '1570000000.{"id":1}' | openssl dgst -sha256 -hmac hunter2
+    assert_eq!(
+      WebhookSink::signature("hunter2", 1_570_000_000, r#"{"id":1}"#),
+      "sha256=0b7f37627adbdee30118f4354c3d5607e18c539f282a9e0f2f3297a950a70ac4",
+    );
+    assert_eq!(
+      WebhookSink::signature("hunter2", 1_570_000_001, r#"{"id":1}"#),
+      "sha256=53848b234e904a40ed826df65a8823269efdb08a93ffae918a5fa47f80f69c16",
+    );
+  }
+}
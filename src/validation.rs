@@ -0,0 +1,196 @@
+use chrono::NaiveTime;
+
+use chrono_tz::Tz;
+
+use lettre::EmailAddress;
+
+use reqwest::Url;
+
+use crate::{
+  database::models::{
+    news_item::NewsKind,
+    server::NewServer,
+  },
+  digest::{DigestSchedule, DigestStyle},
+  errors::*,
+  sink::Sinks,
+};
+
+pub const DISCORD_WEBHOOK_PREFIXES: &[&str] = &[
+  "https://discord.com/api/webhooks/",
+  "https://discordapp.com/api/webhooks/",
+];
+
+thread_local! {
+  // building every sink's http client is slow, so checks share one set per thread
+  static SINKS: Sinks = Sinks::new();
+}
+
+pub fn is_discord_webhook(url: &str) -> bool {
+  DISCORD_WEBHOOK_PREFIXES.iter().any(|p| url.starts_with(p))
+}
+
+/// Normalises a comma-separated list of kinds for `filter_kinds`.
+pub fn kinds(list: &str) -> Result<String> {
+  let kinds = list.split(',')
+    .map(|k| k.trim().parse::<NewsKind>().map_err(|e| Error::from(e.to_string())))
+    .collect::<Result<Vec<_>>>()?;
+  Ok(kinds.iter().map(|k| k.to_string().to_lowercase()).collect::<Vec<_>>().join(","))
+}
+
+/// Normalises a comma-separated list of tags for `filter_tags`.
+pub fn tags(list: &str) -> String {
+  list.split(',')
+    .map(|t| t.trim().to_lowercase())
+    .filter(|t| !t.is_empty())
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+/// Normalises an address for `email_subscribers`.
+pub fn email_address(address: &str) -> Result<String> {
+  let address = address.trim();
+  address.parse::<EmailAddress>().map_err(|_| format!("invalid email address: {}", address))?;
+  Ok(address.to_string())
+}
+
+/// Checks every setting of a destination before it is saved.
+pub fn server(server: &NewServer) -> Result<()> {
+  if server.title.trim().is_empty() {
+    return Err("title must not be empty".into());
+  }
+
+  SINKS.with(|sinks| -> Result<()> {
+    let sink = sinks.get(&server.sink).ok_or_else(|| format!("unknown sink: {}", server.sink))?;
+    Url::parse(&server.url).chain_err(|| format!("invalid url: {}", server.url))?;
+    if server.sink == "discord" && !is_discord_webhook(&server.url) {
+      return Err(format!("not a Discord webhook url: {}", server.url).into());
+    }
+    if let Some(ref config) = server.config {
+      match serde_json::from_str::<serde_json::Value>(config) {
+        Ok(serde_json::Value::Object(_)) => {},
+        _ => return Err("config must be a JSON object".into()),
+      }
+    }
+    sink.check_config(server.config.as_ref().map(String::as_str).unwrap_or("{}"))
+  })?;
+
+  if let Some(ref schedule) = server.digest_schedule {
+    schedule.parse::<DigestSchedule>().map_err(|e| format!("invalid digest schedule: {}", e))?;
+  }
+  server.digest_style.parse::<DigestStyle>().map_err(|e| format!("invalid digest style: {}", e))?;
+  if let Some(ref timezone) = server.timezone {
+    timezone.parse::<Tz>().map_err(|e| format!("invalid time zone {}: {}", timezone, e))?;
+  }
+
+  match (&server.quiet_start, &server.quiet_end) {
+    (Some(start), Some(end)) => for time in &[start, end] {
+      NaiveTime::parse_from_str(time, "%H:%M").chain_err(|| format!("invalid quiet hours time: {}", time))?;
+    },
+    (None, None) => {},
+    _ => return Err("quiet hours need both a start and an end".into()),
+  }
+
+  if let Some(ref filter_kinds) = server.filter_kinds {
+    kinds(filter_kinds)?;
+  }
+  if server.reminder_lead < 0 {
+    return Err("reminder lead must not be negative".into());
+  }
+  if (server.scheduled_events || server.reminders) && server.sink != "discord" {
+    return Err("scheduled events and reminders are only sent to discord".into());
+  }
+  if server.scheduled_events && (server.bot_token.is_none() || server.guild_id.is_none()) {
+    return Err("scheduled events need a bot token and a guild id".into());
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const WEBHOOK: &str = "https://discord.com/api/webhooks/1/abc";
+
+  /// Validates a valid Discord destination after `change` has been made to it.
+  fn check<F: FnOnce(&mut NewServer)>(change: F) -> std::result::Result<(), String> {
+    let mut server = NewServer::new("Test", WEBHOOK);
+    change(&mut server);
+    super::server(&server).map_err(|e| e.to_string())
+  }
+
+  #[test]
+  fn valid_destinations_pass() {
+    assert_eq!(check(|_| {}), Ok(()));
+    assert_eq!(check(|s| s.url = "https://discordapp.com/api/webhooks/1/abc".into()), Ok(()));
+    assert_eq!(check(|s| {
+      s.sink = "telegram".into();
+      s.url = "https://api.telegram.org".into();
+      s.config = Some(r#"{"bot_token":"123:abc","chat_id":"@news"}"#.into());
+      s.digest_schedule = Some("daily 08:00".into());
+      s.digest_style = "summary".into();
+      s.timezone = Some("Europe/London".into());
+      s.quiet_start = Some("22:00".into());
+      s.quiet_end = Some("07:00".into());
+      s.filter_kinds = Some("news,special notice".into());
+    }), Ok(()));
+    assert_eq!(check(|s| {
+      s.reminders = true;
+      s.scheduled_events = true;
+      s.bot_token = Some("token".into());
+      s.guild_id = Some("1".into());
+    }), Ok(()));
+  }
+
+  #[test]
+  fn invalid_settings_are_refused() {
+    assert_eq!(check(|s| s.title = " ".into()), Err("title must not be empty".into()));
+    assert_eq!(check(|s| s.sink = "pigeon".into()), Err("unknown sink: pigeon".into()));
+    assert_eq!(check(|s| s.url = "not a url".into()), Err("invalid url: not a url".into()));
+    assert_eq!(
+      check(|s| s.url = "https://example.com/api/webhooks/1/abc".into()),
+      Err("not a Discord webhook url: https://example.com/api/webhooks/1/abc".into()),
+    );
+    assert_eq!(check(|s| s.config = Some("[]".into())), Err("config must be a JSON object".into()));
+    assert!(check(|s| {
+      s.sink = "telegram".into();
+      s.url = "https://api.telegram.org".into();
+      s.config = Some(r#"{"chat_id":"@news"}"#.into());
+    }).is_err());
+    assert!(check(|s| s.digest_schedule = Some("fortnightly".into())).unwrap_err().starts_with("invalid digest schedule"));
+    assert!(check(|s| s.digest_style = "fancy".into()).unwrap_err().starts_with("invalid digest style"));
+    assert!(check(|s| s.timezone = Some("Mars/Olympus".into())).unwrap_err().starts_with("invalid time zone Mars/Olympus"));
+    assert_eq!(check(|s| s.quiet_start = Some("22:00".into())), Err("quiet hours need both a start and an end".into()));
+    assert_eq!(check(|s| {
+      s.quiet_start = Some("22:00".into());
+      s.quiet_end = Some("7am".into());
+    }), Err("invalid quiet hours time: 7am".into()));
+    assert!(check(|s| s.filter_kinds = Some("news,gossip".into())).is_err());
+    assert_eq!(check(|s| s.reminder_lead = -1), Err("reminder lead must not be negative".into()));
+    assert_eq!(check(|s| {
+      s.sink = "webhook".into();
+      s.url = "https://example.com/hook".into();
+      s.config = Some(r#"{"secret":"hunter2"}"#.into());
+      s.reminders = true;
+    }), Err("scheduled events and reminders are only sent to discord".into()));
+    assert_eq!(check(|s| {
+      s.scheduled_events = true;
+      s.guild_id = Some("1".into());
+    }), Err("scheduled events need a bot token and a guild id".into()));
+  }
+
+  #[test]
+  fn filters_are_normalised() {
+    assert_eq!(kinds(" News,topics , special_notice").unwrap(), "news,topic,special notice");
+    assert!(kinds("news,").is_err());
+    assert_eq!(tags(" Maintenance,,IMPORTANT "), "maintenance,important");
+  }
+
+  #[test]
+  fn email_addresses_are_checked() {
+    assert_eq!(email_address(" someone@example.com ").unwrap(), "someone@example.com");
+    assert!(email_address("someone").is_err());
+    assert!(email_address("someone@").is_err());
+  }
+}
@@ -0,0 +1,501 @@
+use chrono::NaiveDateTime;
+
+use diesel::{
+  prelude::*,
+  insert_into,
+  update,
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use serde_json::Value;
+
+use sha2::{Digest, Sha256};
+
+use tiny_http::{Method, Request};
+
+use crate::{
+  database::models::{
+    admin_token::AdminToken,
+    server::{NewServer, Server},
+  },
+  errors::*,
+  sender::NewsSender,
+  sink::Outcome,
+  validation,
+  web::{self, WebResponse},
+};
+
+use std::{
+  fs::File,
+  io::Read,
+};
+
+/// Changes to a destination. Missing fields are left alone and empty strings clear a setting.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerChanges {
+  pub title: Option<String>,
+  pub url: Option<String>,
+  pub sink: Option<String>,
+  pub config: Option<Value>,
+  pub digest_schedule: Option<String>,
+  pub digest_style: Option<String>,
+  pub timezone: Option<String>,
+  pub quiet_start: Option<String>,
+  pub quiet_end: Option<String>,
+  pub quiet_bypass: Option<bool>,
+  pub guild_id: Option<String>,
+  pub channel_id: Option<String>,
+  pub filter_kinds: Option<String>,
+  pub filter_tags: Option<String>,
+  pub bot_token: Option<String>,
+  pub scheduled_events: Option<bool>,
+  pub reminders: Option<bool>,
+  pub reminder_lead: Option<i32>,
+  pub enabled: Option<bool>,
+}
+
+/// Config keys that hold credentials. They are never shown, and sending back the placeholder that is
+/// shown instead keeps the stored value.
+const SECRET_KEYS: &[&str] = &["access_token", "bot_token", "password", "secret", "token"];
+const REDACTED: &str = "[redacted]";
+
+/// A destination as the api shows it, without its credentials.
+#[derive(Debug, Serialize)]
+struct ServerView<'a> {
+  id: i32,
+  title: &'a str,
+  url: String,
+  created: NaiveDateTime,
+  digest_schedule: &'a Option<String>,
+  digest_style: &'a str,
+  timezone: &'a Option<String>,
+  quiet_start: &'a Option<String>,
+  quiet_end: &'a Option<String>,
+  quiet_bypass: bool,
+  guild_id: &'a Option<String>,
+  channel_id: &'a Option<String>,
+  filter_kinds: &'a Option<String>,
+  filter_tags: &'a Option<String>,
+  has_bot_token: bool,
+  scheduled_events: bool,
+  reminders: bool,
+  reminder_lead: i32,
+  sink: &'a str,
+  config: Option<Value>,
+  enabled: bool,
+}
+
+impl<'a> From<&'a Server> for ServerView<'a> {
+  fn from(server: &'a Server) -> Self {
+    let config = server.config.as_ref().map(|c| {
+      let mut config = serde_json::from_str(c).unwrap_or(Value::Null);
+      redact(&mut config);
+      config
+    });
+    ServerView {
+      id: server.id,
+      title: &server.title,
+      url: redact_url(&server.sink, &server.url),
+      created: server.created,
+      digest_schedule: &server.digest_schedule,
+      digest_style: &server.digest_style,
+      timezone: &server.timezone,
+      quiet_start: &server.quiet_start,
+      quiet_end: &server.quiet_end,
+      quiet_bypass: server.quiet_bypass,
+      guild_id: &server.guild_id,
+      channel_id: &server.channel_id,
+      filter_kinds: &server.filter_kinds,
+      filter_tags: &server.filter_tags,
+      has_bot_token: server.bot_token.is_some(),
+      scheduled_events: server.scheduled_events,
+      reminders: server.reminders,
+      reminder_lead: server.reminder_lead,
+      sink: &server.sink,
+      config,
+      enabled: server.enabled,
+    }
+  }
+}
+
+fn redact(config: &mut Value) {
+  if let Value::Object(ref mut map) = *config {
+    for (key, value) in map.iter_mut() {
+      if SECRET_KEYS.contains(&key.as_str()) {
+        *value = REDACTED.into();
+      }
+    }
+  }
+}
+
+/// Hides the part of a webhook url that works as a password. Discord and Slack keep their ids, and
+/// only the token at the end is hidden. Teams and Google Chat spread theirs over the path and query,
+/// so only the host is kept.
+fn redact_url(sink: &str, url: &str) -> String {
+  let parsed = match reqwest::Url::parse(url) {
+    Ok(u) => u,
+    Err(_) => return url.to_string(),
+  };
+  let origin = match parsed.port() {
+    Some(port) => format!("{}://{}:{}", parsed.scheme(), parsed.host_str().unwrap_or_default(), port),
+    None => format!("{}://{}", parsed.scheme(), parsed.host_str().unwrap_or_default()),
+  };
+  let segments = parsed.path_segments().map(Iterator::count).unwrap_or(0);
+  match sink {
+    "discord" | "slack" if segments > 1 => {
+      let path = parsed.path();
+      format!("{}{}/{}", origin, &path[..path.rfind('/').unwrap_or(0)], REDACTED)
+    },
+    "teams" | "google_chat" => format!("{}/{}", origin, REDACTED),
+    _ => url.to_string(),
+  }
+}
+
+/// Puts the stored credentials back in place of any placeholders in a new config.
+fn unredact(config: &mut Value, old: Option<&String>) {
+  let old: Value = match old.and_then(|c| serde_json::from_str(c).ok()) {
+    Some(c) => c,
+    None => return,
+  };
+  if let Value::Object(ref mut map) = *config {
+    for (key, value) in map.iter_mut() {
+      if SECRET_KEYS.contains(&key.as_str()) && *value == REDACTED {
+        if let Some(old) = old.get(key) {
+          *value = old.clone();
+        }
+      }
+    }
+  }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Filters {
+  kinds: Option<String>,
+  tags: Option<String>,
+}
+
+impl ServerChanges {
+  pub fn apply(self, server: &mut NewServer) -> Result<()> {
+    fn set<T>(value: Option<T>, field: &mut T) {
+      if let Some(v) = value {
+        *field = v;
+      }
+    }
+    fn set_nullable(value: Option<String>, field: &mut Option<String>) {
+      if let Some(v) = value {
+        *field = if v.trim().is_empty() { None } else { Some(v) };
+      }
+    }
+
+    set(self.title, &mut server.title);
+    // a redacted url sent back unchanged keeps the stored one
+    set(self.url.filter(|u| *u != redact_url(&server.sink, &server.url)), &mut server.url);
+    set(self.sink, &mut server.sink);
+    if let Some(mut config) = self.config {
+      unredact(&mut config, server.config.as_ref());
+      server.config = Some(config.to_string());
+    }
+    set_nullable(self.digest_schedule, &mut server.digest_schedule);
+    set(self.digest_style, &mut server.digest_style);
+    set_nullable(self.timezone, &mut server.timezone);
+    set_nullable(self.quiet_start, &mut server.quiet_start);
+    set_nullable(self.quiet_end, &mut server.quiet_end);
+    set(self.quiet_bypass, &mut server.quiet_bypass);
+    set_nullable(self.guild_id, &mut server.guild_id);
+    set_nullable(self.channel_id, &mut server.channel_id);
+    let filter_kinds = match self.filter_kinds {
+      Some(ref k) if !k.trim().is_empty() => Some(validation::kinds(k)?),
+      k => k,
+    };
+    set_nullable(filter_kinds, &mut server.filter_kinds);
+    set_nullable(self.filter_tags.map(|t| validation::tags(&t)), &mut server.filter_tags);
+    set_nullable(self.bot_token, &mut server.bot_token);
+    set(self.scheduled_events, &mut server.scheduled_events);
+    set(self.reminders, &mut server.reminders);
+    set(self.reminder_lead, &mut server.reminder_lead);
+    set(self.enabled, &mut server.enabled);
+    Ok(())
+  }
+}
+
+pub fn hash_token(token: &str) -> String {
+  hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// A random token to give out. Only its hash is stored.
+pub fn new_token() -> Result<String> {
+  let mut bytes = [0; 32];
+  File::open("/dev/urandom")
+    .and_then(|mut f| f.read_exact(&mut bytes))
+    .chain_err(|| "could not read random bytes for a token")?;
+  Ok(hex::encode(bytes))
+}
+
+fn authorized(request: &Request) -> Result<bool> {
+  let token = match web::header(request, "Authorization") {
+    Some(h) if h.starts_with("Bearer ") => &h["Bearer ".len()..],
+    _ => return Ok(false),
+  };
+  is_token(token.trim())
+}
+
+/// Whether a token matches one in `admin_tokens`.
+pub fn is_token(token: &str) -> Result<bool> {
+  let hash = hash_token(token);
+  let found: Option<AdminToken> = crate::CONNECTION.with(|c| {
+    use crate::database::schema::admin_tokens;
+    admin_tokens::table
+      .filter(admin_tokens::token_hash.eq(&hash))
+      .first(c)
+      .optional()
+      .chain_err(|| "could not load admin tokens")
+  })?;
+  Ok(found.is_some())
+}
+
+pub fn handle(request: &mut Request, method: &Method, path: &str) -> Result<WebResponse> {
+  if !authorized(request)? {
+    return Ok(error(401, "missing or invalid admin token"));
+  }
+
+  let segments: Vec<&str> = path.trim_matches('/').split('/').skip(1).collect();
+  if segments.first() != Some(&"servers") {
+    return Ok(error(404, "not found"));
+  }
+  if segments.len() == 1 {
+    return match *method {
+      Method::Get => list(),
+      Method::Post => create(request),
+      _ => Ok(error(405, "method not allowed")),
+    };
+  }
+
+  let server = match segments[1].parse() {
+    Ok(id) => find(id)?,
+    Err(_) => None,
+  };
+  let server = match server {
+    Some(s) => s,
+    None => return Ok(error(404, "no such server")),
+  };
+  match (method, &segments[2..]) {
+    (Method::Get, []) => Ok(web::json(200, &to_json(&server)?)),
+    (Method::Patch, []) => modify(request, server),
+    (Method::Delete, []) => {
+      server.delete()?;
+      info!("Deleted server {} via admin api", server.id);
+      Ok(web::json(200, &serde_json::json!({ "deleted": server.id })))
+    },
+    (Method::Post, ["enable"]) => save(server, changes(|c| c.enabled = Some(true))),
+    (Method::Post, ["disable"]) => save(server, changes(|c| c.enabled = Some(false))),
+    (Method::Put, ["filters"]) => {
+      let filters: Filters = match read_json(request)? {
+        Ok(f) => f,
+        Err(e) => return Ok(e),
+      };
+      save(server, changes(|c| {
+        // filters that aren't given are cleared
+        c.filter_kinds = Some(filters.kinds.unwrap_or_default());
+        c.filter_tags = Some(filters.tags.unwrap_or_default());
+      }))
+    },
+    (Method::Post, ["test"]) => test(&server),
+    _ => Ok(error(404, "not found")),
+  }
+}
+
+fn changes<F: FnOnce(&mut ServerChanges)>(f: F) -> ServerChanges {
+  let mut changes = ServerChanges::default();
+  f(&mut changes);
+  changes
+}
+
+fn find(id: i32) -> Result<Option<Server>> {
+  crate::CONNECTION.with(|c| {
+    use crate::database::schema::servers;
+    servers::table.find(id).first(c).optional().chain_err(|| "could not load server")
+  })
+}
+
+fn list() -> Result<WebResponse> {
+  let servers: Vec<Server> = crate::CONNECTION.with(|c| {
+    use crate::database::schema::servers;
+    servers::table.order(servers::id).load(c).chain_err(|| "could not load servers")
+  })?;
+  let servers: Vec<ServerView> = servers.iter().map(ServerView::from).collect();
+  let servers = serde_json::to_value(&servers).chain_err(|| "could not serialize servers")?;
+  Ok(web::json(200, &serde_json::json!({ "servers": servers })))
+}
+
+fn create(request: &mut Request) -> Result<WebResponse> {
+  let changes: ServerChanges = match read_json(request)? {
+    Ok(c) => c,
+    Err(e) => return Ok(e),
+  };
+  let (title, url) = match (&changes.title, &changes.url) {
+    (Some(t), Some(u)) => (t.clone(), u.clone()),
+    _ => return Ok(error(400, "title and url are required")),
+  };
+  let mut server = NewServer::new(title, url);
+  if let Err(e) = changes.apply(&mut server).and_then(|_| validation::server(&server)) {
+    return Ok(error(400, &e.to_string()));
+  }
+
+  let server: Server = crate::CONNECTION.with(|c| {
+    use crate::database::schema::servers;
+    c.transaction(|| {
+      insert_into(servers::table).values(&server).execute(c)?;
+      servers::table.order(servers::id.desc()).first(c)
+    })
+    .chain_err(|| "could not insert server")
+  })?;
+  info!("Created server {} via admin api", server.id);
+  Ok(web::json(201, &to_json(&server)?))
+}
+
+fn modify(request: &mut Request, server: Server) -> Result<WebResponse> {
+  let changes: ServerChanges = match read_json(request)? {
+    Ok(c) => c,
+    Err(e) => return Ok(e),
+  };
+  save(server, changes)
+}
+
+/// Applies changes to a server and saves it if the result is valid.
+fn save(server: Server, changes: ServerChanges) -> Result<WebResponse> {
+  let id = server.id;
+  let mut new_server = server.into_new();
+  if let Err(e) = changes.apply(&mut new_server).and_then(|_| validation::server(&new_server)) {
+    return Ok(error(400, &e.to_string()));
+  }
+
+  crate::CONNECTION.with(|c| {
+    use crate::database::schema::servers;
+    update(servers::table.find(id))
+      .set(&new_server)
+      .execute(c)
+      .chain_err(|| "could not update server")
+  })?;
+  info!("Updated server {} via admin api", id);
+  let server = find(id)?.ok_or("server disappeared")?;
+  Ok(web::json(200, &to_json(&server)?))
+}
+
+fn test(server: &Server) -> Result<WebResponse> {
+  let (item, outcome) = match NewsSender::new().send_test(server) {
+    Ok(x) => x,
+    Err(e) => return Ok(error(400, &e.to_string())),
+  };
+  let mut result = serde_json::json!({
+    "item": item.lodestone_id,
+    "sent": outcome.is_sent(),
+  });
+  match outcome {
+    Outcome::Sent(remote_id) => result["remote_id"] = serde_json::json!(remote_id),
+    Outcome::Rejected { status, body } => {
+      result["status"] = status.into();
+      result["error"] = body.into();
+    },
+    Outcome::Failed(e) => result["error"] = e.into(),
+  }
+  Ok(web::json(200, &result))
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(request: &mut Request) -> Result<std::result::Result<T, WebResponse>> {
+  let body = match web::read_body(request)? {
+    Ok(b) => b,
+    Err(res) => return Ok(Err(res)),
+  };
+  Ok(serde_json::from_slice(&body).map_err(|e| error(400, &format!("invalid body: {}", e))))
+}
+
+fn to_json(server: &Server) -> Result<Value> {
+  serde_json::to_value(ServerView::from(server)).chain_err(|| "could not serialize server")
+}
+
+fn error(status: u16, message: &str) -> WebResponse {
+  web::json(status, &serde_json::json!({ "error": message }))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn server() -> Server {
+    NewServer {
+      sink: "mastodon".into(),
+      config: Some(r#"{"access_token":"abc","visibility":"public"}"#.into()),
+      bot_token: Some("bot".into()),
+      ..NewServer::new("Test", "https://example.social")
+    }.with_id(1)
+  }
+
+  #[test]
+  fn new_tokens_are_random() {
+    let token = new_token().unwrap();
+    assert_eq!(token.len(), 64);
+    assert!(token.chars().all(|c| c.is_ascii_hexdigit()));
+    assert_ne!(token, new_token().unwrap());
+    assert_eq!(hash_token("hunter2"), "f52fbd32b2b3b86ff88ef6c490628285f482af15ddcb29541f94bcf526a3f6c7");
+  }
+
+  #[test]
+  fn credentials_are_not_shown() {
+    let json = to_json(&server()).unwrap();
+    assert_eq!(json["id"], 1);
+    assert_eq!(json["has_bot_token"], true);
+    assert!(json.get("bot_token").is_none());
+    assert_eq!(json["config"], serde_json::json!({ "access_token": REDACTED, "visibility": "public" }));
+    assert!(!json.to_string().contains("abc"));
+  }
+
+  #[test]
+  fn placeholders_keep_stored_credentials() {
+    let shown = to_json(&server()).unwrap();
+    let mut new_server = server().into_new();
+    let mut config = shown["config"].clone();
+    config["visibility"] = "unlisted".into();
+    changes(|c| c.config = Some(config)).apply(&mut new_server).unwrap();
+    let config: Value = serde_json::from_str(new_server.config.as_ref().unwrap()).unwrap();
+    assert_eq!(config, serde_json::json!({ "access_token": "abc", "visibility": "unlisted" }));
+
+    changes(|c| c.config = Some(serde_json::json!({ "access_token": "new" }))).apply(&mut new_server).unwrap();
+    assert_eq!(new_server.config.as_ref().unwrap(), r#"{"access_token":"new"}"#);
+  }
+
+  #[test]
+  fn webhook_tokens_are_not_shown() {
+    let shown = |sink: &str, url: &str| {
+      let server = NewServer { sink: sink.into(), ..NewServer::new("Test", url) }.with_id(1);
+      to_json(&server).unwrap()["url"].as_str().unwrap().to_string()
+    };
+    assert_eq!(shown("discord", "https://discord.com/api/webhooks/123/s3cr3t"), "https://discord.com/api/webhooks/123/[redacted]");
+    assert_eq!(shown("slack", "https://hooks.slack.com/services/T0/B0/s3cr3t"), "https://hooks.slack.com/services/T0/B0/[redacted]");
+    assert_eq!(
+      shown("teams", "https://example.webhook.office.com/webhookb2/a@b/IncomingWebhook/s3cr3t/c"),
+      "https://example.webhook.office.com/[redacted]",
+    );
+    assert_eq!(
+      shown("google_chat", "https://chat.googleapis.com/v1/spaces/A/messages?key=k&token=s3cr3t"),
+      "https://chat.googleapis.com/[redacted]",
+    );
+    // other sinks' urls are only addresses
+    assert_eq!(shown("ntfy", "https://ntfy.sh/"), "https://ntfy.sh/");
+    // nothing that looks like a token to hide
+    assert_eq!(shown("discord", "https://discord.com/"), "https://discord.com/");
+  }
+
+  #[test]
+  fn redacted_urls_keep_the_stored_url() {
+    let url = "https://discord.com/api/webhooks/123/s3cr3t";
+    let mut server = NewServer::new("Test", url);
+    changes(|c| c.url = Some("https://discord.com/api/webhooks/123/[redacted]".into())).apply(&mut server).unwrap();
+    assert_eq!(server.url, url);
+
+    changes(|c| c.url = Some("https://discord.com/api/webhooks/456/new".into())).apply(&mut server).unwrap();
+    assert_eq!(server.url, "https://discord.com/api/webhooks/456/new");
+  }
+}
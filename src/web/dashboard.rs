@@ -0,0 +1,406 @@
+use chrono::NaiveDateTime;
+
+use diesel::{
+  prelude::*,
+  update,
+};
+
+use tiny_http::{Header, Method, Request};
+
+use crate::{
+  database::models::{
+    news_item::NewsItem,
+    send_failure::SendFailure,
+    send_record::SendRecord,
+    server::Server,
+  },
+  errors::*,
+  metrics,
+  sender::NewsSender,
+  sink::{
+    Outcome,
+    format::escape_html,
+  },
+  web::{self, admin, WebResponse},
+};
+
+use std::{
+  collections::{HashMap, HashSet},
+  fmt::Write,
+};
+
+const RECENT_ITEMS: i64 = 25;
+const RECENT_FAILURES: i64 = 100;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }
+th { background: #eee; }
+form { display: inline; }
+pre { margin: 0; max-width: 40em; white-space: pre-wrap; font-size: 0.85em; }
+.notice { background: #eef; border: 1px solid #99c; padding: 0.5em 1em; }
+.sent { color: #262; }
+.failed { color: #b22; font-weight: bold; }
+.pending { color: #a60; }
+.disabled { color: #888; }
+";
+
+/// Serves the operator dashboard. Browsers are asked to log in with any user name and an admin
+/// token as the password.
+pub fn handle(request: &mut Request, method: &Method, path: &str) -> Result<WebResponse> {
+  if !authorized(web::header(request, "Authorization"))? {
+    return Ok(web::text(401, "an admin token is required")
+      .with_header(Header::from_bytes(&b"WWW-Authenticate"[..], &b"Basic realm=\"lodestone_news\""[..]).unwrap()));
+  }
+
+  let segments: Vec<&str> = path.trim_matches('/').split('/').skip(1).collect();
+  match (method, segments.as_slice()) {
+    (Method::Get, []) => {
+      let notice = web::query(request).into_iter().find(|(k, _)| k == "notice").map(|(_, v)| v);
+      Ok(web::with_type(page(notice)?, "text/html; charset=utf-8"))
+    },
+    (Method::Post, _) if !same_origin(web::header(request, "Origin"), web::header(request, "Referer"), web::header(request, "Host")) => Ok(web::text(403, "cross-origin requests are not allowed")),
+    (Method::Post, ["servers", id, action]) => {
+      let enabled = match *action {
+        "enable" => true,
+        "disable" => false,
+        _ => return Ok(web::text(404, "not found")),
+      };
+      let server = match id.parse().ok().map(find_server).transpose()? {
+        Some(Some(s)) => s,
+        _ => return Ok(web::text(404, "no such server")),
+      };
+      crate::CONNECTION.with(|c| {
+        use crate::database::schema::servers;
+        update(&server)
+          .set(servers::enabled.eq(enabled))
+          .execute(c)
+          .chain_err(|| "could not update server")
+      })?;
+      info!("{} server {} via dashboard", if enabled { "Enabled" } else { "Disabled" }, server.id);
+      Ok(redirect(&format!("{} {}", if enabled { "Enabled" } else { "Disabled" }, server.title)))
+    },
+    (Method::Post, ["resend"]) => resend(request),
+    _ => Ok(web::text(404, "not found")),
+  }
+}
+
+/// Checks an `Authorization` header for Basic credentials with an admin token as the password.
+fn authorized(authorization: Option<&str>) -> Result<bool> {
+  let credentials = match authorization {
+    Some(h) if h.starts_with("Basic ") => base64::decode(h["Basic ".len()..].trim()).ok().and_then(|c| String::from_utf8(c).ok()),
+    _ => None,
+  };
+  match credentials {
+    Some(c) => match c.splitn(2, ':').nth(1) {
+      Some(token) if !token.is_empty() => admin::is_token(token),
+      _ => Ok(false),
+    },
+    None => Ok(false),
+  }
+}
+
+// browsers send the login with every request to this host, so forms posted by other sites are
+// refused. Browsers send `Origin` or at least `Referer` with form posts, so a post with neither is
+// refused too
+fn same_origin(origin: Option<&str>, referer: Option<&str>, host: Option<&str>) -> bool {
+  let source = match origin.or(referer).and_then(|s| reqwest::Url::parse(s).ok()) {
+    Some(s) => s,
+    None => return false,
+  };
+  let authority = match (source.host_str(), source.port()) {
+    (Some(h), Some(port)) => format!("{}:{}", h, port),
+    (Some(h), None) => h.to_string(),
+    (None, _) => return false,
+  };
+  host == Some(authority.as_str())
+}
+
+fn redirect(notice: &str) -> WebResponse {
+  let location: String = url::form_urlencoded::Serializer::for_suffix(String::from("/dashboard?"), "/dashboard?".len())
+    .append_pair("notice", notice)
+    .finish();
+  web::text(303, "")
+    .with_header(Header::from_bytes(&b"Location"[..], location.as_bytes()).unwrap())
+}
+
+fn find_server(id: i32) -> Result<Option<Server>> {
+  crate::CONNECTION.with(|c| {
+    use crate::database::schema::servers;
+    servers::table.find(id).first(c).optional().chain_err(|| "could not load server")
+  })
+}
+
+fn resend(request: &mut Request) -> Result<WebResponse> {
+  let body = match web::read_body(request)? {
+    Ok(b) => b,
+    Err(res) => return Ok(res),
+  };
+  let form: HashMap<String, String> = url::form_urlencoded::parse(&body).into_owned().collect();
+  let id = |name: &str| form.get(name).and_then(|v| v.parse::<i32>().ok());
+  let (server_id, news_id) = match (id("server_id"), id("news_id")) {
+    (Some(s), Some(n)) => (s, n),
+    _ => return Ok(web::text(400, "server_id and news_id are required")),
+  };
+
+  let server = match find_server(server_id)? {
+    Some(s) => s,
+    None => return Ok(web::text(404, "no such server")),
+  };
+  let item: Option<NewsItem> = crate::CONNECTION.with(|c| {
+    use crate::database::schema::news_items;
+    news_items::table.find(news_id).first(c).optional().chain_err(|| "could not load news item")
+  })?;
+  let item = match item {
+    Some(i) => i,
+    None => return Ok(web::text(404, "no such news item")),
+  };
+
+  let notice = match NewsSender::new().resend(&server, &item)? {
+    Outcome::Sent(_) => format!("Sent {} to {}", item.title, server.title),
+    Outcome::Rejected { status, .. } => format!("{} rejected {} ({})", server.title, item.title, status),
+    Outcome::Failed(e) => format!("Could not send {} to {}: {}", item.title, server.title, e),
+  };
+  Ok(redirect(&notice))
+}
+
+fn time(time: NaiveDateTime) -> String {
+  time.format("%Y-%m-%d %H:%M").to_string()
+}
+
+fn resend_button(out: &mut String, server_id: i32, news_id: i32, label: &str) {
+  write!(
+    out,
+    "<form method=\"post\" action=\"/dashboard/resend\">\
+     <input type=\"hidden\" name=\"server_id\" value=\"{}\">\
+     <input type=\"hidden\" name=\"news_id\" value=\"{}\">\
+     <button>{}</button></form>",
+    server_id, news_id, label,
+  ).unwrap();
+}
+
+fn page(notice: Option<String>) -> Result<String> {
+  let (servers, items, records, item_failures, failures, failed_servers) = crate::CONNECTION.with(|c| -> QueryResult<_> {
+    use crate::database::schema::{news_items, send_failures, send_records, servers};
+    let servers: Vec<Server> = servers::table.order(servers::id).load(c)?;
+    let items: Vec<NewsItem> = news_items::table
+      .order((news_items::created.desc(), news_items::id.desc()))
+      .limit(RECENT_ITEMS)
+      .load(c)?;
+    let ids: Vec<i32> = items.iter().map(|i| i.id).collect();
+    let records: Vec<SendRecord> = send_records::table.filter(send_records::news_id.eq_any(&ids)).load(c)?;
+    let item_failures: Vec<SendFailure> = send_failures::table.filter(send_failures::news_id.eq_any(&ids)).load(c)?;
+    let failures: Vec<(SendFailure, NewsItem)> = send_failures::table
+      .inner_join(news_items::table)
+      .order(send_failures::attempted.desc())
+      .limit(RECENT_FAILURES)
+      .load(c)?;
+    let failed_servers: Vec<i32> = send_failures::table.select(send_failures::server_id).load(c)?;
+    Ok((servers, items, records, item_failures, failures, failed_servers))
+  })
+  .chain_err(|| "could not load dashboard")?;
+  let backlog: HashMap<i32, i64> = metrics::backlog()?.into_iter().collect();
+  let sent: HashSet<(i32, i32)> = records.iter().map(|r| (r.server_id, r.news_id)).collect();
+  let failed: HashMap<(i32, i32), &SendFailure> = item_failures.iter().map(|f| ((f.server_id, f.news_id), f)).collect();
+  let server_title = |id: i32| servers.iter().find(|s| s.id == id).map(|s| s.title.as_str()).unwrap_or("?");
+
+  let mut out = String::new();
+  write!(out, "<!doctype html><html><head><meta charset=\"utf-8\"><title>Lodestone news</title><style>{}</style></head><body>", STYLE).unwrap();
+  out.push_str("<h1>Lodestone news</h1>");
+  if let Some(notice) = notice {
+    write!(out, "<p class=\"notice\">{}</p>", escape_html(&notice)).unwrap();
+  }
+
+  out.push_str("<h2>Destinations</h2><table><tr><th>Id</th><th>Title</th><th>Sink</th><th>Waiting</th><th>Failed</th><th>Status</th></tr>");
+  for server in &servers {
+    let failed_count = failed_servers.iter().filter(|&&id| id == server.id).count();
+    write!(
+      out,
+      "<tr class=\"{}\"><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{} \
+       <form method=\"post\" action=\"/dashboard/servers/{}/{}\"><button>{}</button></form></td></tr>",
+      if server.enabled { "" } else { "disabled" },
+      server.id,
+      escape_html(&server.title),
+      escape_html(&server.sink),
+      backlog.get(&server.id).cloned().unwrap_or(0),
+      failed_count,
+      if server.enabled { "enabled" } else { "disabled" },
+      server.id,
+      if server.enabled { "disable" } else { "enable" },
+      if server.enabled { "Disable" } else { "Enable" },
+    ).unwrap();
+  }
+  out.push_str("</table>");
+
+  out.push_str("<h2>Failed deliveries</h2>");
+  if failures.is_empty() {
+    out.push_str("<p>None.</p>");
+  } else {
+    out.push_str("<table><tr><th>Attempted</th><th>Item</th><th>Destination</th><th>Status</th><th>Error</th><th></th></tr>");
+    for (failure, item) in &failures {
+      write!(
+        out,
+        "<tr><td>{}</td><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td><td><pre>{}</pre></td><td>",
+        time(failure.attempted),
+        escape_html(&item.url),
+        escape_html(&item.title),
+        escape_html(server_title(failure.server_id)),
+        failure.status.map(|s| s.to_string()).unwrap_or_else(|| "-".into()),
+        escape_html(&failure.error),
+      ).unwrap();
+      resend_button(&mut out, failure.server_id, failure.news_id, "Resend");
+      out.push_str("</td></tr>");
+    }
+    out.push_str("</table>");
+  }
+
+  out.push_str("<h2>Recent items</h2><table><tr><th>Published</th><th>Kind</th><th>Title</th>");
+  for server in &servers {
+    write!(out, "<th>{}</th>", escape_html(&server.title)).unwrap();
+  }
+  out.push_str("</tr>");
+  for item in &items {
+    write!(
+      out,
+      "<tr><td>{}</td><td>{}</td><td><a href=\"{}\">{}</a></td>",
+      time(item.created),
+      item.kind.to_string(),
+      escape_html(&item.url),
+      escape_html(&item.title),
+    ).unwrap();
+    for server in &servers {
+      out.push_str("<td>");
+      let key = (server.id, item.id);
+      if sent.contains(&key) {
+        out.push_str("<span class=\"sent\">sent</span> ");
+        resend_button(&mut out, server.id, item.id, "Resend");
+      } else if let Some(failure) = failed.get(&key) {
+        write!(out, "<span class=\"failed\" title=\"{}\">failed</span> ", escape_html(&failure.error)).unwrap();
+        resend_button(&mut out, server.id, item.id, "Retry");
+      } else if item.created >= server.created {
+        out.push_str("<span class=\"pending\">waiting</span> ");
+        resend_button(&mut out, server.id, item.id, "Send now");
+      } else {
+        // published before the destination was added, so it was never going to be sent
+        out.push_str("- ");
+        resend_button(&mut out, server.id, item.id, "Send");
+      }
+      out.push_str("</td>");
+    }
+    out.push_str("</tr>");
+  }
+  out.push_str("</table></body></html>");
+
+  Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use diesel::insert_into;
+
+  use crate::database::models::{
+    admin_token::NewAdminToken,
+    send_failure::NewSendFailure,
+    send_record::NewSendRecord,
+    server::NewServer,
+  };
+
+  use chrono::Duration;
+
+  fn basic(credentials: &str) -> String {
+    format!("Basic {}", base64::encode(credentials))
+  }
+
+  #[test]
+  fn logins_need_an_admin_token_as_the_password() {
+    crate::database::test_database();
+    crate::CONNECTION.with(|c| {
+      use crate::database::schema::admin_tokens;
+      insert_into(admin_tokens::table)
+        .values(&NewAdminToken { name: "test".into(), token_hash: admin::hash_token("hunter2"), created: chrono::Utc::now().naive_utc() })
+        .execute(c)
+        .unwrap();
+    });
+
+    assert!(authorized(Some(&basic("anyone:hunter2"))).unwrap());
+    assert!(authorized(Some(&basic(":hunter2"))).unwrap());
+    // the password is everything after the first colon
+    assert!(!authorized(Some(&basic("anyone:hunter2:more"))).unwrap());
+    assert!(!authorized(Some(&basic("anyone:hunter3"))).unwrap());
+    assert!(!authorized(Some(&basic("anyone:"))).unwrap());
+    assert!(!authorized(Some(&basic("hunter2"))).unwrap());
+    assert!(!authorized(Some("Basic not base64!")).unwrap());
+    assert!(!authorized(Some("Bearer hunter2")).unwrap());
+    assert!(!authorized(None).unwrap());
+  }
+
+  #[test]
+  fn posts_must_come_from_the_dashboard() {
+    let host = Some("news.example.com");
+    assert!(same_origin(Some("https://news.example.com"), None, host));
+    assert!(same_origin(None, Some("https://news.example.com/dashboard?notice=x"), host));
+    assert!(same_origin(Some("http://localhost:8080"), None, Some("localhost:8080")));
+
+    assert!(!same_origin(Some("https://evil.example.com"), None, host));
+    assert!(!same_origin(None, Some("https://evil.example.com/news.example.com"), host));
+    // Origin wins over Referer
+    assert!(!same_origin(Some("https://evil.example.com"), Some("https://news.example.com/dashboard"), host));
+    assert!(!same_origin(Some("null"), None, host));
+    assert!(!same_origin(Some("http://localhost:9090"), None, Some("localhost:8080")));
+    assert!(!same_origin(None, None, host));
+    assert!(!same_origin(Some("https://news.example.com"), None, None));
+  }
+
+  #[test]
+  fn page_shows_every_delivery_state() {
+    crate::database::test_database();
+
+    let created = NewsItem::example(1, "").created;
+    crate::CONNECTION.with(|c| {
+      use crate::database::schema::{news_items, send_failures, send_records, servers};
+      let early = NewServer { created, ..NewServer::new("<Early>", "https://example.com/1") };
+      let late = NewServer { created: created + Duration::minutes(30), ..NewServer::new("Late", "https://example.com/2") };
+      insert_into(servers::table).values(&vec![early, late]).execute(c).unwrap();
+      let items = vec![
+        NewsItem::example(1, "Patch <5.1> & more").into_new(),
+        NewsItem { created: created + Duration::hours(1), ..NewsItem::example(2, "Second") }.into_new(),
+      ];
+      insert_into(news_items::table).values(&items).execute(c).unwrap();
+      insert_into(send_records::table)
+        .values(&NewSendRecord { server_id: 1, news_id: 1, remote_id: None })
+        .execute(c)
+        .unwrap();
+      insert_into(send_failures::table)
+        .values(&NewSendFailure { server_id: 1, news_id: 2, status: Some(500), error: "<b>boom</b> & \"more\"".into(), attempted: created })
+        .execute(c)
+        .unwrap();
+    });
+
+    let page = page(Some("Sent <script>".into())).unwrap();
+    assert!(page.contains("<p class=\"notice\">Sent &lt;script&gt;</p>"));
+    assert!(page.contains("<td>&lt;Early&gt;</td>"));
+    assert!(page.contains(">Patch &lt;5.1&gt; &amp; more</a>"));
+    assert!(!page.contains("<script>"));
+    assert!(!page.contains("<b>boom</b>"));
+    assert!(page.contains("<pre>&lt;b&gt;boom&lt;/b&gt; &amp; &quot;more&quot;</pre>"));
+
+    let cell = |server_id: i32, news_id: i32| {
+      let button = format!("name=\"server_id\" value=\"{}\"><input type=\"hidden\" name=\"news_id\" value=\"{}\">", server_id, news_id);
+      let end = page.rfind(&button).unwrap_or_else(|| panic!("no button for {} {}", server_id, news_id));
+      let start = page[..end].rfind("<td>").unwrap();
+      page[start..end].to_string()
+    };
+    assert!(cell(1, 1).contains("<span class=\"sent\">sent</span>"));
+    assert!(cell(1, 2).contains("<span class=\"failed\" title=\"&lt;b&gt;boom&lt;/b&gt; &amp; &quot;more&quot;\">failed</span>"));
+    assert!(cell(2, 2).contains("<span class=\"pending\">waiting</span>"));
+    // published before the destination was added
+    assert!(cell(2, 1).starts_with("<td>- "), "{}", cell(2, 1));
+
+    // waiting and failed counts per destination
+    assert!(page.contains("<td>&lt;Early&gt;</td><td>discord</td><td>1</td><td>1</td>"));
+    assert!(page.contains("<td>Late</td><td>discord</td><td>1</td><td>0</td>"));
+  }
+}
@@ -0,0 +1,16 @@
+use tiny_http::Request;
+
+use crate::{
+  errors::*,
+  feed::{self, FeedFilter, FeedFormat},
+  web::{self, WebResponse},
+};
+
+pub fn handle(request: &Request, format: FeedFormat) -> Result<WebResponse> {
+  let filter = match FeedFilter::from_query(&web::query(request)) {
+    Ok(f) => f,
+    Err(e) => return Ok(web::text(400, e)),
+  };
+  let items = filter.load()?;
+  Ok(web::with_type(feed::render(format, &items), format.content_type()))
+}
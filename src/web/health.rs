@@ -0,0 +1,134 @@
+use diesel::{
+  RunQueryDsl,
+  sql_query,
+};
+
+use crate::{
+  database::migrations,
+  errors::*,
+  health::{self, ThreadStatus},
+  web::{self, WebResponse},
+};
+
+use std::collections::BTreeMap;
+
+/// Whether the worker threads are running and the news is being scraped.
+pub fn healthz() -> Result<WebResponse> {
+  let (ok, status) = status();
+  Ok(web::json(code(ok), &status))
+}
+
+/// Like `healthz`, but also checks that the database can be used.
+pub fn readyz() -> Result<WebResponse> {
+  let (ok, mut status) = status();
+  let (database_ok, database) = database();
+  let ok = ok && database_ok;
+  status["ok"] = ok.into();
+  status["database"] = database;
+  Ok(web::json(code(ok), &status))
+}
+
+fn code(ok: bool) -> u16 {
+  if ok { 200 } else { 503 }
+}
+
+fn status() -> (bool, serde_json::Value) {
+  thread_status(&health::threads(), health::scrape_age(), health::max_scrape_age())
+}
+
+fn thread_status(threads: &BTreeMap<&'static str, ThreadStatus>, scrape_age: i64, max_scrape_age: i64) -> (bool, serde_json::Value) {
+  let mut ok = [health::SCRAPER, health::SENDER].iter()
+    .all(|name| threads.get(name).map(|t| t.alive).unwrap_or(false));
+  ok &= scrape_age <= max_scrape_age;
+
+  let threads: serde_json::Map<String, serde_json::Value> = threads.iter()
+    .map(|(name, t)| (name.to_string(), serde_json::json!({
+      "alive": t.alive,
+      "last_success": t.last_success.map(|l| l.to_rfc3339()),
+    })))
+    .collect();
+  (ok, serde_json::json!({
+    "ok": ok,
+    "threads": threads,
+    "scrape_age": scrape_age,
+    "max_scrape_age": max_scrape_age,
+  }))
+}
+
+/// Whether the database can be reached and has every migration applied.
+fn database() -> (bool, serde_json::Value) {
+  let reachable = crate::CONNECTION.with(|c| sql_query("select 1").execute(c));
+  let migrated = migrations::status()
+    .and_then(|s| s.check_current())
+    .and_then(|_| health::check_schema());
+  let ok = reachable.is_ok() && migrated.is_ok();
+  let mut status = serde_json::json!({
+    "reachable": reachable.is_ok(),
+    "migrated": migrated.is_ok(),
+  });
+  if let Err(e) = reachable.chain_err(|| "database is unreachable").and(migrated) {
+    status["error"] = e.to_string().into();
+  }
+  (ok, status)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use chrono::Utc;
+
+  fn threads(scraper: bool, sender: bool) -> BTreeMap<&'static str, ThreadStatus> {
+    let mut threads = BTreeMap::new();
+    threads.insert(health::SCRAPER, ThreadStatus { alive: scraper, last_success: Some(Utc::now()) });
+    threads.insert(health::SENDER, ThreadStatus { alive: sender, last_success: None });
+    threads
+  }
+
+  #[test]
+  fn old_scrapes_are_unhealthy() {
+    let (ok, status) = thread_status(&threads(true, true), 60, 900);
+    assert_eq!(code(ok), 200);
+    assert_eq!(status["ok"], true);
+    assert_eq!(status["scrape_age"], 60);
+
+    assert_eq!(code(thread_status(&threads(true, true), 900, 900).0), 200);
+    let (ok, status) = thread_status(&threads(true, true), 901, 900);
+    assert_eq!(code(ok), 503);
+    assert_eq!(status["ok"], false);
+    assert_eq!(status["max_scrape_age"], 900);
+  }
+
+  #[test]
+  fn dead_threads_are_unhealthy() {
+    let (ok, status) = thread_status(&threads(true, false), 0, 900);
+    assert_eq!(code(ok), 503);
+    assert_eq!(status["threads"]["sender"]["alive"], false);
+    assert_eq!(status["threads"]["scraper"]["alive"], true);
+    assert!(status["threads"]["scraper"]["last_success"].is_string());
+
+    // a thread that never started counts as dead
+    let mut missing = threads(true, true);
+    missing.remove(health::SCRAPER);
+    assert!(!thread_status(&missing, 0, 900).0);
+  }
+
+  #[test]
+  fn pending_migrations_are_not_ready() {
+    // an empty database, with nothing applied
+    std::env::set_var("LN_DATABASE_LOCATION", ":memory:");
+    let (ok, status) = database();
+    assert!(!ok);
+    assert_eq!(status["reachable"], true);
+    assert_eq!(status["migrated"], false);
+    assert!(status["error"].is_string());
+  }
+
+  #[test]
+  fn migrated_databases_are_ready() {
+    crate::database::test_database();
+    let (ok, status) = database();
+    assert!(ok, "{}", status);
+    assert_eq!(status, serde_json::json!({ "reachable": true, "migrated": true }));
+  }
+}
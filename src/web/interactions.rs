@@ -0,0 +1,512 @@
+use diesel::{
+  prelude::*,
+  insert_into,
+  update,
+};
+
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+
+use serde_derive::Deserialize;
+
+use serde_json::Value;
+
+use tiny_http::Request;
+
+use crate::{
+  database::models::{
+    news_item::{NewsItem, NewsKind},
+    server::{NewServer, Server},
+  },
+  discord::DiscordSink,
+  errors::*,
+  validation,
+  web::{self, WebResponse},
+};
+
+use chrono::Utc;
+
+use std::env;
+
+// how far `X-Signature-Timestamp` may be from the current time, in seconds
+const MAX_TIMESTAMP_SKEW: i64 = 5 * 60;
+
+const PING: u8 = 1;
+const APPLICATION_COMMAND: u8 = 2;
+
+const PONG: u8 = 1;
+const CHANNEL_MESSAGE_WITH_SOURCE: u8 = 4;
+
+const EPHEMERAL: u64 = 1 << 6;
+const MANAGE_GUILD: u64 = 1 << 5;
+
+#[derive(Debug, Deserialize)]
+struct Interaction {
+  #[serde(rename = "type")]
+  kind: u8,
+  data: Option<CommandData>,
+  guild_id: Option<String>,
+  channel_id: Option<String>,
+  member: Option<Member>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommandData {
+  name: String,
+  #[serde(default)]
+  options: Vec<CommandOption>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommandOption {
+  name: String,
+  value: Option<Value>,
+  #[serde(default)]
+  options: Vec<CommandOption>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Member {
+  permissions: String,
+}
+
+impl Interaction {
+  fn can_manage(&self) -> bool {
+    self.member.as_ref()
+      .and_then(|m| m.permissions.parse::<u64>().ok())
+      .map(|p| p & MANAGE_GUILD == MANAGE_GUILD)
+      .unwrap_or(false)
+  }
+}
+
+fn option<'a>(options: &'a [CommandOption], name: &str) -> Option<&'a str> {
+  options.iter()
+    .find(|o| o.name == name)
+    .and_then(|o| o.value.as_ref())
+    .and_then(Value::as_str)
+}
+
+pub fn handle(request: &mut Request) -> Result<WebResponse> {
+  let public_key = env::var("LN_DISCORD_PUBLIC_KEY").chain_err(|| "LN_DISCORD_PUBLIC_KEY is not set")?;
+  let signature = web::header(request, "X-Signature-Ed25519").map(ToString::to_string);
+  let timestamp = web::header(request, "X-Signature-Timestamp").map(ToString::to_string);
+  let body = match web::read_body(request)? {
+    Ok(b) => b,
+    Err(res) => return Ok(res),
+  };
+
+  Ok(match respond(&public_key, signature, timestamp, &body, Utc::now().timestamp())? {
+    Ok(response) => web::json(200, &response),
+    Err((status, message)) => web::text(status, message),
+  })
+}
+
+/// The response to an interaction, or the status and message it is refused with.
+///
+/// Requests signed more than `MAX_TIMESTAMP_SKEW` seconds away from `now` are
+/// refused, so a captured request can't be replayed later.
+fn respond(public_key: &str, signature: Option<String>, timestamp: Option<String>, body: &[u8], now: i64) -> Result<std::result::Result<Value, (u16, String)>> {
+  let (signature, timestamp) = match (signature, timestamp) {
+    (Some(s), Some(t)) => (s, t),
+    _ => return Ok(Err((401, "invalid request signature".into()))),
+  };
+  if !verify(public_key, &signature, &timestamp, body) {
+    return Ok(Err((401, "invalid request signature".into())));
+  }
+  match timestamp.parse::<i64>() {
+    Ok(t) if (now - t).abs() <= MAX_TIMESTAMP_SKEW => {},
+    _ => return Ok(Err((401, "request timestamp is too old".into()))),
+  }
+
+  let interaction: Interaction = match serde_json::from_slice(body) {
+    Ok(i) => i,
+    Err(e) => return Ok(Err((400, format!("invalid interaction: {}", e)))),
+  };
+
+  Ok(match interaction.kind {
+    PING => Ok(serde_json::json!({ "type": PONG })),
+    APPLICATION_COMMAND => Ok(command(&interaction)?),
+    _ => Err((400, "unsupported interaction type".into())),
+  })
+}
+
+pub fn verify(public_key: &str, signature: &str, timestamp: &str, body: &[u8]) -> bool {
+  let key = match hex::decode(public_key).ok().and_then(|k| PublicKey::from_bytes(&k).ok()) {
+    Some(k) => k,
+    None => {
+      warn!("LN_DISCORD_PUBLIC_KEY is not a valid public key");
+      return false;
+    },
+  };
+  let signature = match hex::decode(signature).ok().and_then(|s| Signature::from_bytes(&s).ok()) {
+    Some(s) => s,
+    None => return false,
+  };
+  let mut message = timestamp.as_bytes().to_vec();
+  message.extend_from_slice(body);
+  key.verify(&message, &signature).is_ok()
+}
+
+fn command(interaction: &Interaction) -> Result<Value> {
+  let data = match interaction.data {
+    Some(ref d) if d.name == "lodestone" => d,
+    _ => return Ok(reply("Unknown command.")),
+  };
+  let sub = match data.options.first() {
+    Some(s) => s,
+    None => return Ok(reply("Missing subcommand.")),
+  };
+
+  if sub.name == "latest" {
+    return latest(&sub.options);
+  }
+
+  let (guild_id, channel_id) = match (&interaction.guild_id, &interaction.channel_id) {
+    (Some(g), Some(c)) => (g.as_str(), c.as_str()),
+    _ => return Ok(reply("This command can only be used in a server.")),
+  };
+  if !interaction.can_manage() {
+    return Ok(reply("You need the Manage Server permission to do that."));
+  }
+
+  match sub.name.as_str() {
+    "subscribe" => subscribe(guild_id, channel_id, &sub.options),
+    "unsubscribe" => unsubscribe(guild_id, channel_id),
+    "filter" => filter(guild_id, channel_id, &sub.options),
+    _ => Ok(reply("Unknown subcommand.")),
+  }
+}
+
+fn reply<S: AsRef<str>>(content: S) -> Value {
+  serde_json::json!({
+    "type": CHANNEL_MESSAGE_WITH_SOURCE,
+    "data": {
+      "content": content.as_ref(),
+      "flags": EPHEMERAL,
+    },
+  })
+}
+
+fn find_server(guild_id: &str, channel_id: &str) -> Result<Option<Server>> {
+  crate::CONNECTION.with(|c| {
+    use crate::database::schema::servers;
+    servers::table
+      .filter(servers::guild_id.eq(guild_id))
+      .filter(servers::channel_id.eq(channel_id))
+      .first(c)
+      .optional()
+      .chain_err(|| "could not load server")
+  })
+}
+
+fn subscribe(guild_id: &str, channel_id: &str, options: &[CommandOption]) -> Result<Value> {
+  let webhook = match option(options, "webhook") {
+    Some(w) => w,
+    None => return Ok(reply("A webhook URL is required.")),
+  };
+  if !validation::is_discord_webhook(webhook) {
+    return Ok(reply("That does not look like a Discord webhook URL."));
+  }
+  let title = option(options, "title").unwrap_or(guild_id);
+
+  if let Some(server) = find_server(guild_id, channel_id)? {
+    crate::CONNECTION.with(|c| {
+      use crate::database::schema::servers;
+      update(&server)
+        .set((servers::url.eq(webhook), servers::title.eq(title)))
+        .execute(c)
+        .chain_err(|| "could not update server")
+    })?;
+    return Ok(reply("Updated the webhook for this channel."));
+  }
+
+  let mut server = NewServer::new(title, webhook);
+  server.guild_id = Some(guild_id.to_string());
+  server.channel_id = Some(channel_id.to_string());
+  crate::CONNECTION.with(|c| {
+    use crate::database::schema::servers;
+    insert_into(servers::table)
+      .values(&server)
+      .execute(c)
+      .chain_err(|| "could not insert server")
+  })?;
+  info!("Subscribed guild {} channel {} via interaction", guild_id, channel_id);
+  Ok(reply("Subscribed! New Lodestone news will be posted to this channel."))
+}
+
+fn unsubscribe(guild_id: &str, channel_id: &str) -> Result<Value> {
+  let server = match find_server(guild_id, channel_id)? {
+    Some(s) => s,
+    None => return Ok(reply("This channel is not subscribed.")),
+  };
+  server.delete()?;
+  info!("Unsubscribed guild {} channel {} via interaction", guild_id, channel_id);
+  Ok(reply("Unsubscribed. No more news will be posted to this channel."))
+}
+
+fn filter(guild_id: &str, channel_id: &str, options: &[CommandOption]) -> Result<Value> {
+  let server = match find_server(guild_id, channel_id)? {
+    Some(s) => s,
+    None => return Ok(reply("This channel is not subscribed.")),
+  };
+
+  let kinds = match option(options, "kinds") {
+    Some(k) => match validation::kinds(k) {
+      Ok(kinds) => Some(kinds),
+      Err(e) => return Ok(reply(format!("{}. Use news, topic or special notice.", e))),
+    },
+    None => None,
+  };
+  let tags = option(options, "tags").map(validation::tags);
+
+  crate::CONNECTION.with(|c| {
+    use crate::database::schema::servers;
+    update(&server)
+      .set((servers::filter_kinds.eq(&kinds), servers::filter_tags.eq(&tags)))
+      .execute(c)
+      .chain_err(|| "could not update filters")
+  })?;
+
+  Ok(reply(match (kinds, tags) {
+    (None, None) => "Filters cleared. All news will be posted.".to_string(),
+    (k, t) => format!(
+      "Only posting kinds: {}; tags: {}.",
+      k.unwrap_or_else(|| "any".into()),
+      t.unwrap_or_else(|| "any".into()),
+    ),
+  }))
+}
+
+fn latest(options: &[CommandOption]) -> Result<Value> {
+  let kind = match option(options, "kind").map(str::parse::<NewsKind>) {
+    Some(Ok(k)) => Some(k),
+    Some(Err(e)) => return Ok(reply(e.to_string())),
+    None => None,
+  };
+  let item: Option<NewsItem> = crate::CONNECTION.with(|c| {
+    use crate::database::schema::news_items;
+    let mut query = news_items::table.order(news_items::created.desc()).into_boxed();
+    if let Some(ref kind) = kind {
+      query = query.filter(news_items::kind.eq(kind));
+    }
+    query.first(c)
+      .optional()
+      .chain_err(|| "could not load latest item")
+  })?;
+  Ok(match item {
+    Some(item) => serde_json::json!({
+      "type": CHANNEL_MESSAGE_WITH_SOURCE,
+      "data": {
+        "embeds": [DiscordSink::embed(&item)],
+      },
+    }),
+    None => reply("No news has been collected yet."),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use ed25519_dalek::{Keypair, SecretKey, Signer};
+
+  use std::fs;
+
+  const TIMESTAMP: &str = "1570000000";
+  const NOW: i64 = 1_570_000_060;
+
+  fn keypair() -> Keypair {
+    let secret = SecretKey::from_bytes(&[7; 32]).unwrap();
+    let public = PublicKey::from(&secret);
+    Keypair { secret, public }
+  }
+
+  fn fixture(name: &str) -> Vec<u8> {
+    fs::read(format!("fixtures/interactions/{}.json", name)).unwrap()
+  }
+
+  /// Signs a body with the test key and sends it.
+  fn send(body: &[u8]) -> std::result::Result<Value, (u16, String)> {
+    let keypair = keypair();
+    let mut message = TIMESTAMP.as_bytes().to_vec();
+    message.extend_from_slice(body);
+    let signature = hex::encode(&keypair.sign(&message).to_bytes()[..]);
+    respond(&hex::encode(keypair.public.as_bytes()), Some(signature), Some(TIMESTAMP.into()), body, NOW).unwrap()
+  }
+
+  fn content(response: &Value) -> &str {
+    assert_eq!(response["type"], CHANNEL_MESSAGE_WITH_SOURCE);
+    response["data"]["content"].as_str().unwrap()
+  }
+
+  fn servers() -> Vec<Server> {
+    crate::CONNECTION.with(|c| {
+      use crate::database::schema::servers;
+      servers::table.load(c).unwrap()
+    })
+  }
+
+  #[test]
+  fn shipped_signatures_match_the_shipped_key() {
+    let public_key = fs::read_to_string("fixtures/interactions/public_key").unwrap();
+    for name in &["ping", "subscribe", "filter", "latest", "unsubscribe"] {
+      let signature = fs::read_to_string(format!("fixtures/interactions/{}.sig", name)).unwrap();
+      assert!(verify(public_key.trim(), signature.trim(), TIMESTAMP, &fixture(name)), "{} is not signed", name);
+    }
+  }
+
+  #[test]
+  fn ping_gets_pong() {
+    assert_eq!(send(&fixture("ping")).unwrap(), serde_json::json!({ "type": PONG }));
+  }
+
+  #[test]
+  fn bad_signatures_are_rejected() {
+    let keypair = keypair();
+    let public_key = hex::encode(keypair.public.as_bytes());
+    let body = fixture("ping");
+    let signature = hex::encode(&keypair.sign(b"something else").to_bytes()[..]);
+
+    let status = |signature: Option<&str>, timestamp: Option<&str>| {
+      respond(&public_key, signature.map(Into::into), timestamp.map(Into::into), &body, NOW).unwrap()
+        .err()
+        .map(|(status, _)| status)
+    };
+    assert_eq!(status(Some(&signature), Some(TIMESTAMP)), Some(401));
+    assert_eq!(status(Some("not hex"), Some(TIMESTAMP)), Some(401));
+    assert_eq!(status(None, Some(TIMESTAMP)), Some(401));
+    assert_eq!(status(Some(&signature), None), Some(401));
+
+    // signed correctly, but for a different timestamp
+    let mut message = b"1570000001".to_vec();
+    message.extend_from_slice(&body);
+    let signature = hex::encode(&keypair.sign(&message).to_bytes()[..]);
+    assert_eq!(status(Some(&signature), Some(TIMESTAMP)), Some(401));
+  }
+
+  #[test]
+  fn old_timestamps_are_rejected() {
+    let keypair = keypair();
+    let public_key = hex::encode(keypair.public.as_bytes());
+    let body = fixture("ping");
+    let mut message = TIMESTAMP.as_bytes().to_vec();
+    message.extend_from_slice(&body);
+    let signature = hex::encode(&keypair.sign(&message).to_bytes()[..]);
+
+    let status = |now: i64| {
+      respond(&public_key, Some(signature.clone()), Some(TIMESTAMP.into()), &body, now).unwrap()
+        .err()
+        .map(|(status, _)| status)
+    };
+    assert_eq!(status(1_570_000_000 + MAX_TIMESTAMP_SKEW), None);
+    assert_eq!(status(1_570_000_000 + MAX_TIMESTAMP_SKEW + 1), Some(401));
+    // a timestamp from the future is just as suspicious
+    assert_eq!(status(1_570_000_000 - MAX_TIMESTAMP_SKEW - 1), Some(401));
+  }
+
+  #[test]
+  fn invalid_bodies_are_rejected() {
+    match send(b"not json") {
+      Err((400, _)) => {},
+      r => panic!("unexpected response {:?}", r),
+    }
+    match send(br#"{"type": 3}"#) {
+      Err((400, _)) => {},
+      r => panic!("unexpected response {:?}", r),
+    }
+  }
+
+  #[test]
+  fn subscribe_filter_and_unsubscribe() {
+    crate::database::test_database();
+
+    let response = send(&fixture("subscribe")).unwrap();
+    assert_eq!(content(&response), "Subscribed! New Lodestone news will be posted to this channel.");
+    assert_eq!(response["data"]["flags"], EPHEMERAL);
+    let servers = servers();
+    assert_eq!(servers.len(), 1);
+    assert_eq!(servers[0].title, "Fixture guild");
+    assert_eq!(servers[0].url, "https://discord.com/api/webhooks/1/fixture");
+    assert_eq!(servers[0].guild_id, Some("100".to_string()));
+    assert_eq!(servers[0].channel_id, Some("200".to_string()));
+
+    // subscribing again updates the existing server
+    assert_eq!(content(&send(&fixture("subscribe")).unwrap()), "Updated the webhook for this channel.");
+    assert_eq!(self::servers().len(), 1);
+
+    let response = send(&fixture("filter")).unwrap();
+    assert!(content(&response).starts_with("Only posting kinds: "), "{}", content(&response));
+    let server = &self::servers()[0];
+    assert!(server.filter_kinds.is_some());
+    assert!(server.filter_tags.is_some());
+
+    // everything recorded for the server goes with it
+    crate::CONNECTION.with(|c| {
+      use crate::database::{
+        models::{
+          reminder::NewReminder,
+          scheduled_event::NewScheduledEvent,
+        },
+        schema::{news_items, reminders, scheduled_events},
+      };
+      let item = NewsItem::example(1, "Maintenance");
+      let created = item.created;
+      insert_into(news_items::table).values(&item.into_new()).execute(c).unwrap();
+      insert_into(scheduled_events::table)
+        .values(&NewScheduledEvent { server_id: server.id, news_id: 1, event_id: "1".into(), start_time: created, end_time: created })
+        .execute(c)
+        .unwrap();
+      insert_into(reminders::table)
+        .values(&NewReminder { server_id: server.id, news_id: 1, kind: "start".into(), due: created, sent: false })
+        .execute(c)
+        .unwrap();
+    });
+
+    assert_eq!(content(&send(&fixture("unsubscribe")).unwrap()), "Unsubscribed. No more news will be posted to this channel.");
+    assert!(self::servers().is_empty());
+    crate::CONNECTION.with(|c| {
+      use crate::database::schema::{reminders, scheduled_events};
+      assert_eq!(scheduled_events::table.count().get_result::<i64>(c).unwrap(), 0);
+      assert_eq!(reminders::table.count().get_result::<i64>(c).unwrap(), 0);
+    });
+    assert_eq!(content(&send(&fixture("unsubscribe")).unwrap()), "This channel is not subscribed.");
+  }
+
+  #[test]
+  fn managing_needs_permission() {
+    crate::database::test_database();
+
+    let body = String::from_utf8(fixture("subscribe")).unwrap().replace("2147483647", "0");
+    assert_eq!(content(&send(body.as_bytes()).unwrap()), "You need the Manage Server permission to do that.");
+    assert!(servers().is_empty());
+  }
+
+  #[test]
+  fn subscribe_needs_a_discord_webhook() {
+    crate::database::test_database();
+
+    let body = String::from_utf8(fixture("subscribe")).unwrap().replace("https://discord.com/api/webhooks/1/fixture", "https://example.com/");
+    assert_eq!(content(&send(body.as_bytes()).unwrap()), "That does not look like a Discord webhook URL.");
+    assert!(servers().is_empty());
+  }
+
+  #[test]
+  fn latest_shows_the_newest_item() {
+    crate::database::test_database();
+
+    assert_eq!(content(&send(&fixture("latest")).unwrap()), "No news has been collected yet.");
+
+    crate::CONNECTION.with(|c| {
+      use crate::database::schema::news_items;
+      let older = NewsItem::example(1, "Older");
+      let newer = NewsItem {
+        created: older.created + chrono::Duration::hours(1),
+        ..NewsItem::example(2, "Newer")
+      };
+      let items = vec![older.into_new(), newer.into_new()];
+      insert_into(news_items::table).values(&items).execute(c).unwrap();
+    });
+    let response = send(&fixture("latest")).unwrap();
+    assert_eq!(response["type"], CHANNEL_MESSAGE_WITH_SOURCE);
+    assert_eq!(response["data"]["embeds"][0]["title"], "Newer");
+  }
+}
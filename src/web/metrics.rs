@@ -0,0 +1,9 @@
+use crate::{
+  errors::*,
+  metrics,
+  web::{self, WebResponse},
+};
+
+pub fn handle() -> Result<WebResponse> {
+  Ok(web::with_type(metrics::render()?, "text/plain; version=0.0.4; charset=utf-8"))
+}
@@ -0,0 +1,163 @@
+use crossbeam_channel as chan;
+
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+use crate::{
+  errors::*,
+  feed::FeedFormat,
+};
+
+use std::{
+  env,
+  io::{Cursor, Read},
+  thread,
+  time::Duration,
+};
+
+pub mod admin;
+pub mod dashboard;
+pub mod feeds;
+pub mod health;
+pub mod interactions;
+pub mod metrics;
+pub mod news;
+pub mod stream;
+
+pub type WebResponse = Response<Cursor<Vec<u8>>>;
+
+const DEFAULT_WORKERS: usize = 8;
+// requests waiting for a free worker; any more are turned away
+const QUEUE_SIZE: usize = 64;
+/// The largest request body that will be read.
+pub const MAX_BODY_SIZE: usize = 64 * 1024;
+
+fn workers() -> usize {
+  env::var("LN_WEB_WORKERS").ok()
+    .and_then(|s| s.parse().ok())
+    .filter(|&n| n > 0)
+    .unwrap_or(DEFAULT_WORKERS)
+}
+
+pub fn serve(address: &str, exit_rx: chan::Receiver<()>) -> Result<()> {
+  let server = Server::http(address).map_err(|e| format!("could not listen on {}: {}", address, e))?;
+  info!("Listening on {}", address);
+
+  let (queue_tx, queue_rx) = chan::bounded::<Request>(QUEUE_SIZE);
+  for _ in 0..workers() {
+    let queue_rx = queue_rx.clone();
+    // the workers stop once `queue_tx` is dropped
+    thread::spawn(move || for request in queue_rx.iter() {
+      handle(request);
+    });
+  }
+
+  loop {
+    match server.recv_timeout(Duration::from_secs(1)) {
+      Ok(Some(request)) => dispatch(request, &queue_tx),
+      Ok(None) => {},
+      Err(e) => warn!("Could not receive web request: {}", e),
+    }
+    match exit_rx.try_recv() {
+      Err(chan::TryRecvError::Empty) => {},
+      _ => break,
+    }
+  }
+
+  Ok(())
+}
+
+fn path(request: &Request) -> String {
+  request.url().split('?').next().unwrap_or_default().to_string()
+}
+
+fn dispatch(request: Request, queue_tx: &chan::Sender<Request>) {
+  if *request.method() == Method::Get && path(&request) == "/stream" {
+    // the stream writes its own response and holds the connection open, so it
+    // gets its own thread instead of a worker. `stream::handle` enforces the limit
+    thread::spawn(move || stream::handle(request));
+    return;
+  }
+  match queue_tx.try_send(request) {
+    Ok(()) => {},
+    Err(chan::TrySendError::Full(request)) | Err(chan::TrySendError::Disconnected(request)) => {
+      warn!("Turning away {} {}: every worker is busy", request.method(), path(&request));
+      if let Err(e) = request.respond(text(503, "server is busy")) {
+        warn!("Could not respond to a turned away request: {}", e);
+      }
+    },
+  }
+}
+
+fn handle(mut request: Request) {
+  let method = request.method().clone();
+  let path = path(&request);
+  let res = match (&method, path.as_str()) {
+    (_, p) if p.starts_with("/admin/") => admin::handle(&mut request, &method, p),
+    (_, p) if p == "/dashboard" || p.starts_with("/dashboard/") => dashboard::handle(&mut request, &method, p),
+    (Method::Post, "/interactions") => interactions::handle(&mut request),
+    (Method::Get, "/feed.rss") => feeds::handle(&request, FeedFormat::Rss),
+    (Method::Get, "/feed.atom") => feeds::handle(&request, FeedFormat::Atom),
+    (Method::Get, "/feed.json") => feeds::handle(&request, FeedFormat::Json),
+    (Method::Get, "/healthz") => health::healthz(),
+    (Method::Get, "/readyz") => health::readyz(),
+    (Method::Get, "/metrics") => metrics::handle(),
+    (Method::Get, "/news") => news::list(&request),
+    (Method::Get, p) if p.starts_with("/news/") => news::show(&p["/news/".len()..]),
+    _ => Ok(text(404, "not found")),
+  };
+  let response = match res {
+    Ok(r) => r,
+    Err(e) => {
+      warn!("Could not handle {} {}: {}", method, path, e);
+      text(500, "internal server error")
+    },
+  };
+  if let Err(e) = request.respond(response) {
+    warn!("Could not respond to {} {}: {}", method, path, e);
+  }
+}
+
+pub fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+  request.headers().iter()
+    .find(|h| h.field.equiv(name))
+    .map(|h| h.value.as_str())
+}
+
+/// The decoded query string parameters of a request, in order.
+pub fn query(request: &Request) -> Vec<(String, String)> {
+  match request.url().splitn(2, '?').nth(1) {
+    Some(query) => url::form_urlencoded::parse(query.as_bytes()).into_owned().collect(),
+    None => Vec::new(),
+  }
+}
+
+/// Reads the request body, or returns a 413 response if it is larger than
+/// `MAX_BODY_SIZE`.
+pub fn read_body(request: &mut Request) -> Result<std::result::Result<Vec<u8>, WebResponse>> {
+  let too_large = || text(413, format!("request body is larger than {} bytes", MAX_BODY_SIZE));
+  if request.body_length().map(|len| len > MAX_BODY_SIZE).unwrap_or(false) {
+    return Ok(Err(too_large()));
+  }
+  let mut body = Vec::new();
+  request.as_reader()
+    .take(MAX_BODY_SIZE as u64 + 1)
+    .read_to_end(&mut body)
+    .chain_err(|| "could not read request body")?;
+  if body.len() > MAX_BODY_SIZE {
+    return Ok(Err(too_large()));
+  }
+  Ok(Ok(body))
+}
+
+pub fn text<S: Into<String>>(status: u16, body: S) -> WebResponse {
+  with_type(body, "text/plain; charset=utf-8").with_status_code(StatusCode(status))
+}
+
+pub fn json(status: u16, body: &serde_json::Value) -> WebResponse {
+  with_type(body.to_string(), "application/json").with_status_code(StatusCode(status))
+}
+
+// `Response::from_string` would add its own text/plain content type
+pub fn with_type<S: Into<String>>(body: S, content_type: &str) -> WebResponse {
+  Response::from_data(body.into().into_bytes()).with_header(Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap())
+}
@@ -0,0 +1,160 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+
+use diesel::prelude::*;
+
+use tiny_http::{Header, Request};
+
+use crate::{
+  database::{
+    models::news_item::NewsItem,
+    schema::news_items,
+  },
+  errors::*,
+  export::ExportedItem,
+  feed::FeedFilter,
+  web::{self, WebResponse},
+};
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/// Lists items newest first. Takes the feed filters plus:
+///
+/// - `since` – only items created at or after an RFC 3339 time or a `YYYY-MM-DD` date
+/// - `q` – only items whose title or description contains the text
+/// - `cursor` – the `next_cursor` of the previous page
+pub fn list(request: &Request) -> Result<WebResponse> {
+  let params = web::query(request);
+  let filter = match FeedFilter::from_query(&params) {
+    Ok(f) => f,
+    Err(e) => return Ok(error(400, &e)),
+  };
+  let mut query = filter.query();
+
+  for (key, value) in &params {
+    match key.as_str() {
+      "since" => match parse_since(value) {
+        Some(since) => query = query.filter(news_items::created.ge(since)),
+        None => return Ok(error(400, &format!("invalid since: {}", value))),
+      },
+      "q" => {
+        let pattern = format!("%{}%", value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+        query = query.filter(
+          news_items::title.like(pattern.clone()).escape('\\')
+            .or(news_items::description.like(pattern).escape('\\'))
+        );
+      },
+      "cursor" => match Cursor::parse(value) {
+        Some(cursor) => query = query.filter(
+          news_items::created.lt(cursor.created)
+            .or(news_items::created.eq(cursor.created).and(news_items::id.lt(cursor.id)))
+        ),
+        None => return Ok(error(400, &format!("invalid cursor: {}", value))),
+      },
+      _ => {},
+    }
+  }
+
+  let limit = filter.limit();
+  // one extra item shows whether there is another page
+  let mut items: Vec<NewsItem> = crate::CONNECTION.with(|c| {
+    query.limit(limit + 1).load(c).chain_err(|| "could not load news")
+  })?;
+  let next_cursor = if items.len() as i64 > limit {
+    items.truncate(limit as usize);
+    items.last().map(|i| Cursor { created: i.created, id: i.id }.to_string())
+  } else {
+    None
+  };
+
+  let items: Vec<ExportedItem> = items.iter().map(ExportedItem::from).collect();
+  Ok(json(200, &serde_json::json!({
+    "items": items,
+    "next_cursor": next_cursor,
+  })))
+}
+
+pub fn show(lodestone_id: &str) -> Result<WebResponse> {
+  let item: Option<NewsItem> = crate::CONNECTION.with(|c| {
+    news_items::table
+      .filter(news_items::lodestone_id.eq(lodestone_id))
+      .first(c)
+      .optional()
+      .chain_err(|| "could not load news item")
+  })?;
+  match item {
+    Some(item) => Ok(json(200, &serde_json::to_value(ExportedItem::from(&item)).chain_err(|| "could not serialize item")?)),
+    None => Ok(error(404, "not found")),
+  }
+}
+
+/// The position after the last item of a page, as `<created unix time>.<id>`.
+struct Cursor {
+  created: NaiveDateTime,
+  id: i32,
+}
+
+impl Cursor {
+  fn parse(s: &str) -> Option<Cursor> {
+    let mut parts = s.splitn(2, '.');
+    let created = parts.next()?.parse().ok()?;
+    let id = parts.next()?.parse().ok()?;
+    Some(Cursor {
+      created: NaiveDateTime::from_timestamp_opt(created, 0)?,
+      id,
+    })
+  }
+}
+
+impl Display for Cursor {
+  fn fmt(&self, f: &mut Formatter) -> FmtResult {
+    write!(f, "{}.{}", self.created.timestamp(), self.id)
+  }
+}
+
+/// Parses an RFC 3339 time or a `YYYY-MM-DD` date as UTC.
+pub fn parse_since(s: &str) -> Option<NaiveDateTime> {
+  if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+    return Some(time.naive_utc());
+  }
+  NaiveDate::parse_from_str(s, "%Y-%m-%d").ok().map(|d| d.and_hms(0, 0, 0))
+}
+
+// the api is meant to be used from other sites, so let browsers read it
+fn json(status: u16, body: &serde_json::Value) -> WebResponse {
+  web::json(status, body)
+    .with_header(Header::from_bytes(&b"Access-Control-Allow-Origin"[..], &b"*"[..]).unwrap())
+}
+
+fn error(status: u16, message: &str) -> WebResponse {
+  json(status, &serde_json::json!({ "error": message }))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn cursors_round_trip() {
+    let cursor = Cursor { created: NaiveDateTime::from_timestamp(1_500_000_000, 0), id: 42 };
+    assert_eq!(cursor.to_string(), "1500000000.42");
+    let parsed = Cursor::parse("1500000000.42").unwrap();
+    assert_eq!(parsed.created, cursor.created);
+    assert_eq!(parsed.id, 42);
+  }
+
+  #[test]
+  fn malformed_cursors_are_rejected() {
+    for cursor in &["", "1500000000", "1500000000.", ".42", "abc.42", "1500000000.abc", "1500000000.4.2", "1500000000.42 ", "99999999999999999.1"] {
+      assert!(Cursor::parse(cursor).is_none(), "{:?} was accepted", cursor);
+    }
+  }
+
+  #[test]
+  fn since_takes_times_and_dates() {
+    assert_eq!(parse_since("2019-10-01"), Some(NaiveDate::from_ymd(2019, 10, 1).and_hms(0, 0, 0)));
+    assert_eq!(parse_since("2019-10-01T12:00:00+02:00"), Some(NaiveDate::from_ymd(2019, 10, 1).and_hms(10, 0, 0)));
+    assert_eq!(parse_since("2019-10-01T12:00:00Z"), Some(NaiveDate::from_ymd(2019, 10, 1).and_hms(12, 0, 0)));
+    assert_eq!(parse_since("yesterday"), None);
+    assert_eq!(parse_since("2019-13-01"), None);
+  }
+}
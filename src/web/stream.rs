@@ -0,0 +1,186 @@
+use crossbeam_channel as chan;
+
+use diesel::prelude::*;
+
+use tiny_http::Request;
+
+use crate::{
+  broadcast,
+  database::{
+    models::news_item::NewsItem,
+    schema::news_items,
+  },
+  errors::*,
+  export::ExportedItem,
+  feed::FeedFilter,
+  web,
+};
+
+use std::{
+  env,
+  io::Write,
+  sync::atomic::{AtomicUsize, Ordering},
+  time::Duration,
+};
+
+const BATCH: i64 = 100;
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+const DEFAULT_MAX_STREAMS: usize = 100;
+
+// every stream holds a thread and a database connection for as long as the client stays
+static STREAMS: AtomicUsize = AtomicUsize::new(0);
+
+/// A place among the open streams, given up when dropped.
+struct Slot;
+
+impl Slot {
+  fn take() -> Option<Slot> {
+    if STREAMS.fetch_add(1, Ordering::SeqCst) >= max_streams() {
+      STREAMS.fetch_sub(1, Ordering::SeqCst);
+      return None;
+    }
+    Some(Slot)
+  }
+}
+
+impl Drop for Slot {
+  fn drop(&mut self) {
+    STREAMS.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+/// How many streams may be open at once.
+fn max_streams() -> usize {
+  env::var("LN_MAX_STREAMS").ok()
+    .and_then(|s| s.parse().ok())
+    .unwrap_or(DEFAULT_MAX_STREAMS)
+}
+
+/// Streams new items as server-sent events, taking the same filters as the feeds.
+///
+/// Every event's id is the item's id, so a client reconnecting with `Last-Event-ID` (or
+/// `?last_event_id=` where it can't set headers) gets everything it missed from the database.
+/// Without either, only items inserted after connecting are sent.
+pub fn handle(request: Request) {
+  let _slot = match Slot::take() {
+    Some(s) => s,
+    None => return respond(request, web::json(503, &serde_json::json!({ "error": "too many open streams" }))),
+  };
+  let params = web::query(&request);
+  let filter = match FeedFilter::from_query(&params) {
+    Ok(f) => f,
+    Err(e) => return respond(request, web::json(400, &serde_json::json!({ "error": e }))),
+  };
+  let last_id = web::header(&request, "Last-Event-ID")
+    .map(ToString::to_string)
+    .or_else(|| params.iter().find(|(k, _)| k == "last_event_id").map(|(_, v)| v.clone()));
+  let last_id = match last_id {
+    Some(id) => match id.trim().parse() {
+      Ok(id) => id,
+      Err(_) => return respond(request, web::json(400, &serde_json::json!({ "error": format!("invalid last event id: {}", id) }))),
+    },
+    None => match newest_id() {
+      Ok(id) => id,
+      Err(e) => {
+        warn!("Could not start stream: {}", e);
+        return respond(request, web::text(500, "internal server error"));
+      },
+    },
+  };
+
+  // subscribe before catching up so nothing inserted in between is missed
+  let rx = broadcast::subscribe();
+  let mut writer = request.into_writer();
+  if let Err(e) = stream(&mut writer, &rx, &filter, last_id) {
+    debug!("Closed stream: {}", e);
+  }
+}
+
+fn respond(request: Request, response: web::WebResponse) {
+  if let Err(e) = request.respond(response) {
+    warn!("Could not respond to stream request: {}", e);
+  }
+}
+
+fn stream(writer: &mut dyn Write, rx: &chan::Receiver<()>, filter: &FeedFilter, mut last_id: i32) -> Result<()> {
+  write!(
+    writer,
+    "HTTP/1.1 200 OK\r\n\
+     Content-Type: text/event-stream\r\n\
+     Cache-Control: no-cache\r\n\
+     Connection: close\r\n\
+     Access-Control-Allow-Origin: *\r\n\
+     X-Accel-Buffering: no\r\n\
+     \r\n",
+  ).chain_err(|| "could not write headers")?;
+  writer.flush().chain_err(|| "could not flush headers")?;
+
+  loop {
+    loop {
+      let items = load(filter, last_id)?;
+      for item in &items {
+        let data = serde_json::to_string(&ExportedItem::from(item)).chain_err(|| "could not serialize item")?;
+        write!(writer, "id: {}\nevent: item\ndata: {}\n\n", item.id, data).chain_err(|| "could not write item")?;
+        last_id = item.id;
+      }
+      writer.flush().chain_err(|| "could not flush items")?;
+      if (items.len() as i64) < BATCH {
+        break;
+      }
+    }
+
+    match rx.recv_timeout(KEEP_ALIVE) {
+      Ok(()) => {
+        // one query covers every insert since the last wake-up
+        while rx.try_recv().is_ok() {}
+      },
+      Err(chan::RecvTimeoutError::Timeout) => {
+        // comments keep proxies from closing an idle connection and tell us when the client left
+        writer.write_all(b": keep-alive\n\n").chain_err(|| "could not write keep-alive")?;
+        writer.flush().chain_err(|| "could not flush keep-alive")?;
+      },
+      Err(chan::RecvTimeoutError::Disconnected) => return Ok(()),
+    }
+  }
+}
+
+fn newest_id() -> Result<i32> {
+  let id: Option<i32> = crate::CONNECTION.with(|c| {
+    news_items::table
+      .select(news_items::id)
+      .order(news_items::id.desc())
+      .first(c)
+      .optional()
+      .chain_err(|| "could not load newest item id")
+  })?;
+  Ok(id.unwrap_or(0))
+}
+
+fn load(filter: &FeedFilter, last_id: i32) -> Result<Vec<NewsItem>> {
+  crate::CONNECTION.with(|c| {
+    filter.query()
+      .filter(news_items::id.gt(last_id))
+      .order(news_items::id)
+      .limit(BATCH)
+      .load(c)
+      .chain_err(|| "could not load new items")
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn streams_are_limited() {
+    let mut slots: Vec<Slot> = (0..DEFAULT_MAX_STREAMS).filter_map(|_| Slot::take()).collect();
+    assert_eq!(slots.len(), DEFAULT_MAX_STREAMS);
+    assert!(Slot::take().is_none());
+    assert_eq!(STREAMS.load(Ordering::SeqCst), DEFAULT_MAX_STREAMS);
+
+    slots.pop();
+    assert!(Slot::take().is_some());
+    slots.clear();
+    assert_eq!(STREAMS.load(Ordering::SeqCst), 0);
+  }
+}